@@ -0,0 +1,3 @@
+-- Page cursor of an interrupted sync, so the next run can resume where the
+-- previous one stopped instead of starting over. Cleared once a sync completes.
+ALTER TABLE sync_state ADD COLUMN last_page_cursor TEXT;
@@ -0,0 +1,3 @@
+-- Restore the single sync_state row if it was deleted by hand.
+INSERT INTO sync_state (id, last_sync_at) VALUES (1, NULL)
+ON CONFLICT (id) DO NOTHING;
@@ -0,0 +1,17 @@
+-- Documents edited outside of reader-sync (e.g. by hand in psql) get
+-- local_updated_at stamped, so that a two-way sync can push the edit to
+-- Readwise. reader-sync identifies its own connections by application_name.
+ALTER TABLE reading ADD COLUMN local_updated_at TIMESTAMP WITH TIME ZONE;
+
+CREATE FUNCTION stamp_local_update() RETURNS TRIGGER AS $$
+BEGIN
+    IF current_setting('application_name') <> 'reader-sync' THEN
+        NEW.local_updated_at := now();
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER reading_local_update
+    BEFORE UPDATE ON reading
+    FOR EACH ROW EXECUTE FUNCTION stamp_local_update();
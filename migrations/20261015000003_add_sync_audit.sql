@@ -0,0 +1,11 @@
+-- Field-level changes applied by syncs run with --audit-log.
+CREATE TABLE sync_audit (
+    id         BIGSERIAL PRIMARY KEY,
+    item_id    TEXT NOT NULL,
+    field_name TEXT NOT NULL,
+    old_value  TEXT,
+    new_value  TEXT,
+    changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX sync_audit_item_id_idx ON sync_audit (item_id);
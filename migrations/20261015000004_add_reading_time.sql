@@ -0,0 +1,5 @@
+-- Estimated reading time at an average 238 words per minute
+-- (ReaderResult::estimated_reading_minutes uses the same formula).
+ALTER TABLE reading
+    ADD COLUMN reading_time_minutes INTEGER
+    GENERATED ALWAYS AS (GREATEST(1, word_count / 238)) STORED;
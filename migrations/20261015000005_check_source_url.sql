@@ -0,0 +1,5 @@
+-- source_url must start with a URL scheme. NOT VALID: existing rows are not
+-- checked, `reader-sync check integrity` reports and `--fix` clears them.
+ALTER TABLE reading
+    ADD CONSTRAINT source_url_has_scheme
+    CHECK (source_url ~ '^[A-Za-z][A-Za-z0-9+.-]*:.') NOT VALID;
@@ -0,0 +1,5 @@
+-- Documents removed from Readwise are soft-deleted first, and purged later
+-- with --purge-deleted-older-than.
+ALTER TABLE reading ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX reading_deleted_at_idx ON reading (deleted_at) WHERE deleted_at IS NOT NULL;
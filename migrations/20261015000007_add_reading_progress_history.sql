@@ -0,0 +1,10 @@
+-- Reading progress of each document over time, recorded by syncs when it changes.
+CREATE TABLE reading_progress_history (
+    id          BIGSERIAL PRIMARY KEY,
+    item_id     TEXT NOT NULL,
+    progress    REAL NOT NULL,
+    recorded_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX reading_progress_history_item_id_idx
+    ON reading_progress_history (item_id, recorded_at);
@@ -0,0 +1,7 @@
+-- Checkpoints of syncs restricted to one category (--per-category-interval),
+-- kept apart from the sync_state row of syncs of every category.
+CREATE TABLE category_sync_state (
+    category         category PRIMARY KEY,
+    last_sync_at     TIMESTAMP WITH TIME ZONE,
+    last_page_cursor TEXT
+);
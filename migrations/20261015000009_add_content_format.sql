@@ -0,0 +1,4 @@
+-- `content` is the HTML sent by Readwise, or plain text when synced with
+-- --compact-content.
+ALTER TABLE reading ADD COLUMN content_format TEXT NOT NULL DEFAULT 'html'
+    CONSTRAINT content_format_known CHECK (content_format IN ('html', 'text'));
@@ -0,0 +1,3 @@
+-- SHA-256 of the field names of the documents in the last API page, to notice
+-- when the (unversioned) API starts returning a different set of fields.
+ALTER TABLE sync_state ADD COLUMN schema_checksum TEXT;
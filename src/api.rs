@@ -1,50 +1,844 @@
+use std::collections::{BTreeSet, HashSet};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use chrono::{DateTime, Utc};
-use log::{error, warn};
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use futures::{Stream, StreamExt, TryStreamExt};
+use futures::{future, stream};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
 
-use crate::models::ReaderResponse;
+use crate::error::SyncError;
+use crate::models::{
+    AccessToken, Category, ItemId, Location, PageCursor, ReaderResponse, ReadingItem,
+};
 
-pub fn build_url(cursor: Option<&str>, updated_after: Option<&DateTime<Utc>>) -> String {
-    let base = "https://readwise.io/api/v3/list/";
-    let mut params: Vec<String> = Vec::new();
+const SAVE_URL: &str = "https://readwise.io/api/v3/save/";
+const DELETE_URL: &str = "https://readwise.io/api/v3/delete/";
+const LIST_URL: &str = "https://readwise.io/api/v3/list/";
+const UPDATE_URL: &str = "https://readwise.io/api/v3/update/";
 
-    if let Some(c) = cursor {
-        params.push(format!("pageCursor={c}"));
+/// Default number of consecutive failed attempts before giving up on a request.
+pub const DEFAULT_MAX_RETRIES: u32 = 10;
+
+/// After this many requests all sent on new connections, warn that HTTP
+/// keep-alive is not working.
+const KEEP_ALIVE_CHECK_REQUESTS: usize = 5;
+
+/// Default size limit of a response body, uncompressed.
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 50 * 1024 * 1024;
+
+/// Default number of idle connections kept open, to all hosts.
+pub const DEFAULT_HTTP_POOL_MAX_IDLE: usize = 100;
+
+/// Default number of idle connections kept open to a host: enough for
+/// concurrent workers to each reuse one, where ureq keeps a single one.
+pub const DEFAULT_HTTP_POOL_MAX_IDLE_PER_HOST: usize = 10;
+
+/// A request sent by an [`ApiClient`] through its [`HttpClient`].
+#[derive(Debug)]
+pub struct HttpRequest<'a> {
+    pub method: &'a str,
+    pub url: &'a str,
+    pub access_token: &'a str,
+    pub body: Option<&'a serde_json::Value>,
+    /// Fail with [`SyncError::ResponseTooLarge`] rather than read a longer body.
+    pub max_response_bytes: usize,
+}
+
+/// A response of any status, with its body read as text.
+#[derive(Debug, Clone, Default)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// Local address of the connection the response came on: responses with
+    /// the same address came on the same, reused, connection.
+    pub local_addr: Option<SocketAddr>,
+}
+
+impl HttpResponse {
+    /// Value of the first header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Transport used by [`ApiClient`], so that retries and deserialization can
+/// be tested against canned responses (see `testing::MockHttpClient`).
+///
+/// Implementations return responses of every status, and fail with
+/// [`SyncError::Transport`] for network errors, which are retried.
+pub trait HttpClient: Send + Sync {
+    fn send(&self, request: &HttpRequest<'_>) -> Result<HttpResponse>;
+}
+
+/// The production [`HttpClient`].
+#[derive(Clone)]
+pub struct UreqClient {
+    agent: ureq::Agent,
+    read_timeout: Option<Duration>,
+    max_idle: usize,
+    max_idle_per_host: usize,
+}
+
+impl Default for UreqClient {
+    fn default() -> Self {
+        let mut client = Self {
+            agent: ureq::Agent::new(),
+            read_timeout: None,
+            max_idle: DEFAULT_HTTP_POOL_MAX_IDLE,
+            max_idle_per_host: DEFAULT_HTTP_POOL_MAX_IDLE_PER_HOST,
+        };
+        client.build_agent();
+        client
+    }
+}
+
+impl UreqClient {
+    /// Replace the agent, and its idle connections, by one with the current
+    /// settings.
+    fn build_agent(&mut self) {
+        let mut builder = ureq::AgentBuilder::new()
+            .max_idle_connections(self.max_idle)
+            .max_idle_connections_per_host(self.max_idle_per_host);
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.timeout_read(timeout);
+        }
+        self.agent = builder.build();
+    }
+}
+
+impl HttpClient for UreqClient {
+    fn send(&self, request: &HttpRequest<'_>) -> Result<HttpResponse> {
+        let builder = self
+            .agent
+            .request(request.method, request.url)
+            .set("Authorization", &format!("Token {}", request.access_token))
+            .set("Content-Type", "application/json");
+        let sent = match request.body {
+            Some(body) => builder.send_json(body),
+            None => builder.call(),
+        };
+        let response = match sent {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(ureq::Error::Transport(e)) => {
+                return Err(SyncError::Transport(e.to_string()).into());
+            }
+        };
+        let status = response.status();
+        let local_addr = Some(response.local_addr());
+        let headers = response
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = response.header(&name)?.to_string();
+                Some((name, value))
+            })
+            .collect();
+        let body = read_body(response, request.max_response_bytes)?;
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+            local_addr,
+        })
+    }
+}
+
+/// Rate limit state reported by the Readwise API in the `X-RateLimit-*` headers.
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimitInfo {
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_at: DateTime<Utc>,
+}
+
+impl RateLimitInfo {
+    /// Parse the rate limit headers of a response, if all of them are present.
+    fn from_response(response: &HttpResponse) -> Option<Self> {
+        let header = |name: &str| {
+            response
+                .header(name)
+                .and_then(|v| v.trim().parse::<i64>().ok())
+        };
+
+        let limit = u32::try_from(header("X-RateLimit-Limit")?).ok()?;
+        let remaining = u32::try_from(header("X-RateLimit-Remaining")?).ok()?;
+        let reset = header("X-RateLimit-Reset")?;
+        // The reset header is either an absolute Unix timestamp or a number of
+        // seconds until the window resets.
+        let reset_at = if reset > 1_000_000_000 {
+            DateTime::from_timestamp(reset, 0)?
+        } else {
+            Utc::now() + chrono::Duration::seconds(reset)
+        };
+
+        Some(Self {
+            limit,
+            remaining,
+            reset_at,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ApiClient<C = UreqClient> {
+    http: C,
+    access_token: AccessToken,
+    max_retries: u32,
+    max_response_bytes: usize,
+    strict: bool,
+    /// Time zone of the date-only `published_date`s.
+    published_date_offset: FixedOffset,
+    rate_limit: Arc<RwLock<Option<RateLimitInfo>>>,
+    /// Unknown document fields already warned about.
+    reported_fields: Arc<Mutex<BTreeSet<String>>>,
+    connections: Arc<Mutex<ConnectionStats>>,
+}
+
+/// Connections the responses of an [`ApiClient`] came on.
+#[derive(Debug, Default)]
+struct ConnectionStats {
+    requests: usize,
+    local_addrs: HashSet<SocketAddr>,
+}
+
+impl ApiClient {
+    pub fn new(access_token: AccessToken) -> Self {
+        Self::with_http_client(UreqClient::default(), access_token)
+    }
+
+    /// Fail reads that wait longer than `timeout` for data, so a connection
+    /// silently dropped by the network errors out and is retried instead of
+    /// hanging the sync. ureq reuses idle connections but, unlike reqwest,
+    /// can't enable TCP keep-alive probes.
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.http.read_timeout = Some(timeout);
+        self.http.build_agent();
+        self
+    }
+
+    /// Keep at most `max_idle` idle connections open for reuse, `per_host`
+    /// of them to a host. The client is shared by the workers, so each may
+    /// reuse a connection when `per_host` is at least `--workers`.
+    pub fn with_connection_pool(mut self, max_idle: usize, per_host: usize) -> Self {
+        self.http.max_idle = max_idle;
+        self.http.max_idle_per_host = per_host;
+        self.http.build_agent();
+        self
+    }
+}
+
+impl<C: HttpClient> ApiClient<C> {
+    /// A client sending its requests through `http`.
+    pub fn with_http_client(http: C, access_token: AccessToken) -> Self {
+        Self {
+            http,
+            access_token,
+            max_retries: DEFAULT_MAX_RETRIES,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            strict: false,
+            published_date_offset: FixedOffset::east_opt(0).unwrap(),
+            rate_limit: Arc::new(RwLock::new(None)),
+            reported_fields: Arc::default(),
+            connections: Arc::default(),
+        }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Fail on response bodies larger than this, instead of buffering them.
+    pub fn with_max_response_bytes(mut self, max_response_bytes: usize) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    /// Fail on documents with fields this crate doesn't know, instead of
+    /// warning once about each field and ignoring it.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Take date-only `published_date`s, such as `"2024-01-31"`, to be
+    /// midnight at `offset` instead of midnight UTC.
+    pub fn with_published_date_offset(mut self, offset: FixedOffset) -> Self {
+        self.published_date_offset = offset;
+        self
+    }
+
+    /// Latest rate limit state seen in an API response.
+    pub fn rate_limit_status(&self) -> Option<RateLimitInfo> {
+        self.rate_limit.read().ok().and_then(|info| info.clone())
+    }
+
+    /// Number of connections the responses so far came on, when the
+    /// [`HttpClient`] tells. Fewer than requests means connections are reused.
+    pub fn connections_opened(&self) -> usize {
+        self.connections
+            .lock()
+            .map_or(0, |connections| connections.local_addrs.len())
+    }
+
+    /// Send one request, `attempt` counting from 1 for the logs, in a
+    /// `tracing` span also recording the status, duration and size of the
+    /// response.
+    #[tracing::instrument(
+        skip_all,
+        fields(%method, %url, attempt = attempt, status, elapsed_ms, bytes)
+    )]
+    fn send(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<&serde_json::Value>,
+        attempt: u32,
+    ) -> Result<HttpResponse> {
+        let start = Instant::now();
+        let response = self.http.send(&HttpRequest {
+            method,
+            url,
+            access_token: self.access_token.as_str(),
+            body,
+            max_response_bytes: self.max_response_bytes,
+        })?;
+        let elapsed_ms = start.elapsed().as_millis();
+        let span = tracing::Span::current();
+        span.record("status", response.status);
+        span.record("elapsed_ms", elapsed_ms as u64);
+        span.record("bytes", response.body.len());
+        let connection = response
+            .local_addr
+            .map_or("", |addr| self.record_connection(addr));
+        debug!(
+            "{method} {url} → {} in {elapsed_ms}ms ({} bytes, attempt {attempt}{connection})",
+            response.status,
+            response.body.len()
+        );
+        Ok(response)
+    }
+
+    /// Count the connection of a response from `local_addr`, and describe it
+    /// for the request log.
+    fn record_connection(&self, local_addr: SocketAddr) -> &'static str {
+        let Ok(mut connections) = self.connections.lock() else {
+            return "";
+        };
+        connections.requests += 1;
+        let opened = connections.local_addrs.insert(local_addr);
+        if connections.requests == KEEP_ALIVE_CHECK_REQUESTS
+            && connections.local_addrs.len() == connections.requests
+        {
+            warn!(
+                "Each of the first {KEEP_ALIVE_CHECK_REQUESTS} API requests opened a new \
+                 connection: HTTP keep-alive doesn't seem to work"
+            );
+        }
+        if opened {
+            ", new connection"
+        } else {
+            ", reused connection"
+        }
+    }
+
+    fn record_rate_limit(&self, response: &HttpResponse) {
+        let Some(info) = RateLimitInfo::from_response(response) else {
+            return;
+        };
+        if info.remaining < info.limit / 10 {
+            info!(
+                "Approaching Readwise API rate limit: {}/{} requests remaining until {}",
+                info.remaining, info.limit, info.reset_at
+            );
+        }
+        if let Ok(mut latest) = self.rate_limit.write() {
+            *latest = Some(info);
+        }
     }
-    if let Some(ts) = updated_after {
-        params.push(format!("updatedAfter={}", ts.format("%Y-%m-%dT%H:%M:%SZ")));
+}
+
+/// URL of a page of the list API. Parameters are percent-encoded, cursors are
+/// opaque and may contain any character.
+pub fn build_url(cursor: Option<&PageCursor>, updated_after: Option<&DateTime<Utc>>) -> String {
+    let mut url = Url::parse(LIST_URL).expect("LIST_URL is a valid URL");
+    if cursor.is_some() || updated_after.is_some() {
+        let mut params = url.query_pairs_mut();
+        if let Some(c) = cursor {
+            params.append_pair("pageCursor", c.as_str());
+        }
+        if let Some(ts) = updated_after {
+            params.append_pair("updatedAfter", &ts.format("%Y-%m-%dT%H:%M:%SZ").to_string());
+        }
     }
+    url.into()
+}
+
+/// Options for saving a new document with [`create_item`]. Unset fields are
+/// left for Readwise to fill in.
+#[derive(Debug, Default, Serialize)]
+pub struct CreateItemOptions {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Document content, instead of letting Readwise scrape `url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
+    /// Let Readwise clean up `html` the way it does scraped pages.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub should_clean_html: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<Category>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Shown by Readwise as the source of the document.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub saved_using: Option<String>,
+}
 
-    if params.is_empty() {
-        base.to_string()
+#[derive(Deserialize)]
+struct CreatedItem {
+    id: ItemId,
+}
+
+/// Save a new document to Readwise Reader, returning its ID.
+///
+/// Readwise answers `201 Created` for a new document and `200 OK` when `url`
+/// was already saved, in which case the existing document is left unchanged.
+pub fn create_item<C: HttpClient>(
+    client: &ApiClient<C>,
+    opts: &CreateItemOptions,
+) -> Result<ItemId> {
+    let body = serde_json::to_value(opts)?;
+    let response = call(client, "POST", SAVE_URL, Some(&body))?;
+    let created: CreatedItem = serde_json::from_str(&response.body)?;
+    if response.status == 200 {
+        info!("{} is already in Readwise as {}", opts.url, created.id);
     } else {
-        format!("{}?{}", base, params.join("&"))
+        debug!("Saved {} to Readwise as {}", opts.url, created.id);
     }
+    Ok(created.id)
 }
 
-pub fn get_reading(url: &str, access_token: &str) -> Result<ReaderResponse> {
-    loop {
-        match ureq::get(url)
-            .set("Authorization", &format!("Token {access_token}"))
-            .set("Content-Type", "application/json")
-            .call()
+/// Delete a document from Readwise Reader. Returns `false` if Readwise did not
+/// know it (`404 Not Found`), e.g. because it was already deleted.
+pub fn delete_item<C: HttpClient>(client: &ApiClient<C>, id: &ItemId) -> Result<bool> {
+    match call(client, "DELETE", &format!("{DELETE_URL}{id}/"), None) {
+        Ok(_) => Ok(true),
+        Err(e)
+            if matches!(
+                e.downcast_ref(),
+                Some(SyncError::HttpStatus { status: 404, .. })
+            ) =>
         {
-            Ok(response) => {
-                let body = response.into_string()?;
-                let jd = &mut serde_json::Deserializer::from_str(&body);
-                let page: ReaderResponse = serde_path_to_error::deserialize(jd).map_err(|err| {
-                    error!(
-                        "Failed to deserialize API response at '{}': {err}. Raw body: {body}",
-                        err.path()
-                    );
-                    err
-                })?;
-                return Ok(page);
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Fields to change with [`update_item`]. Unset fields are left as they are.
+#[derive(Debug, Default, Serialize)]
+pub struct UpdateItemOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<Category>,
+    /// Replaces every tag of the document.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+}
+
+/// Change fields of a document in Readwise Reader.
+pub fn update_item<C: HttpClient>(
+    client: &ApiClient<C>,
+    id: &ItemId,
+    opts: &UpdateItemOptions,
+) -> Result<()> {
+    let body = serde_json::to_value(opts)?;
+    call(client, "PATCH", &format!("{UPDATE_URL}{id}/"), Some(&body))?;
+    Ok(())
+}
+
+/// Fetch the current state of one document, `None` if Readwise does not know it.
+pub fn get_single_item<C: HttpClient>(
+    client: &ApiClient<C>,
+    id: &ItemId,
+) -> Result<Option<ReadingItem>> {
+    let mut url = Url::parse(LIST_URL).expect("LIST_URL is a valid URL");
+    url.query_pairs_mut().append_pair("id", id.as_str());
+    let page = get_reading(client, url.as_str())?;
+    Ok(page.into_items().into_iter().next())
+}
+
+/// Add a tag to a document in Readwise Reader. Adding a tag the document
+/// already has does nothing.
+pub fn add_tag<C: HttpClient>(client: &ApiClient<C>, id: &ItemId, tag_name: &str) -> Result<()> {
+    let mut tags = current_tags(client, id)?;
+    if tags.iter().any(|tag| tag == tag_name) {
+        return Ok(());
+    }
+    tags.push(tag_name.to_string());
+    update_tags(client, id, tags)
+}
+
+/// Remove a tag from a document in Readwise Reader, if it has it.
+pub fn remove_tag<C: HttpClient>(client: &ApiClient<C>, id: &ItemId, tag_name: &str) -> Result<()> {
+    let mut tags = current_tags(client, id)?;
+    let before = tags.len();
+    tags.retain(|tag| tag != tag_name);
+    if tags.len() == before {
+        return Ok(());
+    }
+    update_tags(client, id, tags)
+}
+
+/// The update endpoint replaces the whole tag list, so changes start from the
+/// tags the document has in Readwise.
+fn current_tags<C: HttpClient>(client: &ApiClient<C>, id: &ItemId) -> Result<Vec<String>> {
+    let item = get_single_item(client, id)?
+        .with_context(|| format!("Document {id} not found in Readwise"))?;
+    Ok(item.tag_names())
+}
+
+fn update_tags<C: HttpClient>(client: &ApiClient<C>, id: &ItemId, tags: Vec<String>) -> Result<()> {
+    let opts = UpdateItemOptions {
+        tags: Some(tags),
+        ..Default::default()
+    };
+    update_item(client, id, &opts)
+}
+
+/// SHA-256, in hex, of the sorted field names of the first document of a list
+/// API page, see [`ReaderResponse::first_field_names`]. The API is not
+/// versioned: a different checksum is the first sign of a schema change.
+pub fn schema_checksum<'a>(field_names: impl IntoIterator<Item = &'a str>) -> String {
+    let mut hasher = Sha256::new();
+    for name in field_names.into_iter().collect::<BTreeSet<_>>() {
+        hasher.update(name.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Fetch a page of the list API.
+///
+/// Responses are requested gzip-compressed: ureq's `gzip` feature sends
+/// `Accept-Encoding: gzip` and decompresses the body.
+pub fn get_reading<C: HttpClient>(client: &ApiClient<C>, url: &str) -> Result<ReaderResponse> {
+    let body = call(client, "GET", url, None)?.body;
+    debug!("Received {} bytes (uncompressed) from {url}", body.len());
+    let jd = &mut serde_json::Deserializer::from_str(&body);
+    let mut page: ReaderResponse = serde_path_to_error::deserialize(jd).map_err(|err| {
+        error!(
+            "Failed to deserialize API response at '{}': {err}. Raw body: {body}",
+            err.path()
+        );
+        err
+    })?;
+    page.schema_checksum = page
+        .first_field_names()
+        .map(|names| schema_checksum(names.iter().map(String::as_str)));
+    page.localize_published_dates(client.published_date_offset);
+    let unknown_fields = page.unknown_fields();
+    let page_fields = page.unknown_page_fields();
+    if !unknown_fields.is_empty() || !page_fields.is_empty() {
+        debug!(
+            "Unknown API fields: [{}]",
+            page_fields
+                .iter()
+                .chain(&unknown_fields)
+                .copied()
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    if client.strict && !unknown_fields.is_empty() {
+        anyhow::bail!(
+            "Unknown document fields in API response: {}",
+            unknown_fields.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    if let Ok(mut reported) = client.reported_fields.lock() {
+        for field in unknown_fields {
+            if reported.insert(field.to_string()) {
+                warn!("Ignoring unknown document field {field:?} in API response");
             }
-            Err(ureq::Error::Status(code, response)) if code == 429 || code >= 500 => {
+        }
+    }
+    if page.per_page_count() > page.total_remaining {
+        warn!(
+            "API page holds {} documents but reports a total of {}",
+            page.per_page_count(),
+            page.total_remaining
+        );
+    }
+    Ok(page)
+}
+
+/// Where [`paginate_all`] starts.
+#[derive(Debug, Clone, Default)]
+pub struct PaginateOpts {
+    /// Resume from this page rather than the first one.
+    pub cursor: Option<PageCursor>,
+    /// Only list documents updated after this.
+    pub updated_after: Option<DateTime<Utc>>,
+    /// Only list documents of this category.
+    pub category: Option<Category>,
+    pub sort_order: SortOrder,
+}
+
+/// Order of the documents yielded by [`paginate_all`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// The order of the list API.
+    #[default]
+    NewestFirst,
+    /// The list API can't sort, so every page is fetched and held in memory
+    /// before the first one is yielded, last page first with its documents
+    /// reversed. Pages then come out of order with their `next_page_cursor`.
+    OldestFirst,
+}
+
+/// Every page of the list API.
+///
+/// Newest first, pages are fetched lazily: a page is requested when the
+/// stream is polled, so a consumer can finish with a page before the next one
+/// is fetched. The stream ends after the last page or the first error.
+pub fn paginate_all<C>(
+    client: &ApiClient<C>,
+    opts: PaginateOpts,
+) -> impl Stream<Item = Result<ReaderResponse>> + use<C>
+where
+    C: HttpClient + Clone + 'static,
+{
+    let pages = fetch_pages(client, &opts);
+    match opts.sort_order {
+        SortOrder::NewestFirst => pages.left_stream(),
+        SortOrder::OldestFirst => stream::once(pages.collect::<Vec<_>>())
+            .flat_map(|pages| {
+                let reversed = match pages.into_iter().collect::<Result<Vec<_>>>() {
+                    Ok(pages) => pages
+                        .into_iter()
+                        .rev()
+                        .map(|mut page| {
+                            page.results.reverse();
+                            Ok(page)
+                        })
+                        .collect(),
+                    Err(e) => vec![Err(e)],
+                };
+                stream::iter(reversed)
+            })
+            .right_stream(),
+    }
+}
+
+/// Highlights of the document `parent_id`, newest first.
+///
+/// The list API can't filter on the parent document: every highlight is
+/// listed, and those of other documents are dropped here. That is still far
+/// fewer pages than listing every category.
+pub fn stream_highlights<C>(
+    client: &ApiClient<C>,
+    parent_id: &ItemId,
+) -> impl Stream<Item = Result<ReadingItem>> + use<C>
+where
+    C: HttpClient + Clone + 'static,
+{
+    let parent_id = parent_id.clone();
+    let opts = PaginateOpts {
+        category: Some(Category::Highlight),
+        ..Default::default()
+    };
+    paginate_all(client, opts)
+        .map_ok(|page| stream::iter(page.into_items().into_iter().map(Ok)))
+        .try_flatten()
+        .try_filter(move |item| {
+            future::ready(item.parent_id.as_deref() == Some(parent_id.as_str()))
+        })
+}
+
+fn fetch_pages<C>(
+    client: &ApiClient<C>,
+    opts: &PaginateOpts,
+) -> impl Stream<Item = Result<ReaderResponse>> + use<C>
+where
+    C: HttpClient + Clone + 'static,
+{
+    let state = match &opts.cursor {
+        Some(cursor) => SyncState::InProgress(cursor.clone()),
+        None => SyncState::Initial,
+    };
+    let (updated_after, category) = (opts.updated_after, opts.category);
+    stream::unfold((client.clone(), state), move |(client, state)| async move {
+        let cursor = match state {
+            SyncState::Initial => None,
+            SyncState::InProgress(cursor) => Some(cursor),
+            SyncState::Complete => return None,
+        };
+        let mut url = build_url(cursor.as_ref(), updated_after.as_ref());
+        if let Some(category) = category {
+            let mut with_category = Url::parse(&url).expect("build_url returns valid URLs");
+            with_category
+                .query_pairs_mut()
+                .append_pair("category", category.as_str());
+            url = with_category.into();
+        }
+        // The HTTP client is blocking: keep it off the async worker threads.
+        let fetch = {
+            let client = client.clone();
+            tokio::task::spawn_blocking(move || get_reading(&client, &url))
+        };
+        match fetch
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|page| page)
+        {
+            Ok(page) => {
+                let state = next_state(&page);
+                Some((Ok(page), (client, state)))
+            }
+            Err(e) => Some((Err(e), (client, SyncState::Complete))),
+        }
+    })
+}
+
+/// Progress through the pages of the list API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncState {
+    /// No page fetched yet: the first page is next.
+    Initial,
+    /// The page at this cursor is next.
+    InProgress(PageCursor),
+    /// The last page was fetched.
+    Complete,
+}
+
+/// The state after fetching `response`.
+pub fn next_state(response: &ReaderResponse) -> SyncState {
+    match &response.next_page_cursor {
+        Some(cursor) => SyncState::InProgress(cursor.clone()),
+        None => SyncState::Complete,
+    }
+}
+
+/// Read a response body as text, up to `limit` bytes.
+fn read_body(response: ureq::Response, limit: usize) -> Result<String> {
+    let declared = response
+        .header("Content-Length")
+        .and_then(|v| v.parse::<usize>().ok());
+    if let Some(actual) = declared.filter(|&len| len > limit) {
+        return Err(SyncError::ResponseTooLarge { actual, limit }.into());
+    }
+
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(limit as u64 + 1)
+        .read_to_end(&mut body)?;
+    if body.len() > limit {
+        return Err(SyncError::ResponseTooLarge {
+            actual: body.len(),
+            limit,
+        }
+        .into());
+    }
+    Ok(String::from_utf8(body)?)
+}
+
+/// Send a request, with an optional JSON body, retrying on rate limiting,
+/// server errors and network failures.
+fn call<C: HttpClient>(
+    client: &ApiClient<C>,
+    method: &str,
+    url: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<HttpResponse> {
+    // Consecutive failed attempts. Rate-limited responses are not failures:
+    // the server told us exactly how long to wait. They are counted apart,
+    // up to twice as many, so that a server rate limiting forever still
+    // fails the request.
+    let mut attempt: u32 = 0;
+    let mut rate_limited: u32 = 0;
+    let max_rate_limited = client.max_retries.saturating_mul(2);
+    let record_failure = |attempt: &mut u32, last_error: String| -> Result<()> {
+        *attempt += 1;
+        if *attempt >= client.max_retries {
+            return Err(SyncError::MaxRetriesExceeded {
+                attempts: *attempt,
+                last_error,
+            }
+            .into());
+        }
+        Ok(())
+    };
+
+    loop {
+        let response = match client.send(method, url, body, attempt + 1) {
+            Ok(response) => response,
+            Err(e) => match e.downcast::<SyncError>() {
+                Ok(SyncError::Transport(e)) => {
+                    record_failure(&mut attempt, e.clone())?;
+                    error!("Network transport error: {e}. Retrying in 30s.");
+                    thread::sleep(Duration::from_secs(30));
+                    continue;
+                }
+                Ok(e) => return Err(e.into()),
+                Err(e) => return Err(e),
+            },
+        };
+        if response.status != 429 {
+            rate_limited = 0;
+        }
+        match response.status {
+            code if code < 400 => {
+                client.record_rate_limit(&response);
+                return Ok(response);
+            }
+            code if code == 429 || code >= 500 => {
+                if code == 429 {
+                    rate_limited += 1;
+                    if rate_limited >= max_rate_limited {
+                        return Err(SyncError::MaxRetriesExceeded {
+                            attempts: rate_limited,
+                            last_error: String::from("HTTP 429: rate limited"),
+                        }
+                        .into());
+                    }
+                } else {
+                    let message = error_description(&response.body);
+                    let last_error = if message.is_empty() {
+                        format!("HTTP {code}")
+                    } else {
+                        format!("HTTP {code}: {message}")
+                    };
+                    record_failure(&mut attempt, last_error)?;
+                }
                 let retry_after: u64 = response
                     .header("Retry-After")
                     .and_then(|v| v.parse().ok())
@@ -54,16 +848,575 @@ pub fn get_reading(url: &str, access_token: &str) -> Result<ReaderResponse> {
                         );
                         60
                     });
-                warn!("Received HTTP {code}, retrying after {retry_after}s");
+                if code == 429 {
+                    warn!(
+                        "Rate limited; waiting {retry_after}s \
+                         ({rate_limited}/{max_rate_limited} consecutive rate limits)"
+                    );
+                } else {
+                    warn!("Received HTTP {code}, retrying after {retry_after}s");
+                }
                 thread::sleep(Duration::from_secs(retry_after));
             }
-            Err(ureq::Error::Status(code, _)) => {
-                anyhow::bail!("Non-retryable HTTP error {code} from Readwise API");
+            401 => return Err(SyncError::Unauthorized.into()),
+            403 => {
+                let message = error_description(&response.body);
+                return Err(SyncError::Forbidden { message }.into());
             }
-            Err(ureq::Error::Transport(e)) => {
-                error!("Network transport error: {e}. Retrying in 30s.");
-                thread::sleep(Duration::from_secs(30));
+            code => {
+                let message = error_description(&response.body);
+                return Err(SyncError::HttpStatus {
+                    status: code,
+                    message,
+                }
+                .into());
             }
         }
     }
 }
+
+/// Body of an API error response.
+#[derive(Debug, Deserialize)]
+struct ApiErrorBody {
+    detail: Option<String>,
+    error: Option<String>,
+}
+
+/// Characters of a response body kept as an error description, enough for a
+/// one-line message but not for a whole HTML error page.
+const MAX_ERROR_BODY_CHARS: usize = 300;
+
+/// The error of an API response body, `{"detail": "..."}` or
+/// `{"error": "..."}`, or the start of the body itself if it is in neither
+/// format.
+fn error_description(body: &str) -> String {
+    serde_json::from_str::<ApiErrorBody>(body)
+        .ok()
+        .and_then(|body| body.detail.or(body.error))
+        .unwrap_or_else(|| {
+            let body = body.trim();
+            match body.char_indices().nth(MAX_ERROR_BODY_CHARS) {
+                Some((end, _)) => format!("{}…", &body[..end]),
+                None => body.to_string(),
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    use chrono::{FixedOffset, TimeZone};
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::models::ApiResult;
+    use crate::testing::{MockHttpClient, ReaderResultBuilder, token};
+
+    fn query_params(url: &str) -> HashMap<String, String> {
+        let url = Url::parse(url).expect("build_url returned an invalid URL");
+        url.query_pairs().into_owned().collect()
+    }
+
+    fn cursor(s: &str) -> PageCursor {
+        PageCursor::from(s.to_string())
+    }
+
+    #[test]
+    fn no_parameters_is_the_base_url() {
+        assert_eq!(build_url(None, None), LIST_URL);
+    }
+
+    #[test]
+    fn cursor_only() {
+        let url = build_url(Some(&cursor("abc123")), None);
+        assert_eq!(
+            query_params(&url),
+            HashMap::from([("pageCursor".into(), "abc123".into())])
+        );
+    }
+
+    #[test]
+    fn updated_after_only() {
+        let ts = Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap();
+        let url = build_url(None, Some(&ts));
+        assert_eq!(
+            query_params(&url),
+            HashMap::from([("updatedAfter".into(), "2024-03-01T12:30:00Z".into())])
+        );
+    }
+
+    #[test]
+    fn cursor_and_updated_after() {
+        let ts = Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap();
+        let url = build_url(Some(&cursor("abc123")), Some(&ts));
+        assert_eq!(
+            query_params(&url),
+            HashMap::from([
+                ("pageCursor".into(), "abc123".into()),
+                ("updatedAfter".into(), "2024-03-01T12:30:00Z".into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn updated_after_is_sent_in_utc() {
+        let paris = FixedOffset::east_opt(2 * 3600).unwrap();
+        let ts = paris.with_ymd_and_hms(2024, 6, 1, 14, 30, 0).unwrap();
+        let url = build_url(None, Some(&ts.with_timezone(&Utc)));
+        assert_eq!(query_params(&url)["updatedAfter"], "2024-06-01T12:30:00Z");
+    }
+
+    #[test]
+    fn updated_after_across_a_daylight_saving_time_change() {
+        // Clocks in Paris jump from 02:00 (UTC+1) to 03:00 (UTC+2) on 2024-03-31.
+        let winter = FixedOffset::east_opt(3600).unwrap();
+        let summer = FixedOffset::east_opt(2 * 3600).unwrap();
+        let before = winter.with_ymd_and_hms(2024, 3, 31, 1, 59, 59).unwrap();
+        let after = summer.with_ymd_and_hms(2024, 3, 31, 3, 0, 0).unwrap();
+        let param = |ts: DateTime<FixedOffset>| {
+            query_params(&build_url(None, Some(&ts.with_timezone(&Utc))))["updatedAfter"].clone()
+        };
+        assert_eq!(param(before), "2024-03-31T00:59:59Z");
+        assert_eq!(param(after), "2024-03-31T01:00:00Z");
+    }
+
+    #[test]
+    fn updated_after_drops_fractional_seconds() {
+        let ts = Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap()
+            + chrono::Duration::milliseconds(999);
+        let url = build_url(None, Some(&ts));
+        assert_eq!(query_params(&url)["updatedAfter"], "2024-03-01T12:30:00Z");
+    }
+
+    #[test]
+    fn cursor_with_special_characters_is_encoded_once() {
+        let url = build_url(Some(&cursor("a+b=c&d%20e")), None);
+        assert!(!url.contains("%25%"), "double-encoded: {url}");
+        assert_eq!(query_params(&url)["pageCursor"], "a+b=c&d%20e");
+        assert_eq!(query_params(&url).len(), 1);
+    }
+
+    #[test]
+    fn future_timestamp() {
+        let ts = Utc.with_ymd_and_hms(2999, 12, 31, 23, 59, 59).unwrap();
+        let url = build_url(None, Some(&ts));
+        assert_eq!(query_params(&url)["updatedAfter"], "2999-12-31T23:59:59Z");
+    }
+
+    proptest! {
+        #[test]
+        fn any_cursor_round_trips(raw in any::<String>()) {
+            let url = build_url(Some(&cursor(&raw)), None);
+            let params = query_params(&url);
+            prop_assert_eq!(params.len(), 1);
+            prop_assert_eq!(&params["pageCursor"], &raw);
+        }
+    }
+
+    #[test]
+    fn schema_checksum_depends_on_field_names_only() {
+        let document = || serde_json::to_value(ApiResult::from(ReaderResultBuilder::new().build()));
+        let checksum = |results: Vec<serde_json::Value>| {
+            let body = serde_json::json!({"count": 1, "nextPageCursor": null, "results": results});
+            let http = MockHttpClient::new().respond(&body.to_string());
+            let client = ApiClient::with_http_client(http, token());
+            get_reading(&client, LIST_URL).unwrap().schema_checksum
+        };
+
+        let page = checksum(vec![document().unwrap()]);
+        assert_eq!(page.as_ref().map(String::len), Some(64));
+        let mut renamed = document().unwrap();
+        renamed["title"] = "Renamed".into();
+        assert_eq!(checksum(vec![renamed]), page);
+        let mut with_unknown = document().unwrap();
+        with_unknown["last_opened_at"] = serde_json::Value::Null;
+        assert_ne!(checksum(vec![with_unknown.clone()]), page);
+        // Only the first document counts.
+        assert_eq!(checksum(vec![document().unwrap(), with_unknown]), page);
+        let mut without_summary = document().unwrap();
+        without_summary.as_object_mut().unwrap().remove("summary");
+        assert_ne!(checksum(vec![without_summary]), page);
+        assert_eq!(checksum(vec![]), None);
+    }
+
+    #[test]
+    fn bodies_over_the_limit_are_rejected() {
+        let response = |headers: &str, body: &str| {
+            format!("HTTP/1.1 200 OK\r\n{headers}\r\n{body}")
+                .parse::<ureq::Response>()
+                .unwrap()
+        };
+        let too_large = |result: Result<String>| match result.unwrap_err().downcast() {
+            Ok(SyncError::ResponseTooLarge { actual, limit }) => (actual, limit),
+            Ok(other) => panic!("unexpected error {other}"),
+            Err(e) => panic!("unexpected error {e:#}"),
+        };
+
+        // Declared too large: rejected before reading.
+        let declared = response("Content-Length: 1000\r\n", "short");
+        assert_eq!(too_large(read_body(declared, 10)), (1000, 10));
+
+        // Undeclared: read up to one byte past the limit.
+        let streamed = response("", &"x".repeat(1000));
+        assert_eq!(too_large(read_body(streamed, 10)), (11, 10));
+
+        let fits = response("Content-Length: 10\r\n", "0123456789");
+        assert_eq!(read_body(fits, 10).unwrap(), "0123456789");
+    }
+
+    #[test]
+    fn get_reading_decompresses_gzip_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut accepts_gzip = false;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                let line = line.to_ascii_lowercase();
+                accepts_gzip |= line.starts_with("accept-encoding:") && line.contains("gzip");
+            }
+
+            let json = r#"{"count": 0, "nextPageCursor": null, "results": []}"#;
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(json.as_bytes()).unwrap();
+            let body = encoder.finish().unwrap();
+            let mut stream = &stream;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                 Content-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+            accepts_gzip
+        });
+
+        let page = get_reading(&ApiClient::new(token()), &url).unwrap();
+        assert!(server.join().unwrap(), "request did not accept gzip");
+        assert_eq!(page.total_remaining, 0);
+        assert!(page.results.is_empty());
+        assert!(page.next_page_cursor.is_none());
+    }
+
+    #[test]
+    fn pages_are_fetched_over_kept_alive_connections() {
+        const PAGES: usize = 10;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        // Serves one connection at a time, until every page was requested.
+        let server = std::thread::spawn(move || {
+            let (mut connections, mut served) = (0, 0);
+            while served < PAGES {
+                let (stream, _) = listener.accept().unwrap();
+                connections += 1;
+                let mut reader = BufReader::new(&stream);
+                'requests: while served < PAGES {
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap() == 0 {
+                            break 'requests;
+                        }
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                    }
+                    served += 1;
+                    let next = (served < PAGES).then(|| format!("page-{served}"));
+                    let body = serde_json::json!({
+                        "count": PAGES, "nextPageCursor": next, "results": []
+                    })
+                    .to_string();
+                    let mut stream = &stream;
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                         Content-Length: {}\r\n\r\n{body}",
+                        body.len()
+                    )
+                    .unwrap();
+                }
+            }
+            connections
+        });
+
+        let client = ApiClient::new(token());
+        let mut page_url = url.clone();
+        for _ in 0..PAGES {
+            let page = get_reading(&client, &page_url).unwrap();
+            if let Some(cursor) = page.next_page_cursor {
+                page_url = format!("{url}?pageCursor={}", cursor.as_str());
+            }
+        }
+        let connections = server.join().unwrap();
+        assert!(
+            connections < PAGES,
+            "{connections} connections for {PAGES} pages"
+        );
+        assert_eq!(client.connections_opened(), connections);
+    }
+
+    const EMPTY_PAGE: &str = r#"{"count": 0, "nextPageCursor": null, "results": []}"#;
+
+    #[test]
+    fn rate_limited_requests_are_retried() {
+        let http = MockHttpClient::new()
+            .respond_with(429, "")
+            .respond(EMPTY_PAGE);
+        let client = ApiClient::with_http_client(http.clone(), token()).with_max_retries(1);
+
+        let page = get_reading(&client, LIST_URL).unwrap();
+        assert_eq!(page.total_remaining, 0);
+        assert_eq!(http.requests().len(), 2);
+    }
+
+    #[test]
+    fn single_item_ids_are_encoded() {
+        let http = MockHttpClient::new().respond(EMPTY_PAGE);
+        let client = ApiClient::with_http_client(http.clone(), token());
+
+        let item = get_single_item(&client, &ItemId::from("doc&category=pdf")).unwrap();
+
+        assert!(item.is_none());
+        assert_eq!(
+            http.requests(),
+            [format!("GET {LIST_URL}?id=doc%26category%3Dpdf")]
+        );
+    }
+
+    #[test]
+    fn requests_rate_limited_forever_give_up() {
+        let http = (0..5).fold(MockHttpClient::new(), |http, _| http.respond_with(429, ""));
+        let client = ApiClient::with_http_client(http.clone(), token()).with_max_retries(2);
+
+        let err = get_reading(&client, LIST_URL).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SyncError::MaxRetriesExceeded { attempts: 4, .. })
+        ));
+        assert_eq!(http.requests().len(), 4);
+    }
+
+    #[test]
+    fn server_errors_give_up_after_max_retries() {
+        let http = MockHttpClient::new()
+            .respond_with(502, "")
+            .respond_with(503, "")
+            .respond(EMPTY_PAGE);
+        let client = ApiClient::with_http_client(http.clone(), token()).with_max_retries(2);
+
+        let err = get_reading(&client, LIST_URL).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SyncError::MaxRetriesExceeded { attempts: 2, .. })
+        ));
+        assert_eq!(http.requests().len(), 2);
+    }
+
+    #[test]
+    fn pagination_completes_on_the_page_without_cursor() {
+        let page = |json: &str| serde_json::from_str::<ReaderResponse>(json).unwrap();
+        assert_eq!(
+            next_state(&page(
+                r#"{"count": 2, "nextPageCursor": "page-2", "results": []}"#
+            )),
+            SyncState::InProgress(cursor("page-2"))
+        );
+        assert_eq!(next_state(&page(EMPTY_PAGE)), SyncState::Complete);
+    }
+
+    #[test]
+    fn unauthorized_is_not_retried() {
+        let http = MockHttpClient::new()
+            .respond_with(401, "")
+            .respond(EMPTY_PAGE);
+        let client = ApiClient::with_http_client(http.clone(), token());
+
+        let err = get_reading(&client, LIST_URL).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(SyncError::Unauthorized)));
+        assert_eq!(http.requests().len(), 1);
+    }
+
+    #[test]
+    fn client_errors_carry_the_api_error() {
+        let client = |status, body| {
+            ApiClient::with_http_client(MockHttpClient::new().respond_with(status, body), token())
+        };
+
+        let err =
+            get_reading(&client(400, r#"{"error": "Invalid category."}"#), LIST_URL).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SyncError::HttpStatus { status: 400, message }) if message == "Invalid category."
+        ));
+
+        let err = get_reading(&client(422, "Unprocessable\n"), LIST_URL).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Non-retryable HTTP error 422 from Readwise API. Unprocessable"
+        );
+
+        let err = get_reading(&client(404, ""), LIST_URL).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Non-retryable HTTP error 404 from Readwise API."
+        );
+
+        let page = format!("<html>{}</html>", "é".repeat(1000));
+        let err = get_reading(&client(400, &page), LIST_URL).unwrap_err();
+        let Some(SyncError::HttpStatus { message, .. }) = err.downcast_ref() else {
+            panic!("unexpected error {err:?}");
+        };
+        assert_eq!(message.chars().count(), MAX_ERROR_BODY_CHARS + 1);
+        assert!(message.starts_with("<html>é") && message.ends_with("é…"));
+    }
+
+    #[test]
+    fn forbidden_carries_the_api_error() {
+        let http = MockHttpClient::new().respond_with(
+            403,
+            r#"{"detail": "You do not have permission to perform this action."}"#,
+        );
+        let client = ApiClient::with_http_client(http.clone(), token());
+
+        let err = get_reading(&client, LIST_URL).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Readwise Reader access may not be enabled for this account. \
+             You do not have permission to perform this action."
+        );
+        assert_eq!(http.requests().len(), 1);
+    }
+
+    #[test]
+    fn unknown_fields_fail_only_strict_clients() {
+        let body = r#"{"count": 1, "nextPageCursor": null, "results": [{
+            "id": "doc-1", "title": "Title", "category": "article", "location": "new",
+            "created_at": "2025-01-01T00:00:00Z", "reading_progress": 0, "word_count": 10,
+            "published_date": null, "last_opened_at": null
+        }]}"#;
+        let client = ApiClient::with_http_client(MockHttpClient::new().respond(body), token());
+        let page = get_reading(&client, LIST_URL).unwrap();
+        assert_eq!(page.unknown_fields(), BTreeSet::from(["last_opened_at"]));
+
+        let client = ApiClient::with_http_client(MockHttpClient::new().respond(body), token())
+            .with_strict(true);
+        let err = get_reading(&client, LIST_URL).unwrap_err();
+        assert!(err.to_string().contains("last_opened_at"));
+    }
+
+    #[test]
+    fn deleting_an_unknown_document_is_not_an_error() {
+        let http = MockHttpClient::new().respond_with(404, "");
+        let client = ApiClient::with_http_client(http.clone(), token());
+
+        assert!(!delete_item(&client, &ItemId::from("doc-1")).unwrap());
+        assert_eq!(http.requests(), [format!("DELETE {DELETE_URL}doc-1/")]);
+    }
+
+    #[test]
+    fn invalid_pages_fail_to_deserialize() {
+        let http = MockHttpClient::new().respond(r#"{"count": "many"}"#);
+        let client = ApiClient::with_http_client(http, token());
+
+        assert!(get_reading(&client, LIST_URL).is_err());
+    }
+
+    #[tokio::test]
+    async fn paginate_all_follows_cursors_until_the_last_page() {
+        let http = MockHttpClient::new()
+            .respond(r#"{"count": 2, "nextPageCursor": "page-2", "results": []}"#)
+            .respond(EMPTY_PAGE);
+        let client = ApiClient::with_http_client(http.clone(), token());
+
+        let pages: Vec<_> = paginate_all(&client, PaginateOpts::default())
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(pages.len(), 2);
+        let requests = http.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].contains("pageCursor=page-2"));
+    }
+
+    #[tokio::test]
+    async fn stream_highlights_keeps_those_of_the_document() {
+        let highlight = |id: &str, parent_id: &str| {
+            serde_json::to_value(ApiResult::from(
+                ReaderResultBuilder::new()
+                    .id(id)
+                    .category(Category::Highlight)
+                    .parent_id(parent_id)
+                    .build(),
+            ))
+            .unwrap()
+        };
+        let first = serde_json::json!({
+            "count": 3,
+            "nextPageCursor": "page-2",
+            "results": [highlight("h1", "doc"), highlight("h2", "other")],
+        });
+        let second = serde_json::json!({
+            "count": 3,
+            "nextPageCursor": null,
+            "results": [highlight("h3", "doc")],
+        });
+        let http = MockHttpClient::new()
+            .respond(&first.to_string())
+            .respond(&second.to_string());
+        let client = ApiClient::with_http_client(http.clone(), token());
+
+        let highlights: Vec<_> = stream_highlights(&client, &ItemId::from("doc"))
+            .try_collect()
+            .await
+            .unwrap();
+        let ids: Vec<_> = highlights.into_iter().map(|item| item.id).collect();
+        assert_eq!(ids, ["h1", "h3"]);
+        assert!(http.requests()[0].contains("category=highlight"));
+    }
+
+    #[tokio::test]
+    async fn oldest_first_reverses_pages_and_their_documents() {
+        let page = |ids: [&str; 2], next: Option<&str>| {
+            let results: Vec<_> = ids
+                .iter()
+                .map(|id| {
+                    serde_json::to_value(ApiResult::from(
+                        ReaderResultBuilder::new().id(*id).build(),
+                    ))
+                    .unwrap()
+                })
+                .collect();
+            serde_json::json!({"count": 4, "nextPageCursor": next, "results": results}).to_string()
+        };
+        let http = MockHttpClient::new()
+            .respond(&page(["d4", "d3"], Some("page-2")))
+            .respond(&page(["d2", "d1"], None));
+        let client = ApiClient::with_http_client(http, token());
+        let opts = PaginateOpts {
+            sort_order: SortOrder::OldestFirst,
+            ..Default::default()
+        };
+
+        let pages: Vec<_> = paginate_all(&client, opts).try_collect().await.unwrap();
+        let ids: Vec<_> = pages
+            .into_iter()
+            .flat_map(ReaderResponse::into_items)
+            .map(|item| item.id)
+            .collect();
+        assert_eq!(ids, ["d1", "d2", "d3", "d4"]);
+    }
+}
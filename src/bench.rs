@@ -0,0 +1,268 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::info;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use url::Url;
+
+use crate::api::{self, ApiClient, SyncState};
+use crate::db;
+use crate::models::{DbRecord, ReaderResponse, ReadingItem};
+
+/// Throwaway copy of `reading` the benchmark inserts into. Unlogged so the
+/// numbers reflect insert cost rather than WAL throughput, and dropped when
+/// the benchmark ends.
+const BENCH_TABLE: &str = "reading_bench";
+
+pub struct BenchReport {
+    pub items: usize,
+    pub total: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+/// Insert throughput one row per statement, with and without prepared
+/// statement caching, and many rows per statement.
+pub struct BenchComparison {
+    pub single_row: BenchReport,
+    /// Single-row inserts on a connection with a statement cache of capacity
+    /// 0, so the statement is parsed and planned again for every row.
+    pub uncached: BenchReport,
+    /// Latencies are those of statements of up to [`db::MAX_ROWS_PER_INSERT`]
+    /// rows.
+    pub multi_row: BenchReport,
+}
+
+impl BenchComparison {
+    pub fn print(&self) {
+        println!("Single-row INSERTs:");
+        self.single_row.print();
+        println!("Single-row INSERTs without statement cache:");
+        self.uncached.print();
+        if self.uncached.throughput() > 0.0 {
+            println!(
+                "Statement cache speedup: {:.1}x",
+                self.single_row.throughput() / self.uncached.throughput()
+            );
+        }
+        println!("Multi-row INSERTs:");
+        self.multi_row.print();
+        if self.single_row.throughput() > 0.0 {
+            println!(
+                "Multi-row speedup: {:.1}x",
+                self.multi_row.throughput() / self.single_row.throughput()
+            );
+        }
+    }
+}
+
+impl BenchReport {
+    fn from_latencies(items: usize, mut latencies: Vec<Duration>, total: Duration) -> Self {
+        latencies.sort_unstable();
+        let percentile = |p: usize| -> Duration {
+            if latencies.is_empty() {
+                return Duration::ZERO;
+            }
+            let rank = (latencies.len() * p).div_ceil(100).max(1);
+            latencies[rank - 1]
+        };
+        Self {
+            items,
+            total,
+            p50: percentile(50),
+            p95: percentile(95),
+            p99: percentile(99),
+        }
+    }
+
+    pub fn throughput(&self) -> f64 {
+        if self.total.is_zero() {
+            return 0.0;
+        }
+        self.items as f64 / self.total.as_secs_f64()
+    }
+
+    pub fn print(&self) {
+        println!("Inserted {} items in {:.2?}", self.items, self.total);
+        println!("  p50: {:.2?}", self.p50);
+        println!("  p95: {:.2?}", self.p95);
+        println!("  p99: {:.2?}", self.p99);
+        println!("  throughput: {:.1} items/s", self.throughput());
+    }
+}
+
+/// Fetch up to `pages` pages of documents from the Readwise API.
+pub fn fetch_pages(client: &ApiClient, pages: usize) -> Result<Vec<ReadingItem>> {
+    let mut items = Vec::new();
+    let mut state = SyncState::Initial;
+
+    for n in 1..=pages {
+        let cursor = match state {
+            SyncState::Initial => None,
+            SyncState::InProgress(cursor) => Some(cursor),
+            SyncState::Complete => break,
+        };
+        info!("Fetching benchmark page {n}/{pages}...");
+        let page = api::get_reading(client, &api::build_url(cursor.as_ref(), None))?;
+        state = api::next_state(&page);
+        items.extend(page.results.into_iter().map(ReadingItem::from));
+    }
+
+    Ok(items)
+}
+
+/// Load up to `pages` saved API responses (`*.json`, in file name order) from `dir`.
+pub fn load_fixture_pages(dir: &Path, pages: usize) -> Result<Vec<ReadingItem>> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read fixture directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    let mut items = Vec::new();
+    for path in paths.into_iter().take(pages) {
+        let body = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read fixture {}", path.display()))?;
+        let page: ReaderResponse = serde_json::from_str(&body)
+            .with_context(|| format!("Failed to parse fixture {}", path.display()))?;
+        items.extend(page.results.into_iter().map(ReadingItem::from));
+    }
+
+    Ok(items)
+}
+
+/// Insert `items` into an unlogged copy of `reading` one at a time, timing
+/// each insert, then again without caching the prepared statement, then with
+/// multi-row inserts.
+pub async fn run(pool: &PgPool, items: &[ReadingItem]) -> Result<BenchComparison> {
+    sqlx::query(&format!("DROP TABLE IF EXISTS {BENCH_TABLE}"))
+        .execute(pool)
+        .await?;
+    sqlx::query(&format!(
+        "CREATE UNLOGGED TABLE {BENCH_TABLE} (LIKE reading INCLUDING ALL)"
+    ))
+    .execute(pool)
+    .await?;
+
+    let result = compare(pool, items).await;
+
+    sqlx::query(&format!("DROP TABLE {BENCH_TABLE}"))
+        .execute(pool)
+        .await?;
+
+    result
+}
+
+async fn compare(pool: &PgPool, items: &[ReadingItem]) -> Result<BenchComparison> {
+    let truncate = format!("TRUNCATE {BENCH_TABLE}");
+    let single_row = insert_all(pool, items).await?;
+    sqlx::query(&truncate).execute(pool).await?;
+
+    let uncached_pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(
+            pool.connect_options()
+                .as_ref()
+                .clone()
+                .statement_cache_capacity(0),
+        )
+        .await?;
+    let uncached = insert_all(&uncached_pool, items).await;
+    uncached_pool.close().await;
+    let uncached = uncached?;
+    sqlx::query(&truncate).execute(pool).await?;
+
+    let multi_row = insert_batched(pool, items).await?;
+    Ok(BenchComparison {
+        single_row,
+        uncached,
+        multi_row,
+    })
+}
+
+async fn insert_batched(pool: &PgPool, items: &[ReadingItem]) -> Result<BenchReport> {
+    // A statement can't update a row twice, as the single-row inserts do.
+    let mut seen = HashSet::new();
+    let records: Vec<DbRecord> = items
+        .iter()
+        .filter(|item| seen.insert(item.id.as_str()))
+        .cloned()
+        .map(DbRecord::from)
+        .collect();
+
+    let mut latencies = Vec::new();
+    let started_at = Instant::now();
+    for chunk in records.chunks(db::MAX_ROWS_PER_INSERT) {
+        let insert_started_at = Instant::now();
+        db::multi_row_upsert(BENCH_TABLE, chunk)
+            .build()
+            .execute(pool)
+            .await?;
+        latencies.push(insert_started_at.elapsed());
+    }
+
+    Ok(BenchReport::from_latencies(
+        records.len(),
+        latencies,
+        started_at.elapsed(),
+    ))
+}
+
+async fn insert_all(pool: &PgPool, items: &[ReadingItem]) -> Result<BenchReport> {
+    let sql = format!(
+        r#"
+        INSERT INTO {BENCH_TABLE} (
+            id, author, category, content, created_at, image_url, location,
+            notes, parent_id, published_date, reading_progress, readwise_url,
+            site_name, source, source_url, summary, tags, title, updated_at,
+            word_count
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11,
+            $12, $13, $14, $15, $16, $17, $18, $19, $20
+        )
+        ON CONFLICT (id) DO NOTHING
+        "#
+    );
+
+    let mut latencies = Vec::with_capacity(items.len());
+    let started_at = Instant::now();
+
+    for result in items {
+        let insert_started_at = Instant::now();
+        sqlx::query(&sql)
+            .bind(&result.id)
+            .bind(&result.author)
+            .bind(result.category)
+            .bind(&result.content)
+            .bind(result.created_at)
+            .bind(&result.image_url)
+            .bind(result.location)
+            .bind(&result.notes)
+            .bind(&result.parent_id)
+            .bind(result.published_date)
+            .bind(result.reading_progress)
+            .bind(result.readwise_url.as_ref().map(Url::as_str))
+            .bind(&result.site_name)
+            .bind(&result.source)
+            .bind(result.source_url.as_ref().map(Url::as_str))
+            .bind(&result.summary)
+            .bind(&result.tags)
+            .bind(&result.title)
+            .bind(result.updated_at)
+            .bind(result.word_count)
+            .execute(pool)
+            .await?;
+        latencies.push(insert_started_at.elapsed());
+    }
+
+    Ok(BenchReport::from_latencies(
+        items.len(),
+        latencies,
+        started_at.elapsed(),
+    ))
+}
@@ -0,0 +1,1042 @@
+//! Renderings of documents into standalone file formats.
+
+use std::fmt::Write;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use quick_xml::Writer;
+use quick_xml::events::BytesText;
+use serde_json::{Map, Value, json};
+use url::Url;
+
+use crate::models::{Location, ReadingItem};
+
+/// What the renderings include and how they write dates.
+#[derive(Debug, Clone)]
+pub struct ConvertOptions {
+    /// Include the document's content, in the formats which have room for it.
+    pub include_content: bool,
+    /// Summaries longer than this many characters are cut, with an ellipsis.
+    pub max_summary_length: Option<usize>,
+    /// `strftime` format of the dates in Markdown, HTML and Org. Feeds, OPML
+    /// and Pocket links write dates as their specs require.
+    pub date_format: String,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            include_content: true,
+            max_summary_length: None,
+            date_format: String::from("%Y-%m-%dT%H:%M:%SZ"),
+        }
+    }
+}
+
+impl ConvertOptions {
+    /// `ts` in `date_format`, or RFC 3339 if the format is invalid.
+    fn date(&self, ts: &DateTime<Utc>) -> String {
+        let mut date = String::new();
+        match write!(date, "{}", ts.format(&self.date_format)) {
+            Ok(()) => date,
+            Err(_) => timestamp(ts),
+        }
+    }
+
+    fn summary<'a>(&self, result: &'a ReadingItem) -> Option<std::borrow::Cow<'a, str>> {
+        let summary = result.summary.as_deref()?;
+        Some(match self.max_summary_length {
+            Some(max) if summary.chars().count() > max => {
+                let mut cut: String = summary.chars().take(max).collect();
+                cut.truncate(cut.trim_end().len());
+                cut.push('…');
+                cut.into()
+            }
+            _ => summary.into(),
+        })
+    }
+}
+
+/// Render a document as Markdown, with every field but the content and
+/// summary in a YAML front matter block, followed by its `highlights`.
+pub fn to_markdown(
+    result: &ReadingItem,
+    highlights: &[ReadingItem],
+    options: &ConvertOptions,
+) -> String {
+    let mut md = String::from("---\n");
+    let _ = writeln!(md, "id: {}", yaml_string(&result.id));
+    let _ = writeln!(md, "title: {}", yaml_string(&result.title));
+    let _ = writeln!(md, "author: {}", yaml_opt(result.author.as_deref()));
+    let _ = writeln!(md, "category: {}", result.category.as_str());
+    let _ = writeln!(
+        md,
+        "location: {}",
+        result.location.map_or("null", |l| l.as_str())
+    );
+    let _ = writeln!(
+        md,
+        "source_url: {}",
+        yaml_opt(result.source_url.as_ref().map(Url::as_str))
+    );
+    let _ = writeln!(
+        md,
+        "readwise_url: {}",
+        yaml_opt(result.readwise_url.as_ref().map(Url::as_str))
+    );
+    let _ = writeln!(
+        md,
+        "published_date: {}",
+        result
+            .published_date
+            .as_ref()
+            .map_or_else(|| String::from("null"), |d| options.date(d))
+    );
+    let _ = writeln!(md, "created_at: {}", options.date(&result.created_at));
+    let _ = writeln!(md, "reading_progress: {}", result.reading_progress);
+    let _ = writeln!(md, "word_count: {}", result.word_count);
+    let _ = writeln!(
+        md,
+        "reading_time_minutes: {}",
+        result.estimated_reading_minutes()
+    );
+    let tags = result.tag_names();
+    if tags.is_empty() {
+        md.push_str("tags: []\n");
+    } else {
+        md.push_str("tags:\n");
+        for tag in &tags {
+            let _ = writeln!(md, "  - {}", yaml_string(tag));
+        }
+    }
+    let _ = writeln!(md, "site_name: {}", yaml_opt(result.site_name.as_deref()));
+    md.push_str("---\n");
+
+    if let Some(summary) = options.summary(result).filter(|s| !s.is_empty()) {
+        md.push('\n');
+        push_markdown_quote(&mut md, &summary);
+    }
+    if let Some(content) = result.content.as_ref().filter(|_| options.include_content) {
+        md.push('\n');
+        md.push_str(content);
+        if !content.ends_with('\n') {
+            md.push('\n');
+        }
+    }
+    if !highlights.is_empty() {
+        md.push_str("\n## Highlights\n");
+    }
+    for highlight in highlights {
+        if let Some(text) = highlight.content.as_deref().filter(|s| !s.is_empty()) {
+            md.push('\n');
+            push_markdown_quote(&mut md, text);
+        }
+        if let Some(note) = highlight.notes.as_deref().filter(|s| !s.is_empty()) {
+            let _ = writeln!(md, "\n{note}");
+        }
+    }
+    md
+}
+
+fn push_markdown_quote(md: &mut String, text: &str) {
+    for line in text.lines() {
+        md.push_str(if line.is_empty() { ">" } else { "> " });
+        md.push_str(line);
+        md.push('\n');
+    }
+}
+
+/// Render a document as a self-contained HTML5 page.
+///
+/// `content` is inserted as is: Readwise stores it as sanitized HTML.
+pub fn to_html(result: &ReadingItem, options: &ConvertOptions) -> String {
+    let title = escape_html(&result.title);
+    let mut html = String::from("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n");
+    html.push_str("<meta charset=\"utf-8\">\n");
+    html.push_str("<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n");
+    let _ = writeln!(html, "<title>{title}</title>");
+    let _ = writeln!(html, "<meta property=\"og:title\" content=\"{title}\">");
+    if let Some(author) = &result.author {
+        let _ = writeln!(
+            html,
+            "<meta property=\"og:author\" content=\"{}\">",
+            escape_html(author)
+        );
+    }
+    if let Some(url) = &result.source_url {
+        let _ = writeln!(
+            html,
+            "<meta property=\"og:url\" content=\"{}\">",
+            escape_html(url.as_str())
+        );
+    }
+    html.push_str(HTML_STYLE);
+    html.push_str("</head>\n<body>\n<header>\n");
+
+    let _ = writeln!(html, "<h1>{title}</h1>");
+    if let Some(author) = &result.author {
+        let _ = writeln!(html, "<p class=\"author\">{}</p>", escape_html(author));
+    }
+    let _ = writeln!(
+        html,
+        "<span class=\"badge\">{}</span> <span class=\"reading-time\">{} min read</span>",
+        result.category.as_str(),
+        result.estimated_reading_minutes()
+    );
+    let _ = writeln!(
+        html,
+        "<progress value=\"{}\" max=\"1\">{:.0}%</progress>",
+        result.reading_progress,
+        result.reading_progress * 100.0
+    );
+    html.push_str("</header>\n");
+
+    if let Some(content) = result.content.as_ref().filter(|_| options.include_content) {
+        let _ = writeln!(html, "<article>\n{content}\n</article>");
+    }
+
+    html.push_str("<footer>\n");
+    let _ = write!(html, "<p>Saved {}", options.date(&result.created_at));
+    if let Some(updated_at) = &result.updated_at {
+        let _ = write!(html, ", updated {}", options.date(updated_at));
+    }
+    html.push_str("</p>\n");
+    if let Some(url) = &result.readwise_url {
+        let _ = writeln!(
+            html,
+            "<p><a href=\"{url}\">Open in Readwise Reader</a></p>",
+            url = escape_html(url.as_str())
+        );
+    }
+    html.push_str("</footer>\n</body>\n</html>\n");
+    html
+}
+
+const HTML_STYLE: &str = "<style>
+body { max-width: 42rem; margin: 2rem auto; padding: 0 1rem; font: 18px/1.6 Georgia, serif; color: #222; }
+header { border-bottom: 1px solid #ddd; margin-bottom: 2rem; }
+.author { color: #555; margin: 0; }
+.reading-time { color: #777; font-size: 14px; }
+.badge { display: inline-block; padding: 0 .5rem; border-radius: .25rem; background: #eee; font: 12px sans-serif; text-transform: uppercase; }
+progress { display: block; width: 100%; margin: 1rem 0; }
+img { max-width: 100%; }
+footer { border-top: 1px solid #ddd; margin-top: 2rem; color: #777; font-size: 14px; }
+</style>
+";
+
+/// Render a document as an Org file: one heading holding the fields as
+/// properties, the summary, the content as plain text and the
+/// `highlights` in a subheading.
+pub fn to_org(
+    result: &ReadingItem,
+    highlights: &[ReadingItem],
+    options: &ConvertOptions,
+) -> String {
+    let mut org = String::new();
+    let _ = writeln!(org, "#+TITLE: {}", one_line(&result.title));
+    if let Some(author) = &result.author {
+        let _ = writeln!(org, "#+AUTHOR: {}", one_line(author));
+    }
+    let _ = writeln!(org, "#+DATE: {}\n", options.date(&result.created_at));
+
+    let _ = write!(org, "* {}", one_line(&result.title));
+    let tags = result.tag_names();
+    if !tags.is_empty() {
+        let tags: Vec<String> = tags.iter().map(|tag| org_tag(tag)).collect();
+        let _ = write!(org, " :{}:", tags.join(":"));
+    }
+    org.push_str("\n:PROPERTIES:\n");
+    let _ = writeln!(org, ":ID: {}", result.id);
+    let _ = writeln!(org, ":CATEGORY: {}", result.category.as_str());
+    if let Some(location) = result.location {
+        let _ = writeln!(org, ":LOCATION: {}", location.as_str());
+    }
+    if let Some(url) = &result.source_url {
+        let _ = writeln!(org, ":SOURCE_URL: {url}");
+    }
+    if let Some(url) = &result.readwise_url {
+        let _ = writeln!(org, ":READWISE_URL: {url}");
+    }
+    if let Some(site_name) = &result.site_name {
+        let _ = writeln!(org, ":SITE_NAME: {}", one_line(site_name));
+    }
+    if let Some(published) = &result.published_date {
+        let _ = writeln!(org, ":PUBLISHED: {}", options.date(published));
+    }
+    let _ = writeln!(org, ":SAVED: {}", options.date(&result.created_at));
+    let _ = writeln!(org, ":READING_PROGRESS: {}", result.reading_progress);
+    let _ = writeln!(org, ":WORD_COUNT: {}", result.word_count);
+    org.push_str(":END:\n");
+
+    if let Some(summary) = options.summary(result).filter(|s| !s.is_empty()) {
+        org.push('\n');
+        push_org_quote(&mut org, &summary);
+    }
+    if let Some(content) = result.content.as_ref().filter(|_| options.include_content) {
+        let text = html_to_text(content);
+        if !text.is_empty() {
+            org.push('\n');
+            for line in text.lines() {
+                // Keep the text from opening headings: " *" starts a list item.
+                if line.starts_with('*') {
+                    org.push(' ');
+                }
+                org.push_str(line);
+                org.push('\n');
+            }
+        }
+    }
+    if !highlights.is_empty() {
+        org.push_str("\n** Highlights\n");
+    }
+    for highlight in highlights {
+        if let Some(text) = highlight.content.as_deref().filter(|s| !s.is_empty()) {
+            org.push('\n');
+            push_org_quote(&mut org, text);
+        }
+        if let Some(note) = highlight.notes.as_deref().filter(|s| !s.is_empty()) {
+            let _ = writeln!(org, "\n{note}");
+        }
+    }
+    org
+}
+
+fn push_org_quote(org: &mut String, text: &str) {
+    org.push_str("#+begin_quote\n");
+    for line in text.lines() {
+        // Lines which Org would read as headings or keywords are escaped
+        // with a comma.
+        if line.starts_with('*') || line.starts_with("#+") {
+            org.push(',');
+        }
+        org.push_str(line);
+        org.push('\n');
+    }
+    org.push_str("#+end_quote\n");
+}
+
+/// Org tags are made of letters, digits, `_`, `@`, `#` and `%`.
+fn org_tag(tag: &str) -> String {
+    tag.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '_' | '@' | '#' | '%') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn one_line(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Render a document as an OPML 2.0 `outline` of type `link`, for reading
+/// lists. `created` is an RFC 822 date, as the spec requires.
+pub fn to_opml_outline(result: &ReadingItem, options: &ConvertOptions) -> String {
+    let mut outline = format!(
+        "<outline type=\"link\" text=\"{}\"",
+        escape_html(&result.title)
+    );
+    if let Some(url) = &result.source_url {
+        let _ = write!(outline, " url=\"{}\"", escape_html(url.as_str()));
+    }
+    let _ = write!(outline, " created=\"{}\"", result.created_at.to_rfc2822());
+    let tags = result.tag_names();
+    if !tags.is_empty() {
+        let _ = write!(outline, " category=\"{}\"", escape_html(&tags.join(",")));
+    }
+    if let Some(summary) = options.summary(result).filter(|s| !s.is_empty()) {
+        let _ = write!(outline, " description=\"{}\"", escape_html(&summary));
+    }
+    outline.push_str("/>");
+    outline
+}
+
+/// Channel-level fields of a feed.
+#[derive(Debug, Clone)]
+pub struct FeedMeta {
+    pub title: String,
+    pub description: String,
+    /// Address of the feed's website, also the Atom feed ID.
+    pub link: String,
+    pub last_build_date: DateTime<Utc>,
+}
+
+/// Options of the feed entries: summaries only, content would make feeds
+/// heavy.
+fn feed_options() -> ConvertOptions {
+    ConvertOptions {
+        include_content: false,
+        ..Default::default()
+    }
+}
+
+/// Render documents as an RSS 2.0 feed, in the given order.
+pub fn to_rss_feed(items: &[ReadingItem], meta: &FeedMeta) -> String {
+    let options = feed_options();
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer
+        .create_element("rss")
+        .with_attribute(("version", "2.0"))
+        .write_inner_content(|w| {
+            w.create_element("channel").write_inner_content(|w| {
+                text_element(w, "title", &meta.title)?;
+                text_element(w, "link", &meta.link)?;
+                text_element(w, "description", &meta.description)?;
+                text_element(w, "lastBuildDate", &meta.last_build_date.to_rfc2822())?;
+                for item in items {
+                    write_rss_item(w, item, &options)?;
+                }
+                Ok(())
+            })?;
+            Ok(())
+        })
+        .expect("writing to a Vec can't fail");
+    xml_document(writer)
+}
+
+/// Render a document as an RSS 2.0 `item`. RSS has no element for the
+/// content without an extension, so `include_content` is ignored.
+pub fn to_rss_item(result: &ReadingItem, options: &ConvertOptions) -> String {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    write_rss_item(&mut writer, result, options).expect("writing to a Vec can't fail");
+    xml_fragment(writer)
+}
+
+fn write_rss_item(
+    writer: &mut Writer<Vec<u8>>,
+    item: &ReadingItem,
+    options: &ConvertOptions,
+) -> std::io::Result<()> {
+    writer.create_element("item").write_inner_content(|w| {
+        text_element(w, "title", &item.title)?;
+        if let Some(url) = &item.source_url {
+            text_element(w, "link", url.as_str())?;
+        }
+        if let Some(summary) = options.summary(item) {
+            text_element(w, "description", &summary)?;
+        }
+        if let Some(author) = &item.author {
+            // RSS wants an e-mail address here; readers show the name as is.
+            text_element(w, "author", author)?;
+        }
+        if let Some(published) = &item.published_date {
+            text_element(w, "pubDate", &published.to_rfc2822())?;
+        }
+        w.create_element("guid")
+            .with_attribute(("isPermaLink", "false"))
+            .write_text_content(BytesText::new(&item.id))?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Render documents as an Atom 1.0 feed, in the given order.
+pub fn to_atom_feed(items: &[ReadingItem], meta: &FeedMeta) -> String {
+    let options = feed_options();
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer
+        .create_element("feed")
+        .with_attribute(("xmlns", "http://www.w3.org/2005/Atom"))
+        .write_inner_content(|w| {
+            text_element(w, "title", &meta.title)?;
+            text_element(w, "subtitle", &meta.description)?;
+            w.create_element("link")
+                .with_attribute(("href", meta.link.as_str()))
+                .write_empty()?;
+            text_element(w, "id", &meta.link)?;
+            text_element(w, "updated", &timestamp(&meta.last_build_date))?;
+            for item in items {
+                write_atom_entry(w, item, &options)?;
+            }
+            Ok(())
+        })
+        .expect("writing to a Vec can't fail");
+    xml_document(writer)
+}
+
+/// Render a document as an Atom 1.0 `entry`, the content as HTML.
+pub fn to_atom_entry(result: &ReadingItem, options: &ConvertOptions) -> String {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    write_atom_entry(&mut writer, result, options).expect("writing to a Vec can't fail");
+    xml_fragment(writer)
+}
+
+fn write_atom_entry(
+    writer: &mut Writer<Vec<u8>>,
+    item: &ReadingItem,
+    options: &ConvertOptions,
+) -> std::io::Result<()> {
+    writer.create_element("entry").write_inner_content(|w| {
+        text_element(w, "title", &item.title)?;
+        if let Some(url) = &item.source_url {
+            w.create_element("link")
+                .with_attribute(("href", url.as_str()))
+                .write_empty()?;
+        }
+        let id = item
+            .readwise_url
+            .as_ref()
+            .map_or_else(|| format!("urn:readwise:{}", item.id), Url::to_string);
+        text_element(w, "id", &id)?;
+        let updated = item.updated_at.unwrap_or(item.created_at);
+        text_element(w, "updated", &timestamp(&updated))?;
+        if let Some(published) = &item.published_date {
+            text_element(w, "published", &timestamp(published))?;
+        }
+        if let Some(author) = &item.author {
+            w.create_element("author").write_inner_content(|w| {
+                text_element(w, "name", author)?;
+                Ok(())
+            })?;
+        }
+        if let Some(summary) = options.summary(item) {
+            text_element(w, "summary", &summary)?;
+        }
+        if let Some(content) = item.content.as_ref().filter(|_| options.include_content) {
+            w.create_element("content")
+                .with_attribute(("type", "html"))
+                .write_text_content(BytesText::new(content))?;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn text_element(writer: &mut Writer<Vec<u8>>, name: &str, text: &str) -> std::io::Result<()> {
+    writer
+        .create_element(name)
+        .write_text_content(BytesText::new(text))?;
+    Ok(())
+}
+
+fn xml_document(writer: Writer<Vec<u8>>) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}\n",
+        xml_fragment(writer)
+    )
+}
+
+fn xml_fragment(writer: Writer<Vec<u8>>) -> String {
+    String::from_utf8(writer.into_inner()).expect("the feed is written from strings")
+}
+
+/// Render documents as a Pocket export: an HTML page listing links, the
+/// archived documents under "Read Archive" and the others under "Unread".
+/// Documents without a source URL are left out.
+pub fn to_pocket_html(items: &[ReadingItem]) -> String {
+    let options = ConvertOptions::default();
+    let mut unread = String::new();
+    let mut archive = String::new();
+    for item in items.iter().filter(|item| item.source_url.is_some()) {
+        let list = if item.location == Some(Location::Archive) {
+            &mut archive
+        } else {
+            &mut unread
+        };
+        let _ = writeln!(list, "{}", to_pocket_link(item, &options));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n\
+         <meta http-equiv=\"Content-Type\" content=\"text/html; charset=UTF-8\" />\n\
+         <title>Pocket Export</title>\n</head>\n<body>\n\
+         <h1>Unread</h1>\n<ul>\n{unread}</ul>\n\
+         <h1>Read Archive</h1>\n<ul>\n{archive}</ul>\n\
+         </body>\n</html>\n"
+    )
+}
+
+/// Render a document as a link of a Pocket export, `time_added` in seconds
+/// since the epoch as Pocket writes it. Documents without a source URL link
+/// to their Reader page. Pocket imports no summary nor content, so `options`
+/// don't change the link.
+pub fn to_pocket_link(result: &ReadingItem, _options: &ConvertOptions) -> String {
+    let url = result.source_url.as_ref().or(result.readwise_url.as_ref());
+    format!(
+        "<li><a href=\"{}\" time_added=\"{}\" tags=\"{}\">{}</a></li>",
+        escape_html(url.map_or("", Url::as_str)),
+        result.created_at.timestamp(),
+        escape_html(&result.tag_names().join(",")),
+        escape_html(&result.title)
+    )
+}
+
+/// Field names of a document in the Readwise API.
+pub const API_FIELDS: [&str; 20] = [
+    "author",
+    "category",
+    "content",
+    "created_at",
+    "id",
+    "image_url",
+    "location",
+    "notes",
+    "parent_id",
+    "published_date",
+    "reading_progress",
+    "site_name",
+    "source",
+    "source_url",
+    "summary",
+    "tags",
+    "title",
+    "updated_at",
+    "url",
+    "word_count",
+];
+
+/// Render `fields` of a document as a JSON object, named and formatted as in
+/// the Readwise API. Fields not in [`API_FIELDS`] are left out.
+pub fn to_api_json(result: &ReadingItem, fields: &[&str]) -> Map<String, Value> {
+    let mut object = Map::new();
+    for &field in fields {
+        let value = match field {
+            "author" => json!(result.author),
+            "category" => json!(result.category),
+            "content" => json!(result.content),
+            "created_at" => json!(result.created_at),
+            "id" => json!(result.id),
+            "image_url" => json!(result.image_url),
+            "location" => json!(result.location),
+            "notes" => json!(result.notes),
+            "parent_id" => json!(result.parent_id),
+            "published_date" => json!(result.published_date),
+            "reading_progress" => json!(result.reading_progress),
+            "site_name" => json!(result.site_name),
+            "source" => json!(result.source),
+            "source_url" => json!(result.source_url),
+            "summary" => json!(result.summary),
+            "tags" => json!(result.tags),
+            "title" => json!(result.title),
+            "updated_at" => json!(result.updated_at),
+            "url" => json!(result.readwise_url),
+            "word_count" => json!(result.word_count),
+            _ => continue,
+        };
+        object.insert(field.to_string(), value);
+    }
+    object
+}
+
+/// The text of an HTML fragment or document: tags are dropped along with the
+/// content of `<script>`, `<style>` and `<head>`, block elements start new
+/// lines (paragraphs are separated by a blank line), and runs of whitespace
+/// shrink to one space.
+///
+/// A tolerant tag scanner, not a parser: it is meant for storage, not display.
+pub fn html_to_text(html: &str) -> String {
+    /// Elements set apart from the text around them.
+    const BLOCK_TAGS: &[&str] = &[
+        "address",
+        "article",
+        "aside",
+        "blockquote",
+        "div",
+        "dl",
+        "figcaption",
+        "figure",
+        "footer",
+        "h1",
+        "h2",
+        "h3",
+        "h4",
+        "h5",
+        "h6",
+        "header",
+        "ol",
+        "p",
+        "pre",
+        "section",
+        "table",
+        "ul",
+    ];
+    /// Elements starting a new line.
+    const LINE_TAGS: &[&str] = &["br", "dd", "dt", "hr", "li", "tr"];
+
+    let mut text = String::with_capacity(html.len() / 2);
+    // Closing tag ending the element whose content is dropped.
+    let mut skip_until: Option<&str> = None;
+    let mut rest = html;
+    loop {
+        let (chunk, after) = rest.split_at(rest.find('<').unwrap_or(rest.len()));
+        if skip_until.is_none() {
+            push_text(&mut text, chunk);
+        }
+        let Some(after) = after.strip_prefix('<') else {
+            break;
+        };
+        if let Some(comment) = after.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some(end) = after.find('>') else {
+            break;
+        };
+        let tag = &after[..end];
+        rest = &after[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if let Some(skipped) = skip_until {
+            if closing && name == skipped {
+                skip_until = None;
+            }
+            continue;
+        }
+        match name.as_str() {
+            "script" if !closing => skip_until = Some("script"),
+            "style" if !closing => skip_until = Some("style"),
+            "head" if !closing => skip_until = Some("head"),
+            name if BLOCK_TAGS.contains(&name) => {
+                push_line_break(&mut text);
+                push_line_break(&mut text);
+            }
+            name if LINE_TAGS.contains(&name) && !closing => push_line_break(&mut text),
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+/// Append `html` text, unescaped, collapsing whitespace. Entities other
+/// than XML's, character references and `&nbsp;` are kept as they are.
+fn push_text(text: &mut String, html: &str) {
+    let mut rest = html;
+    while let Some(amp) = rest.find('&') {
+        push_collapsed(text, &rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest[1..]
+            .find(|c: char| c == ';' || c == '&' || c.is_whitespace())
+            .filter(|&end| rest[1 + end..].starts_with(';'))
+            .map(|end| &rest[..=1 + end]);
+        let unescaped = entity.and_then(|entity| {
+            quick_xml::escape::unescape_with(entity, |name| match name {
+                "nbsp" => Some(" "),
+                name => quick_xml::escape::resolve_predefined_entity(name),
+            })
+            .ok()
+        });
+        let raw = entity.unwrap_or("&");
+        push_collapsed(text, unescaped.as_deref().unwrap_or(raw));
+        rest = &rest[raw.len()..];
+    }
+    push_collapsed(text, rest);
+}
+
+fn push_collapsed(text: &mut String, s: &str) {
+    for c in s.chars() {
+        if c.is_whitespace() {
+            if !text.is_empty() && !text.ends_with([' ', '\n']) {
+                text.push(' ');
+            }
+        } else {
+            text.push(c);
+        }
+    }
+}
+
+/// End the current line, leaving at most one blank line between paragraphs.
+fn push_line_break(text: &mut String) {
+    while text.ends_with(' ') {
+        text.pop();
+    }
+    if !text.is_empty() && !text.ends_with("\n\n") {
+        text.push('\n');
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn yaml_opt(s: Option<&str>) -> String {
+    s.map_or_else(|| String::from("null"), yaml_string)
+}
+
+/// Quote a string as a YAML double-quoted scalar. JSON string escapes are a
+/// subset of YAML's, so serde_json does the escaping.
+fn yaml_string(s: &str) -> String {
+    serde_json::to_string(s).expect("strings always serialize")
+}
+
+fn timestamp(ts: &DateTime<Utc>) -> String {
+    ts.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::models::Category;
+    use crate::testing::ReaderResultBuilder;
+
+    fn article() -> ReaderResultBuilder {
+        ReaderResultBuilder::new()
+            .id("01hx0k3d9w5t3y2b1q8v7c6n4m")
+            .title("A Wizard of Earthsea")
+            .author("Ursula K. Le Guin")
+            .category(Category::Epub)
+            .location(Some(Location::Later))
+            .source_url("https://example.com/earthsea")
+            .readwise_url("https://read.readwise.io/read/01hx0k3d9w5t3y2b1q8v7c6n4m")
+            .published_date(Utc.with_ymd_and_hms(1968, 11, 1, 0, 0, 0).unwrap())
+            .created_at(Utc.with_ymd_and_hms(2024, 3, 3, 14, 26, 0).unwrap())
+            .reading_progress(0.25)
+            .word_count(8000)
+            .site_name("Parnassus Press")
+    }
+
+    #[test]
+    fn markdown_full_document() {
+        let result = article()
+            .tags(json!({ "fantasy": {}, "classics": {} }))
+            .summary("A young mage learns the true names of things.")
+            .content("# Chapter 1\n\nThe island of Gont...")
+            .build();
+        insta::assert_snapshot!(to_markdown(&result, &[], &ConvertOptions::default()));
+    }
+
+    #[test]
+    fn markdown_minimal_document() {
+        insta::assert_snapshot!(to_markdown(
+            &ReaderResultBuilder::new().location(None).build(),
+            &[],
+            &ConvertOptions::default()
+        ));
+    }
+
+    #[test]
+    fn markdown_escapes_front_matter() {
+        let result = article()
+            .title("Quotes \"inside\": a title\nwith a newline")
+            .author("O'Brien # not a comment")
+            .tags(json!(["with: colon", "- dash"]))
+            .summary("First paragraph.\n\nSecond paragraph.")
+            .build();
+        insta::assert_snapshot!(to_markdown(&result, &[], &ConvertOptions::default()));
+    }
+
+    #[test]
+    fn html_full_document() {
+        let result = article()
+            .title("Earthsea <1968> & \"more\"")
+            .content("<p>The island of Gont...</p>")
+            .updated_at(Utc.with_ymd_and_hms(2025, 3, 3, 14, 26, 0).unwrap())
+            .build();
+        insta::assert_snapshot!(to_html(&result, &ConvertOptions::default()));
+    }
+
+    fn highlights() -> Vec<ReadingItem> {
+        vec![
+            ReaderResultBuilder::new()
+                .id("highlight-1")
+                .category(Category::Highlight)
+                .parent_id("01hx0k3d9w5t3y2b1q8v7c6n4m")
+                .content("To light a candle is to cast a shadow.")
+                .notes("The book's thesis.")
+                .build(),
+            ReaderResultBuilder::new()
+                .id("highlight-2")
+                .category(Category::Highlight)
+                .parent_id("01hx0k3d9w5t3y2b1q8v7c6n4m")
+                .content("* Only in silence the word")
+                .build(),
+        ]
+    }
+
+    #[test]
+    fn markdown_with_highlights_and_options() {
+        let result = article()
+            .summary("A young mage learns the true names of things.")
+            .content("# Chapter 1")
+            .build();
+        let options = ConvertOptions {
+            include_content: false,
+            max_summary_length: Some(12),
+            date_format: String::from("%d %B %Y"),
+        };
+        insta::assert_snapshot!(to_markdown(&result, &highlights(), &options));
+    }
+
+    #[test]
+    fn org_full_document() {
+        let result = article()
+            .tags(json!({ "fantasy": {}, "high fantasy": {} }))
+            .summary("A young mage learns the true names of things.")
+            .content("<h1>Chapter 1</h1><p>The island of Gont...</p><p>*Ged* speaks.</p>")
+            .build();
+        insta::assert_snapshot!(to_org(&result, &highlights(), &ConvertOptions::default()));
+    }
+
+    #[test]
+    fn opml_outline() {
+        let result = article()
+            .title("Earthsea & \"more\"")
+            .tags(json!({ "fantasy": {}, "classics": {} }))
+            .summary("A young mage learns the true names of things.")
+            .build();
+        let options = ConvertOptions {
+            max_summary_length: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(
+            to_opml_outline(&result, &options),
+            "<outline type=\"link\" text=\"Earthsea &amp; &quot;more&quot;\" \
+             url=\"https://example.com/earthsea\" created=\"Sun, 3 Mar 2024 14:26:00 +0000\" \
+             category=\"classics,fantasy\" description=\"A young…\"/>"
+        );
+    }
+
+    #[test]
+    fn rss_item() {
+        let result = article()
+            .summary("Ged & the <shadow>")
+            .content("<p>Left out</p>")
+            .build();
+        insta::assert_snapshot!(to_rss_item(&result, &ConvertOptions::default()));
+    }
+
+    #[test]
+    fn atom_entry_includes_content() {
+        let result = article()
+            .summary("Ged & the <shadow>")
+            .content("<p>The island of Gont...</p>")
+            .build();
+        insta::assert_snapshot!(to_atom_entry(&result, &ConvertOptions::default()));
+
+        let options = ConvertOptions {
+            include_content: false,
+            ..Default::default()
+        };
+        assert!(!to_atom_entry(&result, &options).contains("<content"));
+    }
+
+    #[test]
+    fn pocket_link_falls_back_to_the_reader_page() {
+        let result = article().tags(json!({ "fantasy": {} })).build();
+        assert_eq!(
+            to_pocket_link(&result, &ConvertOptions::default()),
+            "<li><a href=\"https://example.com/earthsea\" time_added=\"1709475960\" \
+             tags=\"fantasy\">A Wizard of Earthsea</a></li>"
+        );
+        let result = ReaderResultBuilder::new()
+            .title("No source")
+            .readwise_url("https://read.readwise.io/read/no-source")
+            .build();
+        assert_eq!(
+            to_pocket_link(&result, &ConvertOptions::default()),
+            format!(
+                "<li><a href=\"https://read.readwise.io/read/no-source\" time_added=\"{}\" \
+                 tags=\"\">No source</a></li>",
+                result.created_at.timestamp()
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_date_format_falls_back_to_rfc3339() {
+        let options = ConvertOptions {
+            date_format: String::from("%Q"),
+            ..Default::default()
+        };
+        let html = to_html(&article().build(), &options);
+        assert!(html.contains("<p>Saved 2024-03-03T14:26:00Z</p>"));
+    }
+
+    #[test]
+    fn api_json_keeps_only_requested_fields() {
+        let result = article().build();
+        let object = to_api_json(&result, &["url", "title", "unknown", "location"]);
+        assert_eq!(
+            Value::Object(object),
+            json!({
+                "url": "https://read.readwise.io/read/01hx0k3d9w5t3y2b1q8v7c6n4m",
+                "title": "A Wizard of Earthsea",
+                "location": "later",
+            })
+        );
+    }
+
+    fn feed_meta() -> FeedMeta {
+        FeedMeta {
+            title: String::from("Reading list"),
+            description: String::from("Documents saved to Readwise Reader"),
+            link: String::from("https://example.com/"),
+            last_build_date: Utc.with_ymd_and_hms(2025, 3, 3, 14, 26, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn rss_feed() {
+        let items = [
+            article().summary("Ged & the <shadow>").build(),
+            ReaderResultBuilder::new()
+                .id("minimal")
+                .title("Minimal")
+                .build(),
+        ];
+        insta::assert_snapshot!(to_rss_feed(&items, &feed_meta()));
+    }
+
+    #[test]
+    fn atom_feed() {
+        let items = [
+            article().summary("Ged & the <shadow>").build(),
+            ReaderResultBuilder::new()
+                .id("minimal")
+                .title("Minimal")
+                .build(),
+        ];
+        insta::assert_snapshot!(to_atom_feed(&items, &feed_meta()));
+    }
+
+    #[test]
+    fn pocket_export() {
+        let items = [
+            article()
+                .title("Earthsea & \"more\"")
+                .tags(json!({ "fantasy": {} }))
+                .build(),
+            article()
+                .id("archived")
+                .location(Some(Location::Archive))
+                .build(),
+            ReaderResultBuilder::new()
+                .id("no-url")
+                .title("Left out")
+                .build(),
+        ];
+        insta::assert_snapshot!(to_pocket_html(&items));
+    }
+
+    #[test]
+    fn html_to_text_keeps_paragraphs_and_drops_markup() {
+        let html = "<html><head><title>Ignored</title><style>p { color: red }</style></head>\
+            <body><h1>A Wizard\n  of Earthsea</h1><!-- <p>comment</p> -->\
+            <p>Ged &amp; <em>Ogion</em>&nbsp;walked.<br/>Then   they\tstopped.</p>\
+            <script>alert('<p>')</script><ul><li>One</li><li>Two &#8212; &unknown;</li></ul>\
+            </body></html>";
+        assert_eq!(
+            html_to_text(html),
+            "A Wizard of Earthsea\n\nGed & Ogion walked.\nThen they stopped.\n\nOne\nTwo \u{2014} &unknown;"
+        );
+    }
+}
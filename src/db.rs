@@ -1,12 +1,242 @@
-use anyhow::Result;
-use chrono::{DateTime, Utc};
-use log::debug;
-use sqlx::postgres::{PgPool, PgQueryResult};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-use crate::models::ReaderResult;
+use anyhow::{Context, Result};
+use async_stream::try_stream;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, Utc};
+use futures::TryStreamExt;
+use futures::stream::BoxStream;
+use log::{debug, info, warn};
+use serde::Serialize;
+use sqlx::postgres::types::PgInterval;
+use sqlx::postgres::{
+    PgConnectOptions, PgConnection, PgDatabaseError, PgExecutor, PgPool, PgPoolOptions,
+    PgQueryResult, Postgres,
+};
+use sqlx::{QueryBuilder, Row};
+use url::Url;
 
-pub async fn save(pool: &PgPool, result: &ReaderResult) -> Result<PgQueryResult> {
-    debug!("Processing: {result:?}");
+use crate::models::{ApiResult, Category, DbRecord, ItemId, Location, PageCursor, ReadingItem};
+
+/// Criteria for selecting documents from the `reading` table. Unset fields do
+/// not filter anything.
+#[derive(Debug, Default, Clone)]
+pub struct QueryFilter {
+    pub category: Option<Category>,
+    pub location: Option<Location>,
+    pub min_reading_progress: Option<f32>,
+    pub max_reading_progress: Option<f32>,
+    pub min_word_count: Option<i32>,
+    pub max_word_count: Option<i32>,
+    /// A `word_count` of 0 means unknown. Such documents fail `min_word_count`
+    /// unless this is set.
+    pub include_zero_word_count: bool,
+    /// Only documents saved at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only documents saved before this time.
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+impl QueryFilter {
+    /// Append the `WHERE` clause (and `LIMIT`, if any) matching this filter.
+    fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        self.push_where(query);
+        query.push(" ORDER BY created_at, id");
+        if let Some(limit) = self.limit {
+            query.push(" LIMIT ").push_bind(limit);
+        }
+    }
+
+    /// Append the `WHERE` clause matching this filter, ignoring `limit`.
+    fn push_where(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        query.push(" WHERE deleted_at IS NULL");
+        if let Some(category) = self.category {
+            query.push(" AND category = ").push_bind(category);
+        }
+        if let Some(location) = self.location {
+            query.push(" AND location = ").push_bind(location);
+        }
+        if let Some(min) = self.min_reading_progress {
+            query.push(" AND reading_progress >= ").push_bind(min);
+        }
+        if let Some(max) = self.max_reading_progress {
+            query.push(" AND reading_progress <= ").push_bind(max);
+        }
+        if let Some(min) = self.min_word_count {
+            query.push(" AND (word_count >= ").push_bind(min);
+            if self.include_zero_word_count {
+                query.push(" OR word_count = 0");
+            }
+            query.push(")");
+        }
+        if let Some(max) = self.max_word_count {
+            query.push(" AND word_count <= ").push_bind(max);
+        }
+        if let Some(since) = self.since {
+            query.push(" AND created_at >= ").push_bind(since);
+        }
+        if let Some(until) = self.until {
+            query.push(" AND created_at < ").push_bind(until);
+        }
+    }
+
+    /// Whether `item` matches this filter, as [`push_where`](Self::push_where)
+    /// would select it, for documents that are not in the database. `limit` is
+    /// ignored.
+    pub fn matches(&self, item: &ReadingItem) -> bool {
+        self.category.is_none_or(|c| c == item.category)
+            && self.location.is_none_or(|l| Some(l) == item.location)
+            && self
+                .min_reading_progress
+                .is_none_or(|min| item.reading_progress >= min)
+            && self
+                .max_reading_progress
+                .is_none_or(|max| item.reading_progress <= max)
+            && self.min_word_count.is_none_or(|min| {
+                item.word_count >= min || (item.word_count == 0 && self.include_zero_word_count)
+            })
+            && self.max_word_count.is_none_or(|max| item.word_count <= max)
+            && self.since.is_none_or(|since| item.created_at >= since)
+            && self.until.is_none_or(|until| item.created_at < until)
+    }
+}
+
+/// `application_name` of our database connections. Updates made from other
+/// connections mark documents as locally modified (see `local_updated_at`).
+pub const APPLICATION_NAME: &str = "reader-sync";
+
+/// Connection pools for the primary (writes) and an optional read replica.
+///
+/// Without a replica, `read` is a handle to the same pool as `write`.
+#[derive(Clone)]
+pub struct DbPools {
+    pub write: PgPool,
+    pub read: PgPool,
+}
+
+impl DbPools {
+    pub async fn connect(
+        options: PgPoolOptions,
+        write: PgConnectOptions,
+        read: Option<PgConnectOptions>,
+    ) -> Result<Self> {
+        let write = options
+            .clone()
+            .connect_with(write.application_name(APPLICATION_NAME))
+            .await?;
+        let read = match read {
+            Some(read) => {
+                info!("Using read replica for queries.");
+                options
+                    .connect_with(read.application_name(APPLICATION_NAME))
+                    .await?
+            }
+            None => write.clone(),
+        };
+        Ok(Self { write, read })
+    }
+}
+
+pub fn connection_pool_config(max: u32, min: u32, acquire_timeout_secs: u64) -> PgPoolOptions {
+    info!(
+        "Database pool: max {max} connections, min {min}, acquire timeout {acquire_timeout_secs}s"
+    );
+    PgPoolOptions::new()
+        .max_connections(max)
+        .min_connections(min)
+        .acquire_timeout(Duration::from_secs(acquire_timeout_secs))
+}
+
+/// Migrations applied to the database, as `<version> (<description>)`, and those
+/// embedded in this binary but not applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub applied: Vec<String>,
+    pub pending: Vec<String>,
+    /// Version of the latest applied migration, 0 on an empty database.
+    pub current_version: i64,
+}
+
+/// Run the embedded migrations, then check against `_sqlx_migrations` that
+/// all of them are applied.
+pub async fn migrate_and_verify(pool: &PgPool) -> Result<MigrationStatus> {
+    let migrator = sqlx::migrate!();
+    migrator
+        .run(pool)
+        .await
+        .context("Failed to migrate the database")?;
+
+    let rows = sqlx::query!(
+        "SELECT version, description FROM _sqlx_migrations WHERE success ORDER BY version"
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list applied migrations")?;
+    let applied: Vec<String> = rows
+        .iter()
+        .map(|row| format!("{} ({})", row.version, row.description))
+        .collect();
+    for migration in &applied {
+        info!("Applied migration {migration}");
+    }
+    let pending: Vec<String> = migrator
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .filter(|migration| rows.iter().all(|row| row.version != migration.version))
+        .map(|migration| format!("{} ({})", migration.version, migration.description))
+        .collect();
+    let current_version = rows.last().map_or(0, |row| row.version);
+    Ok(MigrationStatus {
+        applied,
+        pending,
+        current_version,
+    })
+}
+
+/// Run `f` on a connection whose statements are cancelled after `secs` seconds.
+///
+/// The timeout is set with `SET LOCAL` semantics inside a transaction, so it
+/// does not leak to other users of the pooled connection.
+pub async fn with_statement_timeout<F, T>(pool: &PgPool, secs: u32, f: F) -> Result<T>
+where
+    F: AsyncFnOnce(&mut PgConnection) -> Result<T>,
+{
+    let mut tx = pool.begin().await?;
+    let timeout_ms = (u64::from(secs) * 1000).to_string();
+    sqlx::query!(
+        "SELECT set_config('statement_timeout', $1, true)",
+        timeout_ms
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let value = f(&mut tx).await?;
+    tx.commit().await?;
+    Ok(value)
+}
+
+/// What [`save`] does with a document that is already stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SaveMode {
+    /// Replace it.
+    #[default]
+    Upsert,
+    /// Keep it as it is: the first version saved is canonical.
+    InsertOnly,
+}
+
+pub async fn save<'e>(
+    executor: impl PgExecutor<'e>,
+    record: &DbRecord,
+    mode: SaveMode,
+) -> Result<PgQueryResult> {
+    debug!("Processing: {record:?}");
+    let record = &*strip_null_bytes(record);
     sqlx::query!(
         r#"
         INSERT INTO reading (
@@ -29,10 +259,11 @@ pub async fn save(pool: &PgPool, result: &ReaderResult) -> Result<PgQueryResult>
             tags,
             title,
             updated_at,
-            word_count
+            word_count,
+            content_format
         ) VALUES (
             $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11,
-            $12, $13, $14, $15, $16, $17, $18, $19, $20
+            $12, $13, $14, $15, $16, $17, $18, $19, $20, $21
         )
         ON CONFLICT (id) DO UPDATE SET
             author           = EXCLUDED.author,
@@ -48,56 +279,2483 @@ pub async fn save(pool: &PgPool, result: &ReaderResult) -> Result<PgQueryResult>
             summary          = EXCLUDED.summary,
             tags             = EXCLUDED.tags,
             title            = EXCLUDED.title,
-            updated_at       = EXCLUDED.updated_at,
-            word_count       = EXCLUDED.word_count
+            updated_at       = COALESCE(EXCLUDED.updated_at, reading.updated_at),
+            word_count       = EXCLUDED.word_count,
+            content_format   = EXCLUDED.content_format,
+            local_updated_at = NULL,
+            deleted_at       = NULL
+        WHERE $22
         "#,
-        result.id,
-        result.author,
-        result.category as _,
-        result.content,
-        result.created_at,
-        result.image_url,
-        result.location as _,
-        result.notes,
-        result.parent_id,
-        result.published_date,
-        result.reading_progress,
-        result.readwise_url,
-        result.site_name,
-        result.source,
-        result.source_url,
-        result.summary,
-        result.tags,
-        result.title,
-        result.updated_at,
-        result.word_count,
-    )
-    .execute(pool)
+        record.id,
+        record.author,
+        record.category as _,
+        record.content,
+        record.created_at,
+        record.image_url,
+        record.location as _,
+        record.notes,
+        record.parent_id,
+        record.published_date,
+        record.reading_progress,
+        record.readwise_url.as_ref().map(Url::as_str),
+        record.site_name,
+        record.source,
+        record.source_url.as_ref().map(Url::as_str),
+        record.summary,
+        record.tags,
+        record.title,
+        record.updated_at,
+        record.word_count,
+        record.content_format as _,
+        mode == SaveMode::Upsert,
+    )
+    .execute(executor)
     .await
     .map_err(|e| {
-        anyhow::anyhow!(
-            "Failed to save '{:?}' (id={:?}, source_url={:?}): {e}",
-            result.title,
-            result.id,
-            result.source_url
+        // SQLSTATE of database errors, e.g. 23514 for a check constraint violation.
+        let code = e
+            .as_database_error()
+            .and_then(|db| db.code())
+            .map(|code| format!(", code={code}"))
+            .unwrap_or_default();
+        if let Some((field, detail)) = invalid_field(&e) {
+            return anyhow::Error::new(e).context(format!(
+                "Type error on field '{field}' for item {}: {detail}",
+                record.id
+            ));
+        }
+        // Keep the sqlx error as the cause: retries look for its SQLSTATE.
+        anyhow::Error::new(e).context(format!(
+            "Failed to save '{:?}' (id={:?}, category={:?}, location={:?}, \
+             reading_progress={}, source_url={:?}{code})",
+            record.title,
+            record.id,
+            record.category,
+            record.location,
+            record.reading_progress,
+            record.source_url.as_ref().map(Url::as_str)
+        ))
+    })
+}
+
+/// The column of a value PostgreSQL rejected, as an invalid enum value
+/// (SQLSTATE 22P02) or a check constraint violation (23514), and the reason.
+fn invalid_field(e: &sqlx::Error) -> Option<(String, String)> {
+    /// Longest reason kept: the detail of a check violation lists the whole row.
+    const MAX_DETAIL_CHARS: usize = 300;
+
+    let db = e
+        .as_database_error()?
+        .try_downcast_ref::<PgDatabaseError>()?;
+    let field = match db.code() {
+        // invalid input value for enum category: "..."
+        "22P02" => db
+            .message()
+            .split_once("for enum ")?
+            .1
+            .split(':')
+            .next()?
+            .to_string(),
+        "23514" => match db.constraint()? {
+            "reading_progress_bounds" => String::from("reading_progress"),
+            "source_url_has_scheme" => String::from("source_url"),
+            "content_format_known" => String::from("content_format"),
+            other => other.to_string(),
+        },
+        _ => return None,
+    };
+    let detail = db.detail().unwrap_or(db.message());
+    let detail = match detail.char_indices().nth(MAX_DETAIL_CHARS) {
+        Some((end, _)) => format!("{}…", &detail[..end]),
+        None => detail.to_string(),
+    };
+    Some((field, detail))
+}
+
+/// `record` without the NUL characters PostgreSQL rejects in text columns,
+/// which some documents have in their content.
+pub(crate) fn strip_null_bytes(record: &DbRecord) -> Cow<'_, DbRecord> {
+    let has_null = |s: &Option<String>| s.as_deref().is_some_and(|s| s.contains('\0'));
+    if !record.title.contains('\0')
+        && !has_null(&record.content)
+        && !has_null(&record.notes)
+        && !has_null(&record.summary)
+    {
+        return Cow::Borrowed(record);
+    }
+
+    warn!("Document {}: stripping null bytes", record.id);
+    let mut record = record.clone();
+    record.title.retain(|c| c != '\0');
+    for text in [&mut record.content, &mut record.notes, &mut record.summary]
+        .into_iter()
+        .flatten()
+    {
+        text.retain(|c| c != '\0');
+    }
+    Cow::Owned(record)
+}
+
+/// Times a transaction is run again after a serialization failure.
+const SERIALIZATION_RETRIES: u32 = 3;
+
+/// The transaction lost a race with a concurrent one touching the same rows
+/// (SQLSTATE 40001), and would succeed if run again.
+fn is_serialization_failure(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<sqlx::Error>()
+            .and_then(sqlx::Error::as_database_error)
+            .and_then(|db| db.code())
+            .is_some_and(|code| code == "40001")
+    })
+}
+
+/// Run the transaction `f`, running it again up to [`SERIALIZATION_RETRIES`]
+/// times, with exponential backoff, while it fails with a serialization
+/// failure.
+pub async fn retry_serialization_failures<T, F, Fut>(mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut delay = Duration::from_millis(100);
+    let mut retries = 0;
+    loop {
+        match f().await {
+            Err(e) if retries < SERIALIZATION_RETRIES && is_serialization_failure(&e) => {
+                retries += 1;
+                warn!(
+                    "{e:#}. Retrying in {}ms ({retries}/{SERIALIZATION_RETRIES})",
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A document by ID.
+pub async fn find_by_id<'e>(
+    executor: impl PgExecutor<'e>,
+    id: &ItemId,
+) -> Result<Option<ReadingItem>> {
+    Ok(find_record(executor, id).await?.map(ReadingItem::from))
+}
+
+pub(crate) async fn find_record<'e>(
+    executor: impl PgExecutor<'e>,
+    id: &ItemId,
+) -> Result<Option<DbRecord>> {
+    Ok(sqlx::query_as("SELECT * FROM reading WHERE id = $1")
+        .bind(id)
+        .fetch_optional(executor)
+        .await?)
+}
+
+/// The stored documents among `ids`, in no particular order.
+pub(crate) async fn find_records<'e>(
+    executor: impl PgExecutor<'e>,
+    ids: &[&str],
+) -> Result<Vec<DbRecord>> {
+    Ok(sqlx::query_as("SELECT * FROM reading WHERE id = ANY($1)")
+        .bind(ids)
+        .fetch_all(executor)
+        .await?)
+}
+
+fn into_items(records: Vec<DbRecord>) -> Vec<ReadingItem> {
+    records.into_iter().map(ReadingItem::from).collect()
+}
+
+/// Write to `sync_audit` the fields that differ between each stored
+/// version `old` and the version `new` replacing it, in one statement.
+pub(crate) async fn record_changes(
+    conn: &mut PgConnection,
+    changes: &[(&DbRecord, &DbRecord)],
+) -> Result<()> {
+    let rows: Vec<_> = changes
+        .iter()
+        .flat_map(|(old, new)| {
+            changed_fields(old, new)
+                .into_iter()
+                .map(|(field, old_value, new_value)| (&new.id, field, old_value, new_value))
+        })
+        .collect();
+    // 4 bind parameters per row.
+    for chunk in rows.chunks(MAX_BIND_PARAMS / 4) {
+        QueryBuilder::new("INSERT INTO sync_audit (item_id, field_name, old_value, new_value) ")
+            .push_values(chunk, |mut row, (id, field, old_value, new_value)| {
+                row.push_bind(*id)
+                    .push_bind(*field)
+                    .push_bind(old_value)
+                    .push_bind(new_value);
+            })
+            .build()
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Append to `reading_progress_history` the reading progress of each `new`
+/// version that differs from the stored version `old`, or whose document is
+/// new, in one statement.
+pub(crate) async fn record_progress(
+    conn: &mut PgConnection,
+    changes: &[(Option<&DbRecord>, &DbRecord)],
+) -> Result<()> {
+    let rows: Vec<_> = changes
+        .iter()
+        .filter(|(old, new)| !old.is_some_and(|old| old.reading_progress == new.reading_progress))
+        .map(|(_, new)| (&new.id, new.reading_progress))
+        .collect();
+    for chunk in rows.chunks(MAX_BIND_PARAMS / 2) {
+        QueryBuilder::new("INSERT INTO reading_progress_history (item_id, progress) ")
+            .push_values(chunk, |mut row, (id, progress)| {
+                row.push_bind(*id).push_bind(*progress);
+            })
+            .build()
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Reading progress of a document each time a sync saw it change, oldest first.
+pub async fn progress_history(pools: &DbPools, id: &ItemId) -> Result<Vec<(DateTime<Utc>, f32)>> {
+    let rows = sqlx::query!(
+        "SELECT recorded_at, progress FROM reading_progress_history
+         WHERE item_id = $1
+         ORDER BY recorded_at, id",
+        id.as_str()
+    )
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| (r.recorded_at, r.progress))
+        .collect())
+}
+
+/// Fields differing between two versions of a document, with their old and
+/// new values as text. A missing `updated_at` is no change: saving keeps the
+/// stored one.
+pub(crate) fn changed_fields(
+    old: &DbRecord,
+    new: &DbRecord,
+) -> Vec<(&'static str, Option<String>, Option<String>)> {
+    fn text<T: Serialize>(value: &T) -> Option<String> {
+        match serde_json::to_value(value).ok()? {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(s) => Some(s),
+            other => Some(other.to_string()),
+        }
+    }
+
+    let mut changes = Vec::new();
+    macro_rules! diff {
+        ($($field:ident),*) => {
+            $(
+                if old.$field != new.$field {
+                    changes.push((stringify!($field), text(&old.$field), text(&new.$field)));
+                }
+            )*
+        };
+    }
+    diff!(
+        author,
+        category,
+        content,
+        created_at,
+        image_url,
+        location,
+        notes,
+        parent_id,
+        published_date,
+        reading_progress,
+        readwise_url,
+        site_name,
+        source,
+        source_url,
+        summary,
+        tags,
+        title,
+        word_count,
+        content_format
+    );
+    if new.updated_at.is_some() && old.updated_at != new.updated_at {
+        changes.push(("updated_at", text(&old.updated_at), text(&new.updated_at)));
+    }
+    changes
+}
+
+/// A change recorded by the saves of a
+/// [`PostgresBackend`](crate::storage::PostgresBackend) with an audit log.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub field_name: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Changes recorded for a document, oldest first.
+pub async fn audit_history(pools: &DbPools, id: &ItemId) -> Result<Vec<AuditEntry>> {
+    Ok(sqlx::query_as!(
+        AuditEntry,
+        "SELECT field_name, old_value, new_value, changed_at FROM sync_audit
+         WHERE item_id = $1
+         ORDER BY changed_at, id",
+        id.as_str()
+    )
+    .fetch_all(&pools.read)
+    .await?)
+}
+
+/// Bind parameters PostgreSQL accepts in one statement.
+const MAX_BIND_PARAMS: usize = 65_535;
+
+/// Columns of `reading` written when saving a document.
+const SAVED_COLUMNS: &str = "id, author, category, content, created_at, image_url, location, \
+     notes, parent_id, published_date, reading_progress, readwise_url, site_name, source, \
+     source_url, summary, tags, title, updated_at, word_count, content_format";
+
+/// Documents saved by one statement of [`save_batch`].
+pub const MAX_ROWS_PER_INSERT: usize = MAX_BIND_PARAMS / 21;
+
+/// A single `INSERT` of `records` into `table`, replacing the rows already
+/// there. Ids must be unique: one statement can't update a row twice.
+pub(crate) fn multi_row_upsert<'a>(
+    table: &str,
+    records: &'a [DbRecord],
+) -> QueryBuilder<'a, Postgres> {
+    let mut query = QueryBuilder::new(format!("INSERT INTO {table} ({SAVED_COLUMNS}) "));
+    query.push_values(records, |mut row, record| {
+        row.push_bind(&record.id)
+            .push_bind(&record.author)
+            .push_bind(record.category)
+            .push_bind(&record.content)
+            .push_bind(record.created_at)
+            .push_bind(&record.image_url)
+            .push_bind(record.location)
+            .push_bind(&record.notes)
+            .push_bind(&record.parent_id)
+            .push_bind(record.published_date)
+            .push_bind(record.reading_progress)
+            .push_bind(record.readwise_url.as_ref().map(Url::as_str))
+            .push_bind(&record.site_name)
+            .push_bind(&record.source)
+            .push_bind(record.source_url.as_ref().map(Url::as_str))
+            .push_bind(&record.summary)
+            .push_bind(&record.tags)
+            .push_bind(&record.title)
+            .push_bind(record.updated_at)
+            .push_bind(record.word_count)
+            .push_bind(record.content_format);
+    });
+    // Same updates as `save`.
+    query.push(
+        " ON CONFLICT (id) DO UPDATE SET \
+         author = EXCLUDED.author, content = EXCLUDED.content, \
+         image_url = EXCLUDED.image_url, location = EXCLUDED.location, \
+         notes = EXCLUDED.notes, published_date = EXCLUDED.published_date, \
+         reading_progress = EXCLUDED.reading_progress, site_name = EXCLUDED.site_name, \
+         source = EXCLUDED.source, source_url = EXCLUDED.source_url, \
+         summary = EXCLUDED.summary, tags = EXCLUDED.tags, title = EXCLUDED.title, \
+         updated_at = COALESCE(EXCLUDED.updated_at, reading.updated_at), \
+         word_count = EXCLUDED.word_count, \
+         content_format = EXCLUDED.content_format, local_updated_at = NULL, deleted_at = NULL",
+    );
+    query
+}
+
+/// `records` split into the statements of [`save_batch`], of up to
+/// `chunk_size` documents and never more than [`MAX_ROWS_PER_INSERT`].
+fn insert_chunks(records: &[DbRecord], chunk_size: usize) -> std::slice::Chunks<'_, DbRecord> {
+    let chunks = records.chunks(chunk_size.clamp(1, MAX_ROWS_PER_INSERT));
+    if chunks.len() > 1 {
+        debug!(
+            "Saving {} documents in {} statements",
+            records.len(),
+            chunks.len()
+        );
+    }
+    chunks
+}
+
+/// Write `records`, whose ids must be unique, by multi-row `INSERT`s of up to
+/// `chunk_size` documents (see [`insert_chunks`]). Returns the number of rows
+/// written and, among them, inserted.
+pub(crate) async fn upsert_chunks(
+    conn: &mut PgConnection,
+    records: &[DbRecord],
+    chunk_size: usize,
+) -> Result<(u64, u64)> {
+    let (mut rows, mut inserted) = (0, 0);
+    for chunk in insert_chunks(records, chunk_size) {
+        let mut query = multi_row_upsert("reading", chunk);
+        // `xmax` is 0 on rows this transaction inserted rather than updated.
+        query.push(" RETURNING xmax = 0");
+        let outcomes: Vec<bool> = query
+            .build_query_scalar()
+            .fetch_all(&mut *conn)
+            .await
+            .with_context(|| format!("Failed to save a batch of {} documents", chunk.len()))?;
+        rows += outcomes.len() as u64;
+        inserted += outcomes.iter().filter(|&&inserted| inserted).count() as u64;
+    }
+    Ok((rows, inserted))
+}
+
+/// Save several documents in a single transaction: either all of them are
+/// stored or none is. They are written by multi-row `INSERT`s of up to
+/// `chunk_size` documents (at most [`MAX_ROWS_PER_INSERT`]), and the
+/// transaction is retried on serialization failures. Returns the number of
+/// rows written.
+pub async fn save_batch(pool: &PgPool, records: &[DbRecord], chunk_size: usize) -> Result<u64> {
+    // The last version of a document listed twice wins, as with one save each.
+    let mut positions = HashMap::new();
+    let mut unique: Vec<DbRecord> = Vec::with_capacity(records.len());
+    for record in records {
+        let record = strip_null_bytes(record).into_owned();
+        match positions.get(&record.id) {
+            Some(&i) => unique[i] = record,
+            None => {
+                positions.insert(record.id.clone(), unique.len());
+                unique.push(record);
+            }
+        }
+    }
+
+    let unique = &unique;
+    let (rows, inserted) = retry_serialization_failures(|| async move {
+        let mut tx = pool.begin().await?;
+        let written = upsert_chunks(&mut tx, unique, chunk_size).await?;
+        tx.commit().await?;
+        Ok(written)
+    })
+    .await?;
+    debug!(
+        "Saved a batch of {} documents: {inserted} inserted, {} updated",
+        records.len(),
+        rows - inserted
+    );
+    Ok(rows)
+}
+
+/// Load a JSON Lines file of documents (one document in the API format per
+/// line) into the database, `chunk_size` documents per statement (see
+/// [`save_batch`]). Returns the number of documents loaded.
+pub async fn load_fixtures(pools: &DbPools, path: &Path, chunk_size: usize) -> Result<usize> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open fixtures {}", path.display()))?;
+
+    let mut records = Vec::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result: ApiResult = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid fixture at {}:{}", path.display(), n + 1))?;
+        let item = ReadingItem::from(result);
+        for warning in item.validate() {
+            warn!("Document {}: {warning}", item.id);
+        }
+        records.push(DbRecord::from(item));
+    }
+
+    save_batch(&pools.write, &records, chunk_size).await?;
+    Ok(records.len())
+}
+
+/// Documents matching `filter`, oldest first.
+pub async fn find_all(pools: &DbPools, filter: &QueryFilter) -> Result<Vec<ReadingItem>> {
+    let mut query = QueryBuilder::new("SELECT * FROM reading");
+    filter.push_sql(&mut query);
+    Ok(into_items(
+        query.build_query_as().fetch_all(&pools.read).await?,
+    ))
+}
+
+/// The `limit` most recently saved documents matching `filter`, newest first.
+/// Highlights and notes are left out, and so is `filter.limit`.
+pub async fn latest_documents(
+    pools: &DbPools,
+    filter: &QueryFilter,
+    limit: usize,
+) -> Result<Vec<ReadingItem>> {
+    let mut query = QueryBuilder::new("SELECT * FROM reading");
+    filter.push_where(&mut query);
+    query
+        .push(" AND parent_id IS NULL ORDER BY created_at DESC, id DESC LIMIT ")
+        .push_bind(i64::try_from(limit).unwrap_or(i64::MAX));
+    Ok(into_items(
+        query.build_query_as().fetch_all(&pools.read).await?,
+    ))
+}
+
+/// Documents matching `filter`, oldest first, fetched as they are consumed.
+pub fn stream_all<'a>(
+    pools: &'a DbPools,
+    filter: &'a QueryFilter,
+) -> BoxStream<'a, Result<ReadingItem>> {
+    Box::pin(try_stream! {
+        let mut query = QueryBuilder::new("SELECT * FROM reading");
+        filter.push_sql(&mut query);
+        let mut records = query.build_query_as::<DbRecord>().fetch(&pools.read);
+        while let Some(record) = records.try_next().await? {
+            yield ReadingItem::from(record);
+        }
+    })
+}
+
+/// A document saved from `url`, if any.
+///
+/// Saved URLs are normalized by [`Url`] (lowercase scheme and host, no default
+/// port, `/` path for bare hosts), except in rows saved before `source_url`
+/// was parsed, which keep it as Readwise sent it until a full sync rewrites
+/// them. `url` is looked up both as given and normalized, to match either.
+pub async fn find_by_source_url(pools: &DbPools, url: &str) -> Result<Option<ReadingItem>> {
+    let normalized = Url::parse(url).map_or_else(|_| url.to_string(), String::from);
+    let record: Option<DbRecord> = sqlx::query_as(
+        "SELECT * FROM reading
+             WHERE source_url IN ($1, $2) AND deleted_at IS NULL LIMIT 1",
+    )
+    .bind(url)
+    .bind(normalized)
+    .fetch_optional(&pools.read)
+    .await?;
+    Ok(record.map(ReadingItem::from))
+}
+
+/// Above this many IDs, [`find_ids_not_in`] loads them in a temporary table
+/// rather than binding them as one array.
+const SEEN_IDS_ARRAY_LIMIT: usize = 10_000;
+
+/// Documents stored and not soft-deleted whose ID is not in `seen_ids`: after
+/// a full sync, the documents removed from Readwise.
+pub async fn find_ids_not_in(pools: &DbPools, seen_ids: &[ItemId]) -> Result<Vec<ItemId>> {
+    if seen_ids.len() <= SEEN_IDS_ARRAY_LIMIT {
+        let seen: Vec<&str> = seen_ids.iter().map(ItemId::as_str).collect();
+        let ids = sqlx::query_scalar!(
+            "SELECT id FROM reading WHERE id != ALL($1) AND deleted_at IS NULL ORDER BY id",
+            &seen as &[&str]
+        )
+        .fetch_all(&pools.read)
+        .await?;
+        return Ok(ids.into_iter().map(ItemId::from).collect());
+    }
+
+    // Comparing against a large array is a scan of the array per row: load
+    // the IDs in batches into an indexed temporary table and anti-join it.
+    let mut tx = pools.write.begin().await?;
+    sqlx::query("CREATE TEMPORARY TABLE seen_ids (id TEXT PRIMARY KEY) ON COMMIT DROP")
+        .execute(&mut *tx)
+        .await?;
+    for batch in seen_ids.chunks(SEEN_IDS_ARRAY_LIMIT) {
+        let batch: Vec<&str> = batch.iter().map(ItemId::as_str).collect();
+        sqlx::query("INSERT INTO seen_ids SELECT UNNEST($1::text[]) ON CONFLICT DO NOTHING")
+            .bind(&batch)
+            .execute(&mut *tx)
+            .await?;
+    }
+    let ids: Vec<String> = sqlx::query_scalar(
+        "SELECT r.id FROM reading r
+         WHERE r.deleted_at IS NULL
+           AND NOT EXISTS (SELECT 1 FROM seen_ids s WHERE s.id = r.id)
+         ORDER BY r.id",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(ids.into_iter().map(ItemId::from).collect())
+}
+
+/// Soft-delete the documents `ids`, which stay hidden from queries until
+/// [`purge_deleted`] removes them or a sync saves them again. Returns the
+/// number of documents marked.
+pub async fn mark_deleted(pools: &DbPools, ids: &[ItemId]) -> Result<u64> {
+    let ids: Vec<&str> = ids.iter().map(ItemId::as_str).collect();
+    let marked = sqlx::query!(
+        "UPDATE reading SET deleted_at = now() WHERE id = ANY($1) AND deleted_at IS NULL",
+        &ids as &[&str]
+    )
+    .execute(&pools.write)
+    .await?
+    .rows_affected();
+    Ok(marked)
+}
+
+/// Delete a document with its highlights and their notes. Returns the number
+/// of rows deleted, 0 if the document was not in the database.
+pub async fn delete_by_id(pools: &DbPools, id: &ItemId) -> Result<u64> {
+    let deleted = sqlx::query!(
+        "DELETE FROM reading
+         WHERE id = $1
+            OR parent_id = $1
+            OR parent_id IN (SELECT id FROM reading WHERE parent_id = $1)",
+        id.as_str()
+    )
+    .execute(&pools.write)
+    .await?
+    .rows_affected();
+    Ok(deleted)
+}
+
+/// Hard-delete documents soft-deleted more than `older_than` ago, with their
+/// highlights and their notes, as [`delete_by_id`] does, along with their
+/// `sync_audit` and `reading_progress_history` entries. Returns the number of
+/// rows deleted.
+pub async fn purge_deleted(pools: &DbPools, older_than: Duration) -> Result<u64> {
+    let older_than = PgInterval::try_from(older_than)
+        .map_err(|e| anyhow::anyhow!("Invalid purge age {older_than:?}: {e}"))?;
+    let purged = sqlx::query_scalar!(
+        r#"
+        WITH expired AS (
+            SELECT id FROM reading WHERE deleted_at < now() - $1::interval
+        ),
+        purged AS (
+            DELETE FROM reading
+            WHERE id IN (SELECT id FROM expired)
+               OR parent_id IN (SELECT id FROM expired)
+               OR parent_id IN (
+                   SELECT id FROM reading WHERE parent_id IN (SELECT id FROM expired)
+               )
+            RETURNING id
+        ),
+        audit AS (
+            DELETE FROM sync_audit WHERE item_id IN (SELECT id FROM purged)
+        ),
+        progress AS (
+            DELETE FROM reading_progress_history WHERE item_id IN (SELECT id FROM purged)
         )
+        SELECT COUNT(*) AS "count!" FROM purged
+        "#,
+        older_than
+    )
+    .fetch_one(&pools.write)
+    .await?;
+    Ok(u64::try_from(purged).unwrap_or(0))
+}
+
+/// Delete the `sync_audit` entries recorded more than `older_than` ago.
+/// Returns the number of entries deleted.
+pub async fn prune_sync_audit(pools: &DbPools, older_than: Duration) -> Result<u64> {
+    let older_than = PgInterval::try_from(older_than)
+        .map_err(|e| anyhow::anyhow!("Invalid prune age {older_than:?}: {e}"))?;
+    let pruned = sqlx::query!(
+        "DELETE FROM sync_audit WHERE changed_at < now() - $1::interval",
+        older_than
+    )
+    .execute(&pools.write)
+    .await?
+    .rows_affected();
+    Ok(pruned)
+}
+
+/// Move documents of the `new` location saved more than `older_than` ago to
+/// `target`. Returns the number of documents moved.
+///
+/// The moves are stamped as local edits, for the next two-way sync to push
+/// them to Readwise: a one-way sync would move the documents back.
+pub async fn archive_old_items(
+    pools: &DbPools,
+    older_than: Duration,
+    target: Location,
+) -> Result<u64> {
+    let older_than = PgInterval::try_from(older_than)
+        .map_err(|e| anyhow::anyhow!("Invalid archive age {older_than:?}: {e}"))?;
+    let archived = sqlx::query!(
+        r#"
+        UPDATE reading SET location = $1, local_updated_at = now()
+        WHERE location = 'new' AND created_at < now() - $2::interval AND deleted_at IS NULL
+        "#,
+        target as _,
+        older_than
+    )
+    .execute(&pools.write)
+    .await?;
+    Ok(archived.rows_affected())
+}
+
+/// Documents edited in the database since they were last synced, with the
+/// time of the edit.
+pub async fn get_locally_modified(pools: &DbPools) -> Result<Vec<(ReadingItem, DateTime<Utc>)>> {
+    let records: Vec<DbRecord> = sqlx::query_as(
+        "SELECT * FROM reading
+         WHERE local_updated_at IS NOT NULL AND deleted_at IS NULL
+           AND (updated_at IS NULL OR local_updated_at > updated_at)
+         ORDER BY local_updated_at",
+    )
+    .fetch_all(&pools.write)
+    .await?;
+    Ok(records
+        .into_iter()
+        .filter_map(|record| {
+            let edited_at = record.local_updated_at?;
+            Some((ReadingItem::from(record), edited_at))
+        })
+        .collect())
+}
+
+/// A document with its highlights, in reading order.
+#[derive(Debug, Clone)]
+pub struct DocumentWithHighlights {
+    pub document: ReadingItem,
+    pub highlights: Vec<HighlightWithNotes>,
+}
+
+/// A highlight with the notes attached to it.
+#[derive(Debug, Clone)]
+pub struct HighlightWithNotes {
+    pub highlight: ReadingItem,
+    pub notes: Vec<ReadingItem>,
+}
+
+/// Load a document and the tree of its children: highlights point at the
+/// document through `parent_id`, and notes point at their highlight.
+pub async fn thread_highlights(
+    pools: &DbPools,
+    parent_id: &ItemId,
+) -> Result<DocumentWithHighlights> {
+    let document = find_by_id(&pools.read, parent_id)
+        .await?
+        .with_context(|| format!("Document {parent_id} not found"))?;
+
+    let children: Vec<DbRecord> = sqlx::query_as(
+        r#"
+        SELECT * FROM reading
+        WHERE (parent_id = $1 OR parent_id IN (SELECT id FROM reading WHERE parent_id = $1))
+          AND deleted_at IS NULL
+        ORDER BY created_at, id
+        "#,
+    )
+    .bind(parent_id)
+    .fetch_all(&pools.read)
+    .await?;
+
+    let (highlights, notes): (Vec<_>, Vec<_>) = into_items(children)
+        .into_iter()
+        .partition(|child| child.parent_id.as_deref() == Some(parent_id.as_str()));
+    let mut highlights: Vec<HighlightWithNotes> = highlights
+        .into_iter()
+        .map(|highlight| HighlightWithNotes {
+            highlight,
+            notes: Vec::new(),
+        })
+        .collect();
+    for note in notes {
+        if let Some(highlight) = highlights
+            .iter_mut()
+            .find(|h| note.parent_id.as_deref() == Some(h.highlight.id.as_str()))
+        {
+            highlight.notes.push(note);
+        }
+    }
+
+    Ok(DocumentWithHighlights {
+        document,
+        highlights,
     })
 }
 
-pub async fn load_checkpoint(pool: &PgPool) -> Result<Option<DateTime<Utc>>> {
+/// The `limit` most recently created highlights.
+pub async fn recently_highlighted(pools: &DbPools, limit: i64) -> Result<Vec<ReadingItem>> {
+    let records = sqlx::query_as(
+        "SELECT * FROM reading WHERE category = 'highlight' AND deleted_at IS NULL
+         ORDER BY created_at DESC, id LIMIT $1",
+    )
+    .bind(limit)
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(into_items(records))
+}
+
+/// The first `limit` highlights of documents by `author`, oldest first.
+pub async fn highlights_for_author(
+    pools: &DbPools,
+    author: &str,
+    limit: i64,
+) -> Result<Vec<ReadingItem>> {
+    let records = sqlx::query_as(
+        "SELECT * FROM reading
+         WHERE category = 'highlight' AND author = $1 AND deleted_at IS NULL
+         ORDER BY created_at, id LIMIT $2",
+    )
+    .bind(author)
+    .bind(limit)
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(into_items(records))
+}
+
+/// The first `limit` highlights carrying a note, oldest first.
+pub async fn highlights_with_notes(pools: &DbPools, limit: i64) -> Result<Vec<ReadingItem>> {
+    let records = sqlx::query_as(
+        "SELECT * FROM reading
+         WHERE category = 'highlight' AND notes IS NOT NULL AND notes <> ''
+           AND deleted_at IS NULL
+         ORDER BY created_at, id LIMIT $1",
+    )
+    .bind(limit)
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(into_items(records))
+}
+
+/// The `limit` documents read furthest without being finished, furthest first.
+pub async fn most_advanced_in_progress(pools: &DbPools, limit: i64) -> Result<Vec<ReadingItem>> {
+    let records = sqlx::query_as(
+        "SELECT * FROM reading
+         WHERE parent_id IS NULL AND reading_progress > 0 AND reading_progress < 1
+           AND deleted_at IS NULL
+         ORDER BY reading_progress DESC, id LIMIT $1",
+    )
+    .bind(limit)
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(into_items(records))
+}
+
+/// Number of highlights carrying a note.
+pub async fn count_highlights_with_notes(pools: &DbPools) -> Result<i64> {
+    let count = sqlx::query_scalar(
+        "SELECT count(*) FROM reading
+         WHERE category = 'highlight' AND notes IS NOT NULL AND notes <> ''
+           AND deleted_at IS NULL",
+    )
+    .fetch_one(&pools.read)
+    .await?;
+    Ok(count)
+}
+
+/// Write documents matching `filter` to a JSON Lines file loadable with
+/// [`load_fixtures`]. The bulky `content` field is dropped unless
+/// `with_content` is set. Returns the number of documents written.
+pub async fn export_fixtures(
+    pools: &DbPools,
+    filter: &QueryFilter,
+    path: &Path,
+    with_content: bool,
+) -> Result<usize> {
+    let results = find_all(pools, filter).await?;
+
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create fixtures {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    for mut result in results.iter().cloned() {
+        if !with_content {
+            result.content = None;
+        }
+        serde_json::to_writer(&mut writer, &ApiResult::from(result))?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+
+    Ok(results.len())
+}
+
+/// A column of the `reading` table, for [`export_csv`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[value(rename_all = "snake_case")]
+pub enum CsvColumn {
+    Id,
+    Title,
+    Author,
+    Category,
+    Location,
+    ReadingProgress,
+    WordCount,
+    SiteName,
+    Source,
+    SourceUrl,
+    ReadwiseUrl,
+    ImageUrl,
+    Summary,
+    Notes,
+    Tags,
+    Content,
+    ParentId,
+    PublishedDate,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl CsvColumn {
+    /// Name of the column, also used as the CSV header.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Title => "title",
+            Self::Author => "author",
+            Self::Category => "category",
+            Self::Location => "location",
+            Self::ReadingProgress => "reading_progress",
+            Self::WordCount => "word_count",
+            Self::SiteName => "site_name",
+            Self::Source => "source",
+            Self::SourceUrl => "source_url",
+            Self::ReadwiseUrl => "readwise_url",
+            Self::ImageUrl => "image_url",
+            Self::Summary => "summary",
+            Self::Notes => "notes",
+            Self::Tags => "tags",
+            Self::Content => "content",
+            Self::ParentId => "parent_id",
+            Self::PublishedDate => "published_date",
+            Self::CreatedAt => "created_at",
+            Self::UpdatedAt => "updated_at",
+        }
+    }
+}
+
+/// How [`export_csv`] writes `reading_progress` and `word_count`, for
+/// spreadsheets expecting the conventions of another locale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvLocale {
+    pub decimal_separator: char,
+    /// Written between groups of three digits, as in `12,345`.
+    pub thousands_separator: Option<char>,
+}
+
+impl Default for CsvLocale {
+    fn default() -> Self {
+        Self {
+            decimal_separator: '.',
+            thousands_separator: None,
+        }
+    }
+}
+
+impl CsvLocale {
+    /// Rewrite a number written the way PostgreSQL does, such as `12345.5`.
+    /// Numbers in scientific notation only get their decimal separator.
+    fn format_number(&self, number: &str) -> String {
+        if number.contains(['e', 'E']) {
+            return number.replace('.', &self.decimal_separator.to_string());
+        }
+        let (sign, digits) = match number.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", number),
+        };
+        let (integer, fraction) = match digits.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (digits, None),
+        };
+
+        let mut formatted = String::from(sign);
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                formatted.extend(self.thousands_separator);
+            }
+            formatted.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            formatted.push(self.decimal_separator);
+            formatted.push_str(fraction);
+        }
+        formatted
+    }
+}
+
+/// Layout of the file written by [`export_csv`].
+#[derive(Debug, Clone)]
+pub struct CsvExportOptions {
+    /// Columns to write, in this order.
+    pub columns: Vec<CsvColumn>,
+    pub include_headers: bool,
+    /// An ASCII character.
+    pub delimiter: char,
+    pub locale: CsvLocale,
+    /// `strftime`-like format of `published_date`, `created_at` and
+    /// `updated_at`, in UTC.
+    pub date_format: String,
+}
+
+impl CsvExportOptions {
+    /// `value` of `column`, as text, laid out for the CSV file.
+    fn format(&self, column: CsvColumn, value: String) -> String {
+        match column {
+            CsvColumn::ReadingProgress | CsvColumn::WordCount => self.locale.format_number(&value),
+            CsvColumn::PublishedDate | CsvColumn::CreatedAt | CsvColumn::UpdatedAt => {
+                match DateTime::parse_from_rfc3339(&value) {
+                    Ok(date) => date.to_utc().format(&self.date_format).to_string(),
+                    Err(_) => value,
+                }
+            }
+            _ => value,
+        }
+    }
+}
+
+impl Default for CsvExportOptions {
+    fn default() -> Self {
+        Self {
+            columns: vec![
+                CsvColumn::Id,
+                CsvColumn::Title,
+                CsvColumn::Author,
+                CsvColumn::Category,
+                CsvColumn::Location,
+                CsvColumn::ReadingProgress,
+                CsvColumn::WordCount,
+                CsvColumn::SourceUrl,
+                CsvColumn::CreatedAt,
+            ],
+            include_headers: true,
+            delimiter: ',',
+            locale: CsvLocale::default(),
+            date_format: String::from("%Y-%m-%d"),
+        }
+    }
+}
+
+/// Write the documents matching `filter` as CSV, oldest first. Dates and
+/// numbers are laid out as `options` say, and tags are a JSON object. Returns
+/// the number of documents written.
+pub async fn export_csv(
+    pools: &DbPools,
+    filter: &QueryFilter,
+    path: &Path,
+    options: &CsvExportOptions,
+) -> Result<usize> {
+    anyhow::ensure!(!options.columns.is_empty(), "No columns to export");
+    let delimiter = u8::try_from(options.delimiter)
+        .ok()
+        .filter(u8::is_ascii)
+        .with_context(|| format!("CSV delimiter {:?} is not ASCII", options.delimiter))?;
+    anyhow::ensure!(
+        !StrftimeItems::new(&options.date_format).any(|item| item == Item::Error),
+        "Invalid date format {:?}",
+        options.date_format
+    );
+
+    // JSON renders every type as text the same way the API does.
+    let columns = options
+        .columns
+        .iter()
+        .map(|c| format!("to_jsonb({0}) #>> '{{}}' AS {0}", c.as_str()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut query = QueryBuilder::new(format!("SELECT {columns} FROM reading"));
+    filter.push_sql(&mut query);
+
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(BufWriter::new(file));
+    if options.include_headers {
+        writer.write_record(options.columns.iter().map(CsvColumn::as_str))?;
+    }
+    let mut rows = query.build().fetch(&pools.read);
+    let mut exported = 0;
+    while let Some(row) = rows.try_next().await? {
+        let fields = options
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, &column)| {
+                row.try_get::<Option<String>, _>(i)
+                    .map(|value| value.map_or_else(String::new, |v| options.format(column, v)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        writer.write_record(&fields)?;
+        exported += 1;
+    }
+    writer.flush()?;
+    Ok(exported)
+}
+
+/// Content of the file written by [`export_jsonl`].
+#[derive(Debug, Clone)]
+pub struct JsonlExportOptions {
+    /// Fields of each document, named as in the Readwise API (see
+    /// [`API_FIELDS`](crate::convert::API_FIELDS)).
+    pub fields: Vec<String>,
+    /// Indent each object over several lines instead of one line per document.
+    pub pretty: bool,
+}
+
+impl Default for JsonlExportOptions {
+    fn default() -> Self {
+        Self {
+            fields: crate::convert::API_FIELDS.map(String::from).to_vec(),
+            pretty: false,
+        }
+    }
+}
+
+/// Write the documents matching `filter` as JSON objects, oldest first,
+/// keeping only `options.fields`. Returns the number of documents written.
+pub async fn export_jsonl(
+    pools: &DbPools,
+    filter: &QueryFilter,
+    path: &Path,
+    options: &JsonlExportOptions,
+) -> Result<usize> {
+    let fields: Vec<&str> = options.fields.iter().map(String::as_str).collect();
+    if let Some(unknown) = fields
+        .iter()
+        .find(|f| !crate::convert::API_FIELDS.contains(f))
+    {
+        anyhow::bail!(
+            "Unknown field {unknown:?}, expected one of: {}",
+            crate::convert::API_FIELDS.join(", ")
+        );
+    }
+
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    let mut items = stream_all(pools, filter);
+    let mut exported = 0;
+    while let Some(item) = items.try_next().await? {
+        let object = crate::convert::to_api_json(&item, &fields);
+        if options.pretty {
+            serde_json::to_writer_pretty(&mut writer, &object)?;
+        } else {
+            serde_json::to_writer(&mut writer, &object)?;
+        }
+        writer.write_all(b"\n")?;
+        exported += 1;
+    }
+    writer.flush()?;
+    Ok(exported)
+}
+
+/// Write the documents matching `filter` as a Pocket export (see
+/// [`to_pocket_html`](crate::convert::to_pocket_html)), oldest first, for
+/// services importing those. Highlights and notes are left out. Returns the
+/// number of documents written.
+pub async fn export_to_pocket_format(
+    pools: &DbPools,
+    filter: &QueryFilter,
+    path: &Path,
+) -> Result<usize> {
+    let items: Vec<ReadingItem> = stream_all(pools, filter)
+        .try_filter(|item| {
+            std::future::ready(item.parent_id.is_none() && item.source_url.is_some())
+        })
+        .try_collect()
+        .await?;
+    std::fs::write(path, crate::convert::to_pocket_html(&items))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(items.len())
+}
+
+/// Start time of the last completed sync. `None` before the first one, or if
+/// the `sync_state` row is missing; database errors are returned as such.
+pub async fn load_checkpoint(pools: &DbPools) -> Result<Option<DateTime<Utc>>> {
     let row = sqlx::query!("SELECT last_sync_at FROM sync_state WHERE id = 1")
-        .fetch_one(pool)
+        .fetch_optional(&pools.write)
         .await?;
-    Ok(row.last_sync_at)
+    Ok(row.and_then(|row| row.last_sync_at))
+}
+
+/// Record a checkpoint: the start time of a sync, and the page an interrupted
+/// sync resumes from (`None` once a sync completed). Both columns are written
+/// by one statement, on `executor`, which may be a transaction.
+pub async fn save_checkpoint<'e>(
+    executor: impl PgExecutor<'e>,
+    ts: &DateTime<Utc>,
+    cursor: Option<&PageCursor>,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO sync_state (id, last_sync_at, last_page_cursor) VALUES (1, $1, $2)
+         ON CONFLICT (id) DO UPDATE SET
+             last_sync_at = EXCLUDED.last_sync_at,
+             last_page_cursor = EXCLUDED.last_page_cursor",
+        ts,
+        cursor.map(PageCursor::as_str)
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Forget the last sync and any saved page cursor, so that an interrupted full
+/// sync resumes as a full sync.
+pub async fn clear_checkpoint(pools: &DbPools) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO sync_state (id, last_sync_at, last_page_cursor) VALUES (1, NULL, NULL)
+         ON CONFLICT (id) DO UPDATE SET last_sync_at = NULL, last_page_cursor = NULL"
+    )
+    .execute(&pools.write)
+    .await?;
+    Ok(())
 }
 
-pub async fn save_checkpoint(pool: &PgPool, ts: &DateTime<Utc>) -> Result<()> {
+/// Load the page cursor saved by an interrupted sync, if any. `None` too if
+/// the `sync_state` row is missing.
+pub async fn load_page_cursor(pools: &DbPools) -> Result<Option<PageCursor>> {
+    let row = sqlx::query!(
+        r#"SELECT last_page_cursor AS "last_page_cursor: PageCursor" FROM sync_state WHERE id = 1"#
+    )
+    .fetch_optional(&pools.write)
+    .await?;
+    Ok(row.and_then(|row| row.last_page_cursor))
+}
+
+/// Record how far an unfinished sync got, leaving `last_sync_at` untouched.
+pub async fn save_page_cursor(pools: &DbPools, cursor: Option<&PageCursor>) -> Result<()> {
     sqlx::query!(
-        "INSERT INTO sync_state (id, last_sync_at) VALUES (1, $1)
-         ON CONFLICT (id) DO UPDATE SET last_sync_at = EXCLUDED.last_sync_at",
-        ts
+        "INSERT INTO sync_state (id, last_page_cursor) VALUES (1, $1)
+         ON CONFLICT (id) DO UPDATE SET last_page_cursor = EXCLUDED.last_page_cursor",
+        cursor.map(PageCursor::as_str)
     )
-    .execute(pool)
+    .execute(&pools.write)
     .await?;
     Ok(())
 }
+
+/// Checksum of the document fields of the last API page, see
+/// [`crate::api::schema_checksum`].
+pub async fn load_schema_checksum(pools: &DbPools) -> Result<Option<String>> {
+    let row = sqlx::query!("SELECT schema_checksum FROM sync_state WHERE id = 1")
+        .fetch_optional(&pools.write)
+        .await?;
+    Ok(row.and_then(|row| row.schema_checksum))
+}
+
+pub async fn save_schema_checksum(pools: &DbPools, checksum: &str) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO sync_state (id, schema_checksum) VALUES (1, $1)
+         ON CONFLICT (id) DO UPDATE SET schema_checksum = EXCLUDED.schema_checksum",
+        checksum
+    )
+    .execute(&pools.write)
+    .await?;
+    Ok(())
+}
+
+/// Checkpoint and page cursor of the syncs restricted to `category`, as
+/// [`load_checkpoint`] and [`load_page_cursor`] for syncs of every category.
+pub async fn load_category_state(
+    pools: &DbPools,
+    category: Category,
+) -> Result<(Option<DateTime<Utc>>, Option<PageCursor>)> {
+    let row = sqlx::query!(
+        r#"SELECT last_sync_at, last_page_cursor AS "last_page_cursor: PageCursor"
+           FROM category_sync_state WHERE category = $1"#,
+        category as _
+    )
+    .fetch_optional(&pools.write)
+    .await?;
+    Ok(row.map_or((None, None), |row| (row.last_sync_at, row.last_page_cursor)))
+}
+
+/// Record the checkpoint of a sync restricted to `category`. `None` for both
+/// forgets it.
+pub async fn save_category_checkpoint(
+    pools: &DbPools,
+    category: Category,
+    ts: Option<&DateTime<Utc>>,
+    cursor: Option<&PageCursor>,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO category_sync_state (category, last_sync_at, last_page_cursor)
+         VALUES ($1, $2, $3)
+         ON CONFLICT (category) DO UPDATE SET
+             last_sync_at = EXCLUDED.last_sync_at,
+             last_page_cursor = EXCLUDED.last_page_cursor",
+        category as _,
+        ts,
+        cursor.map(PageCursor::as_str)
+    )
+    .execute(&pools.write)
+    .await?;
+    Ok(())
+}
+
+/// Record how far an unfinished sync restricted to `category` got, leaving its
+/// checkpoint time untouched.
+pub async fn save_category_page_cursor(
+    pools: &DbPools,
+    category: Category,
+    cursor: Option<&PageCursor>,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO category_sync_state (category, last_page_cursor) VALUES ($1, $2)
+         ON CONFLICT (category) DO UPDATE SET last_page_cursor = EXCLUDED.last_page_cursor",
+        category as _,
+        cursor.map(PageCursor::as_str)
+    )
+    .execute(&pools.write)
+    .await?;
+    Ok(())
+}
+
+/// Number of documents stored locally.
+pub async fn count(pools: &DbPools) -> Result<i64> {
+    let row = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM reading WHERE deleted_at IS NULL"#)
+        .fetch_one(&pools.read)
+        .await?;
+    Ok(row.count)
+}
+
+/// Total word count per category, largest first.
+pub async fn word_count_by_category(pools: &DbPools) -> Result<Vec<(Category, i64)>> {
+    let rows = sqlx::query!(
+        r#"SELECT category AS "category!: Category", SUM(word_count) AS "words!"
+           FROM reading
+           WHERE category IS NOT NULL AND deleted_at IS NULL
+           GROUP BY category
+           ORDER BY SUM(word_count) DESC"#
+    )
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.category, r.words)).collect())
+}
+
+/// Average reading progress per category, in category order.
+pub async fn avg_progress_by_category(pools: &DbPools) -> Result<Vec<(Category, f64)>> {
+    let rows = sqlx::query!(
+        r#"SELECT category AS "category!: Category",
+                  AVG(COALESCE(reading_progress, 0))::float8 AS "progress!"
+           FROM reading
+           WHERE category IS NOT NULL AND deleted_at IS NULL
+           GROUP BY category
+           ORDER BY category"#
+    )
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.category, r.progress)).collect())
+}
+
+/// Number of documents for each category and location pair present.
+pub async fn item_count_by_category_and_location(
+    pools: &DbPools,
+) -> Result<Vec<(Category, Location, i64)>> {
+    let rows = sqlx::query!(
+        r#"SELECT category AS "category!: Category",
+                  location AS "location!: Location",
+                  COUNT(*) AS "count!"
+           FROM reading
+           WHERE category IS NOT NULL AND location IS NOT NULL AND deleted_at IS NULL
+           GROUP BY category, location
+           ORDER BY category, location"#
+    )
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| (r.category, r.location, r.count))
+        .collect())
+}
+
+/// Length of the periods of [`category_timeline`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Granularity {
+    Day,
+    /// Weeks starting on Monday
+    Week,
+    Month,
+}
+
+impl Granularity {
+    /// The unit of `date_trunc`.
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        }
+    }
+}
+
+/// Number of documents of one category saved during one period.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimelinePoint {
+    /// Start of the period, in UTC.
+    pub period: DateTime<Utc>,
+    pub category: Category,
+    pub count: i64,
+}
+
+/// Number of documents saved per period and category, oldest period first.
+/// Periods and categories without documents are left out.
+pub async fn category_timeline(
+    pools: &DbPools,
+    granularity: Granularity,
+    since: Option<DateTime<Utc>>,
+) -> Result<Vec<TimelinePoint>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT date_trunc($1, created_at AT TIME ZONE 'UTC') AT TIME ZONE 'UTC' AS "period!",
+               category AS "category!: Category",
+               COUNT(*) AS "count!"
+        FROM reading
+        WHERE category IS NOT NULL
+          AND parent_id IS NULL
+          AND deleted_at IS NULL
+          AND ($2::timestamptz IS NULL OR created_at >= $2)
+        GROUP BY 1, 2
+        ORDER BY 1, 2
+        "#,
+        granularity.as_str(),
+        since
+    )
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| TimelinePoint {
+            period: r.period,
+            category: r.category,
+            count: r.count,
+        })
+        .collect())
+}
+
+/// Ordering of [`top_authors`] and [`top_sites`], largest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsOrder {
+    /// Number of documents
+    Count,
+    /// Total word count
+    Words,
+    /// Average reading progress
+    Progress,
+}
+
+/// Aggregates over the documents of one author (or one site).
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AuthorStats {
+    pub name: String,
+    pub article_count: i64,
+    pub total_words: i64,
+    pub avg_progress: f64,
+}
+
+/// The `limit` authors with the most documents (or words, or progress)
+/// among those matching `filter`.
+pub async fn top_authors(
+    pools: &DbPools,
+    filter: &QueryFilter,
+    order: StatsOrder,
+    limit: usize,
+) -> Result<Vec<AuthorStats>> {
+    top_by(pools, "author", filter, order, limit).await
+}
+
+/// Reading profile of one author, over their documents (not highlights or
+/// notes).
+#[derive(Debug, Clone)]
+pub struct AuthorReadingStats {
+    /// The author's name as stored, or as requested if no document matched.
+    pub name: String,
+    pub total_articles: i64,
+    /// Read to the end.
+    pub completed: i64,
+    pub in_progress: i64,
+    pub unread: i64,
+    pub total_words: i64,
+    pub avg_progress: f64,
+    /// Oldest and latest `updated_at` among the documents with some progress.
+    pub first_read: Option<DateTime<Utc>>,
+    pub last_read: Option<DateTime<Utc>>,
+}
+
+/// [`AuthorReadingStats`] of the author called `name`, ignoring case.
+pub async fn author_reading_stats(pools: &DbPools, name: &str) -> Result<AuthorReadingStats> {
+    let pattern = name
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    let row = sqlx::query!(
+        r#"
+        SELECT COALESCE(MIN(author), $2) AS "name!",
+               COUNT(*) AS "total_articles!",
+               COUNT(*) FILTER (WHERE reading_progress >= 1) AS "completed!",
+               COUNT(*) FILTER (WHERE reading_progress > 0 AND reading_progress < 1) AS "in_progress!",
+               COUNT(*) FILTER (WHERE reading_progress = 0) AS "unread!",
+               COALESCE(SUM(word_count), 0)::bigint AS "total_words!",
+               COALESCE(AVG(reading_progress), 0)::float8 AS "avg_progress!",
+               MIN(updated_at) FILTER (WHERE reading_progress > 0) AS first_read,
+               MAX(updated_at) FILTER (WHERE reading_progress > 0) AS last_read
+        FROM reading
+        WHERE author ILIKE $1 AND parent_id IS NULL AND deleted_at IS NULL
+        "#,
+        pattern,
+        name
+    )
+    .fetch_one(&pools.read)
+    .await?;
+    Ok(AuthorReadingStats {
+        name: row.name,
+        total_articles: row.total_articles,
+        completed: row.completed,
+        in_progress: row.in_progress,
+        unread: row.unread,
+        total_words: row.total_words,
+        avg_progress: row.avg_progress,
+        first_read: row.first_read,
+        last_read: row.last_read,
+    })
+}
+
+/// Categories of the items in the database, deleted ones aside, in the order
+/// of the `category` enum.
+pub async fn list_categories_present(pools: &DbPools) -> Result<Vec<Category>> {
+    let categories = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT category AS "category!: Category"
+        FROM reading
+        WHERE deleted_at IS NULL AND category IS NOT NULL
+        ORDER BY 1
+        "#
+    )
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(categories)
+}
+
+/// Locations of the items in the database, deleted ones aside, in the order
+/// of the `location` enum.
+pub async fn list_locations_present(pools: &DbPools) -> Result<Vec<Location>> {
+    let locations = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT location AS "location!: Location"
+        FROM reading
+        WHERE deleted_at IS NULL AND location IS NOT NULL
+        ORDER BY 1
+        "#
+    )
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(locations)
+}
+
+/// Authors of the items in the database, deleted ones aside, alphabetically.
+pub async fn list_authors_present(pools: &DbPools) -> Result<Vec<String>> {
+    let authors = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT author AS "author!"
+        FROM reading
+        WHERE deleted_at IS NULL AND author IS NOT NULL
+        ORDER BY 1
+        "#
+    )
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(authors)
+}
+
+/// Distinct authors of the documents matching `filter`, alphabetically.
+pub async fn list_authors(pools: &DbPools, filter: &QueryFilter) -> Result<Vec<String>> {
+    let mut query = QueryBuilder::new("SELECT DISTINCT author FROM reading");
+    filter.push_where(&mut query);
+    query.push(" AND author IS NOT NULL ORDER BY author");
+    if let Some(limit) = filter.limit {
+        query.push(" LIMIT ").push_bind(limit);
+    }
+    Ok(query.build_query_scalar().fetch_all(&pools.read).await?)
+}
+
+/// Same as [`top_authors`], grouping documents by `site_name`.
+pub async fn top_sites(
+    pools: &DbPools,
+    filter: &QueryFilter,
+    order: StatsOrder,
+    limit: usize,
+) -> Result<Vec<AuthorStats>> {
+    top_by(pools, "site_name", filter, order, limit).await
+}
+
+/// Aggregates over the documents of one site, with the categories found there.
+#[derive(Debug, Clone)]
+pub struct SiteStats {
+    pub site_name: String,
+    pub article_count: i64,
+    pub avg_progress: f64,
+    pub total_words: i64,
+    pub categories: Vec<Category>,
+}
+
+/// [`SiteStats`] of the sites with at least `min_articles` documents (not
+/// highlights or notes), those with the most documents first.
+pub async fn aggregate_by_site(pools: &DbPools, min_articles: i64) -> Result<Vec<SiteStats>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT site_name AS "site_name!",
+               COUNT(*) AS "article_count!",
+               AVG(COALESCE(reading_progress, 0))::float8 AS "avg_progress!",
+               COALESCE(SUM(word_count), 0)::bigint AS "total_words!",
+               COALESCE(
+                   array_agg(DISTINCT category) FILTER (WHERE category IS NOT NULL),
+                   '{}'
+               ) AS "categories!: Vec<Category>"
+        FROM reading
+        WHERE site_name IS NOT NULL AND parent_id IS NULL AND deleted_at IS NULL
+        GROUP BY site_name
+        HAVING COUNT(*) >= $1
+        ORDER BY COUNT(*) DESC, site_name
+        "#,
+        min_articles
+    )
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| SiteStats {
+            site_name: row.site_name,
+            article_count: row.article_count,
+            avg_progress: row.avg_progress,
+            total_words: row.total_words,
+            categories: row.categories,
+        })
+        .collect())
+}
+
+/// Number of documents per `source` (how they were saved: RSS feed, email
+/// newsletter, browser extension…), ignoring those without one.
+pub async fn reading_sources_breakdown(pools: &DbPools) -> Result<HashMap<String, i64>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT source AS "source!", COUNT(*) AS "count!"
+        FROM reading
+        WHERE source IS NOT NULL AND parent_id IS NULL AND deleted_at IS NULL
+        GROUP BY source
+        "#
+    )
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.source, row.count))
+        .collect())
+}
+
+async fn top_by(
+    pools: &DbPools,
+    column: &'static str,
+    filter: &QueryFilter,
+    order: StatsOrder,
+    limit: usize,
+) -> Result<Vec<AuthorStats>> {
+    let mut query = QueryBuilder::new("");
+    push_top_by(&mut query, column, filter, order, limit);
+    Ok(query.build_query_as().fetch_all(&pools.read).await?)
+}
+
+fn push_top_by(
+    query: &mut QueryBuilder<'_, Postgres>,
+    column: &'static str,
+    filter: &QueryFilter,
+    order: StatsOrder,
+    limit: usize,
+) {
+    query.push(format!(
+        "SELECT {column} AS name, COUNT(*) AS article_count, \
+         SUM(word_count)::bigint AS total_words, \
+         AVG(COALESCE(reading_progress, 0))::float8 AS avg_progress \
+         FROM reading"
+    ));
+    filter.push_where(query);
+    query.push(format!(" GROUP BY {column} HAVING {column} IS NOT NULL"));
+    query.push(match order {
+        StatsOrder::Count => " ORDER BY article_count DESC",
+        StatsOrder::Words => " ORDER BY total_words DESC",
+        StatsOrder::Progress => " ORDER BY avg_progress DESC",
+    });
+    query
+        .push(", name LIMIT ")
+        .push_bind(i64::try_from(limit).unwrap_or(i64::MAX));
+}
+
+/// Number of documents, highlights and notes tagged with both `tag1` and
+/// `tag2`.
+pub async fn tag_co_occurrence(pools: &DbPools, tag1: &str, tag2: &str) -> Result<i64> {
+    // `?` matches the keys of the API's tag objects as well as plain arrays of
+    // names.
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM reading
+        WHERE tags ? $1 AND tags ? $2 AND deleted_at IS NULL
+        "#,
+        tag1,
+        tag2
+    )
+    .fetch_one(&pools.read)
+    .await?;
+    Ok(count)
+}
+
+/// The `limit` tags most often found alongside `tag`, with the number of
+/// items carrying both, most frequent first.
+pub async fn most_co_occurring_tags(
+    pools: &DbPools,
+    tag: &str,
+    limit: usize,
+) -> Result<Vec<(String, i64)>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT other AS "tag!", COUNT(*) AS "count!"
+        FROM reading
+        CROSS JOIN LATERAL (
+            SELECT jsonb_object_keys(tags) WHERE jsonb_typeof(tags) = 'object'
+            UNION ALL
+            SELECT jsonb_array_elements_text(tags) WHERE jsonb_typeof(tags) = 'array'
+        ) AS names(other)
+        WHERE tags ? $1 AND other <> $1 AND deleted_at IS NULL
+        GROUP BY other
+        ORDER BY 2 DESC, 1
+        LIMIT $2
+        "#,
+        tag,
+        i64::try_from(limit).unwrap_or(i64::MAX)
+    )
+    .fetch_all(&pools.read)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.tag, r.count)).collect())
+}
+
+/// Number of documents with a reading progress in `[lower, upper)` (the last
+/// bucket includes 1.0).
+#[derive(Debug, Clone)]
+pub struct ProgressBucket {
+    pub lower: f32,
+    pub upper: f32,
+    pub count: i64,
+}
+
+/// Split `[0, 1]` into `buckets` equal ranges and count the documents whose
+/// reading progress falls in each.
+pub async fn progress_distribution(pools: &DbPools, buckets: usize) -> Result<Vec<ProgressBucket>> {
+    anyhow::ensure!(buckets > 0, "At least one bucket is needed");
+    let mut distribution = progress_buckets(buckets);
+
+    let mut query = QueryBuilder::new("");
+    push_progress_distribution(&mut query, &distribution);
+    let rows: Vec<(Option<i32>, i64)> = query.build_query_as().fetch_all(&pools.read).await?;
+    for (bucket, count) in rows {
+        if let Some(bucket) = bucket.and_then(|b| distribution.get_mut(b as usize)) {
+            bucket.count = count;
+        }
+    }
+    Ok(distribution)
+}
+
+/// `buckets` empty buckets of equal width, covering 0 to 1.
+fn progress_buckets(buckets: usize) -> Vec<ProgressBucket> {
+    (0..buckets)
+        .map(|i| ProgressBucket {
+            lower: i as f32 / buckets as f32,
+            upper: (i + 1) as f32 / buckets as f32,
+            count: 0,
+        })
+        .collect()
+}
+
+fn push_progress_distribution(query: &mut QueryBuilder<'_, Postgres>, buckets: &[ProgressBucket]) {
+    query.push("SELECT CASE");
+    for (i, bucket) in buckets.iter().enumerate() {
+        let upper_op = if i + 1 == buckets.len() {
+            " <= "
+        } else {
+            " < "
+        };
+        query
+            .push(" WHEN reading_progress >= ")
+            .push_bind(bucket.lower)
+            .push(" AND reading_progress")
+            .push(upper_op)
+            .push_bind(bucket.upper)
+            .push(" THEN ")
+            .push_bind(i as i32);
+    }
+    query.push(
+        " END AS bucket, COUNT(*) AS count FROM reading \
+         WHERE reading_progress IS NOT NULL AND deleted_at IS NULL GROUP BY 1",
+    );
+}
+
+/// Fraction of documents read to the end, 0 when there are none.
+pub async fn completion_rate(pools: &DbPools) -> Result<f64> {
+    let rate = sqlx::query_scalar!(
+        r#"SELECT COALESCE(AVG((COALESCE(reading_progress, 0) >= 1)::int), 0)::float8 AS "rate!"
+           FROM reading
+           WHERE deleted_at IS NULL"#
+    )
+    .fetch_one(&pools.read)
+    .await?;
+    Ok(rate)
+}
+
+/// Personal reading speed in words per minute, measured from the progress
+/// made between consecutive entries of `reading_progress_history` at most an
+/// hour apart. `None` until such progress has been recorded.
+///
+/// Syncs only see progress at their own interval, and the time between two of
+/// them was not necessarily spent reading: the estimate is more accurate with
+/// frequent syncs, and errs on the slow side.
+pub async fn estimated_reading_speed(pools: &DbPools) -> Result<Option<f64>> {
+    let wpm = sqlx::query_scalar!(
+        r#"
+        WITH steps AS (
+            SELECT item_id,
+                   progress - LAG(progress) OVER w AS gained,
+                   EXTRACT(EPOCH FROM recorded_at - LAG(recorded_at) OVER w) / 60 AS minutes
+            FROM reading_progress_history
+            WINDOW w AS (PARTITION BY item_id ORDER BY recorded_at, id)
+        )
+        SELECT (SUM(s.gained * r.word_count) / NULLIF(SUM(s.minutes), 0))::float8 AS wpm
+        FROM steps s
+        JOIN reading r ON r.id = s.item_id
+        WHERE s.gained > 0 AND s.minutes <= 60 AND r.word_count > 0 AND r.deleted_at IS NULL
+        "#
+    )
+    .fetch_one(&pools.read)
+    .await?;
+    Ok(wpm.filter(|wpm| *wpm > 0.0))
+}
+
+/// Time left to finish reading a document at [`estimated_reading_speed`].
+/// `None` if the document is unknown, has no word count, or the reading speed
+/// can't be estimated yet.
+pub async fn estimated_time_remaining(pools: &DbPools, id: &ItemId) -> Result<Option<Duration>> {
+    let Some(record) = find_record(&pools.read, id).await? else {
+        return Ok(None);
+    };
+    if record.word_count <= 0 {
+        return Ok(None);
+    }
+    let Some(wpm) = estimated_reading_speed(pools).await? else {
+        return Ok(None);
+    };
+    let progress = f64::from(record.reading_progress.clamp(0.0, 1.0));
+    let remaining_words = f64::from(record.word_count) * (1.0 - progress);
+    Ok(Some(Duration::from_secs_f64(remaining_words / wpm * 60.0)))
+}
+
+/// Words read in documents updated since midnight in the time zone `offset`:
+/// the sum of `word_count * reading_progress`. The whole progress of those
+/// documents counts, including what was read before the period.
+pub async fn words_read_today(pools: &DbPools, offset: FixedOffset) -> Result<i64> {
+    let today = Utc::now().with_timezone(&offset).date_naive();
+    words_read_since(pools, local_midnight(today, offset)).await
+}
+
+/// [`words_read_today`], since the start of the week (Monday).
+pub async fn words_read_this_week(pools: &DbPools, offset: FixedOffset) -> Result<i64> {
+    let today = Utc::now().with_timezone(&offset).date_naive();
+    let monday = today - chrono::Days::new(u64::from(today.weekday().num_days_from_monday()));
+    words_read_since(pools, local_midnight(monday, offset)).await
+}
+
+/// [`words_read_today`], since the first day of the month.
+pub async fn words_read_this_month(pools: &DbPools, offset: FixedOffset) -> Result<i64> {
+    let today = Utc::now().with_timezone(&offset).date_naive();
+    let first = today.with_day(1).expect("every month has a first day");
+    words_read_since(pools, local_midnight(first, offset)).await
+}
+
+fn local_midnight(date: NaiveDate, offset: FixedOffset) -> DateTime<Utc> {
+    date.and_time(chrono::NaiveTime::MIN)
+        .and_local_timezone(offset)
+        .single()
+        .expect("fixed offsets have no ambiguous times")
+        .with_timezone(&Utc)
+}
+
+async fn words_read_since(pools: &DbPools, since: DateTime<Utc>) -> Result<i64> {
+    let words = sqlx::query_scalar!(
+        r#"SELECT COALESCE(SUM(word_count * reading_progress), 0)::bigint AS "words!"
+           FROM reading
+           WHERE updated_at >= $1 AND deleted_at IS NULL"#,
+        since
+    )
+    .fetch_one(&pools.read)
+    .await?;
+    Ok(words)
+}
+
+/// Sum of the estimated reading times of the documents matching `filter`,
+/// highlights and notes excluded.
+pub async fn total_reading_time_minutes(pools: &DbPools, filter: &QueryFilter) -> Result<i64> {
+    let mut query =
+        QueryBuilder::new("SELECT COALESCE(SUM(reading_time_minutes), 0)::bigint FROM reading");
+    filter.push_where(&mut query);
+    query.push(" AND parent_id IS NULL");
+    Ok(query.build_query_scalar().fetch_one(&pools.read).await?)
+}
+
+/// Reclaim the space of deleted rows and refresh the planner statistics of
+/// the `reading` table, for after large syncs that autovacuum may lag behind.
+pub async fn vacuum_and_analyze(pools: &DbPools) -> Result<()> {
+    // VACUUM can't run in a transaction block, hence a plain connection and
+    // the simple query protocol.
+    let mut conn = pools.write.acquire().await?;
+    let size_before = reading_size(&mut conn).await?;
+    let started_at = Instant::now();
+    sqlx::raw_sql("VACUUM ANALYZE reading")
+        .execute(&mut *conn)
+        .await?;
+    let size_after = reading_size(&mut conn).await?;
+    info!(
+        "VACUUM ANALYZE reading took {:.1?}, size {} kB -> {} kB",
+        started_at.elapsed(),
+        size_before / 1024,
+        size_after / 1024
+    );
+    Ok(())
+}
+
+/// Rebuild every index of the `reading` table, without locking out writes on
+/// PostgreSQL 12 and later. Older servers get a plain, locking `REINDEX`.
+pub async fn reindex_concurrently(pools: &DbPools) -> Result<()> {
+    // REINDEX CONCURRENTLY can't run in a transaction block either.
+    let mut conn = pools.write.acquire().await?;
+    let version =
+        sqlx::query_scalar!(r#"SELECT current_setting('server_version_num')::int AS "version!""#)
+            .fetch_one(&mut *conn)
+            .await?;
+    let concurrently = if version >= 120_000 {
+        " CONCURRENTLY"
+    } else {
+        warn!("PostgreSQL {version} can't reindex concurrently, writes are blocked meanwhile");
+        ""
+    };
+
+    let indexes = sqlx::query_scalar!(
+        r#"SELECT quote_ident(indexname) AS "name!" FROM pg_indexes
+           WHERE schemaname = current_schema() AND tablename = 'reading'
+           ORDER BY indexname"#
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for index in indexes {
+        let started_at = Instant::now();
+        sqlx::raw_sql(&format!("REINDEX INDEX{concurrently} {index}"))
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("Failed to reindex {index}"))?;
+        info!("Reindexed {index} in {:.1?}", started_at.elapsed());
+    }
+    Ok(())
+}
+
+/// Queries [`explain`] knows about.
+pub const EXPLAINABLE_QUERIES: &[&str] = &[
+    "stream_all",
+    "top_authors",
+    "top_sites",
+    "progress_distribution",
+];
+
+/// The plan of the query called `name`, as `EXPLAIN (ANALYZE, BUFFERS)` prints
+/// it, with `sample_filter` for the queries taking a filter.
+///
+/// The query is run for real, however long it takes.
+pub async fn explain(pool: &PgPool, name: &str, sample_filter: &QueryFilter) -> Result<String> {
+    let mut query = QueryBuilder::new("EXPLAIN (ANALYZE, BUFFERS) ");
+    match name {
+        "stream_all" => {
+            query.push("SELECT * FROM reading");
+            sample_filter.push_sql(&mut query);
+        }
+        "top_authors" => push_top_by(&mut query, "author", sample_filter, StatsOrder::Count, 20),
+        "top_sites" => push_top_by(
+            &mut query,
+            "site_name",
+            sample_filter,
+            StatsOrder::Count,
+            20,
+        ),
+        "progress_distribution" => push_progress_distribution(&mut query, &progress_buckets(10)),
+        _ => anyhow::bail!(
+            "Unknown query '{name}', expected one of: {}",
+            EXPLAINABLE_QUERIES.join(", ")
+        ),
+    }
+    let lines: Vec<String> = query.build_query_scalar().fetch_all(pool).await?;
+    Ok(lines.join("\n"))
+}
+
+/// Size of the `reading` table and its indexes, in bytes.
+async fn reading_size(conn: &mut PgConnection) -> Result<i64> {
+    Ok(
+        sqlx::query_scalar!(r#"SELECT pg_total_relation_size('reading') AS "size!""#)
+            .fetch_one(conn)
+            .await?,
+    )
+}
+
+/// Disk usage and upkeep of the `reading` table.
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageStats {
+    /// The table itself, with its TOAST data.
+    pub table_size_bytes: i64,
+    pub index_size_bytes: i64,
+    pub total_size_bytes: i64,
+    /// Estimates maintained by the statistics collector.
+    pub row_count: i64,
+    pub dead_row_count: i64,
+    /// Latest manual or automatic run.
+    pub last_vacuum: Option<DateTime<Utc>>,
+    pub last_analyze: Option<DateTime<Utc>>,
+}
+
+/// [`StorageStats`] of the `reading` table.
+pub async fn storage_usage(pools: &DbPools) -> Result<StorageStats> {
+    let row = sqlx::query!(
+        r#"
+        SELECT pg_table_size(relid) AS "table_size!",
+               pg_indexes_size(relid) AS "index_size!",
+               pg_total_relation_size(relid) AS "total_size!",
+               n_live_tup AS "row_count!",
+               n_dead_tup AS "dead_row_count!",
+               GREATEST(last_vacuum, last_autovacuum) AS last_vacuum,
+               GREATEST(last_analyze, last_autoanalyze) AS last_analyze
+        FROM pg_stat_user_tables
+        WHERE schemaname = current_schema() AND relname = 'reading'
+        "#
+    )
+    .fetch_one(&pools.read)
+    .await?;
+    Ok(StorageStats {
+        table_size_bytes: row.table_size,
+        index_size_bytes: row.index_size,
+        total_size_bytes: row.total_size,
+        row_count: row.row_count,
+        dead_row_count: row.dead_row_count,
+        last_vacuum: row.last_vacuum,
+        last_analyze: row.last_analyze,
+    })
+}
+
+/// Problems found in the `reading` table by [`check_consistency`].
+#[derive(Debug, Default, Clone)]
+pub struct ConsistencyReport {
+    /// Highlights and notes whose parent is not in the table.
+    pub orphaned_highlights: usize,
+    /// Documents sharing their `source_url` with another, more recent document.
+    pub duplicate_urls: usize,
+    /// Rows with a missing `reading_progress`.
+    pub invalid_progress_values: usize,
+    pub missing_category_items: usize,
+    /// Rows whose `source_url` does not start with a URL scheme, saved before
+    /// the `source_url_has_scheme` constraint.
+    pub malformed_source_urls: usize,
+}
+
+impl ConsistencyReport {
+    pub fn is_clean(&self) -> bool {
+        self.orphaned_highlights == 0
+            && self.duplicate_urls == 0
+            && self.invalid_progress_values == 0
+            && self.missing_category_items == 0
+            && self.malformed_source_urls == 0
+    }
+
+    pub fn print(&self) {
+        println!("Orphaned highlights:     {}", self.orphaned_highlights);
+        println!("Duplicate URLs:          {}", self.duplicate_urls);
+        println!("Invalid progress values: {}", self.invalid_progress_values);
+        println!("Missing category:        {}", self.missing_category_items);
+        println!("Malformed source URLs:   {}", self.malformed_source_urls);
+    }
+}
+
+/// Run every consistency check against the read pool, concurrently.
+pub async fn check_consistency(pools: &DbPools) -> Result<ConsistencyReport> {
+    let (orphaned, duplicates, invalid_progress, missing_category, malformed_urls) = tokio::join!(
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM reading r
+               WHERE parent_id IS NOT NULL AND deleted_at IS NULL
+                 AND NOT EXISTS (SELECT 1 FROM reading p WHERE p.id = r.parent_id)"#
+        )
+        .fetch_one(&pools.read),
+        sqlx::query_scalar!(
+            r#"SELECT (COUNT(*) - COUNT(DISTINCT source_url)) AS "count!" FROM reading
+               WHERE source_url IS NOT NULL AND parent_id IS NULL AND deleted_at IS NULL"#
+        )
+        .fetch_one(&pools.read),
+        // The `reading_progress_bounds` constraint keeps values within
+        // [0, 1], but lets NULL through.
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM reading WHERE reading_progress IS NULL"#
+        )
+        .fetch_one(&pools.read),
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM reading WHERE category IS NULL"#)
+            .fetch_one(&pools.read),
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM reading
+               WHERE source_url !~ '^[A-Za-z][A-Za-z0-9+.-]*:.'"#
+        )
+        .fetch_one(&pools.read),
+    );
+
+    let count = |n: i64| usize::try_from(n).unwrap_or(0);
+    Ok(ConsistencyReport {
+        orphaned_highlights: count(orphaned?),
+        duplicate_urls: count(duplicates?),
+        invalid_progress_values: count(invalid_progress?),
+        missing_category_items: count(missing_category?),
+        malformed_source_urls: count(malformed_urls?),
+    })
+}
+
+/// Delete documents sharing their `source_url` with a more recently updated
+/// one, moving their highlights to the document that is kept. Returns the
+/// number of documents deleted.
+///
+/// This only affects the local database: the duplicates stay in Readwise and
+/// come back on a full sync.
+pub async fn merge_duplicates(pools: &DbPools) -> Result<u64> {
+    let deleted = sqlx::query!(
+        r#"
+        WITH ranked AS (
+            SELECT id, FIRST_VALUE(id) OVER (
+                PARTITION BY source_url
+                ORDER BY updated_at DESC NULLS LAST, created_at DESC, id
+            ) AS kept_id
+            FROM reading
+            WHERE source_url IS NOT NULL AND parent_id IS NULL AND deleted_at IS NULL
+        ),
+        duplicates AS (
+            SELECT id, kept_id FROM ranked WHERE id <> kept_id
+        ),
+        moved AS (
+            UPDATE reading r SET parent_id = d.kept_id
+            FROM duplicates d
+            WHERE r.parent_id = d.id
+        )
+        DELETE FROM reading r USING duplicates d WHERE r.id = d.id
+        "#
+    )
+    .execute(&pools.write)
+    .await?
+    .rows_affected();
+    Ok(deleted)
+}
+
+/// Delete highlights and notes whose parent is not in the table. Returns the
+/// number of rows deleted.
+pub async fn delete_orphaned_highlights(pools: &DbPools) -> Result<u64> {
+    let deleted = sqlx::query!(
+        "DELETE FROM reading r
+         WHERE parent_id IS NOT NULL AND deleted_at IS NULL
+           AND NOT EXISTS (SELECT 1 FROM reading p WHERE p.id = r.parent_id)"
+    )
+    .execute(&pools.write)
+    .await?
+    .rows_affected();
+    Ok(deleted)
+}
+
+/// Clear `source_url` values that do not start with a URL scheme. Returns the
+/// number of rows updated.
+pub async fn clear_malformed_source_urls(pools: &DbPools) -> Result<u64> {
+    let updated = sqlx::query!(
+        "UPDATE reading SET source_url = NULL
+         WHERE source_url !~ '^[A-Za-z][A-Za-z0-9+.-]*:.'"
+    )
+    .execute(&pools.write)
+    .await?
+    .rows_affected();
+    Ok(updated)
+}
+
+/// Set missing or out-of-range `reading_progress` values to the closest valid
+/// one (0 when missing). Returns the number of rows updated.
+pub async fn clamp_reading_progress(pools: &DbPools) -> Result<u64> {
+    let updated = sqlx::query!(
+        "UPDATE reading
+         SET reading_progress = LEAST(GREATEST(COALESCE(reading_progress, 0), 0), 1)
+         WHERE reading_progress IS NULL OR reading_progress < 0 OR reading_progress > 1"
+    )
+    .execute(&pools.write)
+    .await?
+    .rows_affected();
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::testing::ReaderResultBuilder;
+
+    /// A database error with a SQLSTATE, as PostgreSQL would report it.
+    #[derive(Debug)]
+    struct FakeDatabaseError(&'static str);
+
+    impl std::fmt::Display for FakeDatabaseError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "SQLSTATE {}", self.0)
+        }
+    }
+
+    impl std::error::Error for FakeDatabaseError {}
+
+    impl sqlx::error::DatabaseError for FakeDatabaseError {
+        fn message(&self) -> &str {
+            "fake"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.0))
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> sqlx::error::ErrorKind {
+            sqlx::error::ErrorKind::Other
+        }
+    }
+
+    /// Run `retry_serialization_failures` on a transaction failing with
+    /// `code` `failures` times, returning its result and the number of runs.
+    async fn run_failing(code: &'static str, failures: u32) -> (Result<&'static str>, u32) {
+        let mut runs = 0;
+        let result = retry_serialization_failures(|| {
+            runs += 1;
+            let failed = runs <= failures;
+            async move {
+                if failed {
+                    let e = sqlx::Error::Database(Box::new(FakeDatabaseError(code)));
+                    Err(anyhow::Error::from(e).context("Failed to save"))
+                } else {
+                    Ok("saved")
+                }
+            }
+        })
+        .await;
+        (result, runs)
+    }
+
+    #[tokio::test]
+    async fn serialization_failures_are_retried() {
+        let (result, runs) = run_failing("40001", 2).await;
+        assert_eq!(result.unwrap(), "saved");
+        assert_eq!(runs, 3);
+
+        let (result, runs) = run_failing("40001", SERIALIZATION_RETRIES + 1).await;
+        assert!(is_serialization_failure(&result.unwrap_err()));
+        assert_eq!(runs, SERIALIZATION_RETRIES + 1);
+    }
+
+    #[tokio::test]
+    async fn other_database_errors_are_not_retried() {
+        // Deadlock detected: not a serialization failure, even if retryable.
+        let (result, runs) = run_failing("40P01", 2).await;
+        assert!(!is_serialization_failure(&result.unwrap_err()));
+        assert_eq!(runs, 1);
+    }
+
+    #[test]
+    fn filters_match_documents_outside_the_database() {
+        let day = |d| Utc.with_ymd_and_hms(2024, 3, d, 0, 0, 0).unwrap();
+        let item = ReaderResultBuilder::new()
+            .category(Category::Pdf)
+            .location(Some(Location::Later))
+            .reading_progress(0.5)
+            .word_count(1000)
+            .created_at(day(2))
+            .build();
+        let matches = |filter: QueryFilter| filter.matches(&item);
+
+        assert!(matches(QueryFilter::default()));
+        assert!(matches(QueryFilter {
+            category: Some(Category::Pdf),
+            location: Some(Location::Later),
+            min_reading_progress: Some(0.5),
+            max_reading_progress: Some(0.5),
+            min_word_count: Some(1000),
+            max_word_count: Some(1000),
+            since: Some(day(2)),
+            until: Some(day(3)),
+            limit: Some(0),
+            ..Default::default()
+        }));
+        assert!(!matches(QueryFilter {
+            category: Some(Category::Article),
+            ..Default::default()
+        }));
+        assert!(!matches(QueryFilter {
+            location: Some(Location::Archive),
+            ..Default::default()
+        }));
+        assert!(!matches(QueryFilter {
+            min_reading_progress: Some(0.75),
+            ..Default::default()
+        }));
+        assert!(!matches(QueryFilter {
+            max_reading_progress: Some(0.25),
+            ..Default::default()
+        }));
+        assert!(!matches(QueryFilter {
+            max_word_count: Some(999),
+            ..Default::default()
+        }));
+        // `until` is exclusive.
+        assert!(!matches(QueryFilter {
+            until: Some(day(2)),
+            ..Default::default()
+        }));
+        assert!(!matches(QueryFilter {
+            since: Some(day(3)),
+            ..Default::default()
+        }));
+
+        let unknown_length = ReaderResultBuilder::new().word_count(0).build();
+        let min_words = |include_zero_word_count| QueryFilter {
+            min_word_count: Some(500),
+            include_zero_word_count,
+            ..Default::default()
+        };
+        assert!(!min_words(false).matches(&unknown_length));
+        assert!(min_words(true).matches(&unknown_length));
+    }
+
+    #[test]
+    fn null_bytes_are_stripped_before_saving() {
+        let result: ApiResult =
+            serde_json::from_str(include_str!("../fixtures/null_bytes.jsonl")).unwrap();
+        let record = DbRecord::from(ReadingItem::from(result));
+
+        let stripped = strip_null_bytes(&record);
+        assert_eq!(stripped.title, "Scanned newsletter");
+        assert_eq!(stripped.content.as_deref(), Some("<p>Body text</p>"));
+        assert_eq!(stripped.notes.as_deref(), Some("Note"));
+        assert_eq!(stripped.summary.as_deref(), Some("A summary"));
+        assert!(matches!(strip_null_bytes(&stripped), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn csv_numbers_and_dates_follow_the_options() {
+        let options = CsvExportOptions {
+            locale: CsvLocale {
+                decimal_separator: ',',
+                thousands_separator: Some(' '),
+            },
+            date_format: String::from("%d/%m/%Y"),
+            ..Default::default()
+        };
+        let format = |column, value: &str| options.format(column, value.to_string());
+        assert_eq!(format(CsvColumn::WordCount, "1234567"), "1 234 567");
+        assert_eq!(format(CsvColumn::WordCount, "-123"), "-123");
+        assert_eq!(format(CsvColumn::ReadingProgress, "0.25"), "0,25");
+        assert_eq!(format(CsvColumn::ReadingProgress, "1e-05"), "1e-05");
+        assert_eq!(
+            format(CsvColumn::CreatedAt, "2024-01-31T23:30:00-02:00"),
+            "01/02/2024"
+        );
+        assert_eq!(format(CsvColumn::Title, "1234.5"), "1234.5");
+
+        let default = CsvExportOptions::default();
+        assert_eq!(
+            default.format(CsvColumn::ReadingProgress, String::from("0.25")),
+            "0.25"
+        );
+        assert_eq!(
+            default.format(
+                CsvColumn::UpdatedAt,
+                String::from("2024-01-31T12:00:00+00:00")
+            ),
+            "2024-01-31"
+        );
+    }
+
+    #[test]
+    fn a_missing_updated_at_is_not_a_change() {
+        let updated_at = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let old = DbRecord::from(ReaderResultBuilder::new().updated_at(updated_at).build());
+        let new = DbRecord {
+            updated_at: None,
+            ..old.clone()
+        };
+        assert!(changed_fields(&old, &new).is_empty());
+
+        let newer = DbRecord {
+            updated_at: Some(updated_at + chrono::TimeDelta::hours(1)),
+            ..old.clone()
+        };
+        let changes = changed_fields(&old, &newer);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].0, "updated_at");
+    }
+
+    #[test]
+    fn changes_are_reported_as_text() {
+        let created_at = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let old = DbRecord::from(
+            ReaderResultBuilder::new()
+                .title("Old title")
+                .created_at(created_at)
+                .location(Some(Location::Later))
+                .reading_progress(0.5)
+                .tags(serde_json::json!({}))
+                .build(),
+        );
+        let new = DbRecord::from(
+            ReaderResultBuilder::new()
+                .title("New title")
+                .author("Ursula K. Le Guin")
+                .created_at(created_at + chrono::TimeDelta::days(1))
+                .location(Some(Location::Archive))
+                .reading_progress(0.75)
+                .tags(serde_json::json!({"fantasy": {"name": "fantasy"}}))
+                .build(),
+        );
+
+        let text = |s: &str| Some(s.to_string());
+        assert_eq!(
+            changed_fields(&old, &new),
+            [
+                ("author", None, text("Ursula K. Le Guin")),
+                (
+                    "created_at",
+                    text("2024-03-01T12:00:00Z"),
+                    text("2024-03-02T12:00:00Z")
+                ),
+                ("location", text("later"), text("archive")),
+                ("reading_progress", text("0.5"), text("0.75")),
+                (
+                    "tags",
+                    text("{}"),
+                    text(r#"{"fantasy":{"name":"fantasy"}}"#)
+                ),
+                ("title", text("Old title"), text("New title")),
+            ]
+        );
+        assert!(changed_fields(&new, &new.clone()).is_empty());
+    }
+
+    #[test]
+    fn batches_are_split_into_chunks_of_the_configured_size() {
+        let chunk_size = 3;
+        let records: Vec<_> = (0..=chunk_size)
+            .map(|n| {
+                let item = ReaderResultBuilder::new().id(format!("doc-{n}")).build();
+                DbRecord::from(item)
+            })
+            .collect();
+
+        let chunks: Vec<_> = insert_chunks(&records, chunk_size).collect();
+        let sizes: Vec<_> = chunks.iter().map(|chunk| chunk.len()).collect();
+        assert_eq!(sizes, [chunk_size, 1]);
+        assert_eq!(chunks.concat(), records);
+
+        assert_eq!(insert_chunks(&records, usize::MAX).len(), 1);
+        assert_eq!(insert_chunks(&records, 0).len(), records.len());
+    }
+}
@@ -0,0 +1,52 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SyncError {
+    #[error("Readwise API request failed after {attempts} attempts: {last_error}")]
+    MaxRetriesExceeded { attempts: u32, last_error: String },
+
+    /// The request did not get a response, e.g. the connection failed or timed out.
+    #[error("Network transport error: {0}")]
+    Transport(String),
+
+    /// A `401 Unauthorized` response.
+    #[error(
+        "Readwise API token is invalid or expired. Check your READWISE_ACCESS_TOKEN environment variable."
+    )]
+    Unauthorized,
+
+    /// A `403 Forbidden` response: the token is valid but may not use Reader.
+    /// `message` is the error the API gave, if any.
+    #[error(
+        "Readwise Reader access may not be enabled for this account{}",
+        sentence(.message)
+    )]
+    Forbidden { message: String },
+
+    /// A 4xx response other than 401, 403 and 429, which retrying would not fix.
+    /// `message` is the error the API gave, if any.
+    #[error("Non-retryable HTTP error {status} from Readwise API{}", sentence(.message))]
+    HttpStatus { status: u16, message: String },
+
+    /// The body was not read past `limit` bytes, so `actual` is a lower bound
+    /// unless the response declared its length.
+    #[error("Readwise API response of {actual} bytes exceeds the {limit} bytes limit")]
+    ResponseTooLarge { actual: usize, limit: usize },
+}
+
+impl SyncError {
+    /// The access token is rejected: syncing again won't work until the
+    /// configuration changes.
+    pub fn is_configuration_error(&self) -> bool {
+        matches!(self, Self::Unauthorized | Self::Forbidden { .. })
+    }
+}
+
+/// `message` as a sentence following another, or nothing if it is empty.
+fn sentence(message: &str) -> String {
+    if message.is_empty() {
+        String::from(".")
+    } else {
+        format!(". {message}")
+    }
+}
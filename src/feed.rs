@@ -0,0 +1,362 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::Result;
+use axum::body::Body;
+use axum::extract::{Query, Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::net::TcpListener;
+
+use crate::convert::{self, FeedMeta};
+use crate::db::QueryFilter;
+use crate::models::{Category, Location};
+use crate::storage::StorageBackend;
+
+/// How long clients may reuse a feed before asking again.
+const MAX_AGE_SECS: u32 = 300;
+
+/// Smaller responses are sent uncompressed: gzip would save little, if anything.
+const GZIP_MIN_BYTES: usize = 1024;
+
+pub struct FeedState {
+    pub storage: Arc<dyn StorageBackend>,
+    /// Channel fields of the feeds; `last_build_date` is set per request.
+    pub meta: FeedMeta,
+    /// Number of documents in a feed, the most recent ones.
+    pub max_items: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FeedFormat {
+    #[default]
+    Rss,
+    Atom,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FeedQuery {
+    #[serde(default)]
+    format: FeedFormat,
+    category: Option<Category>,
+    location: Option<Location>,
+}
+
+/// Serve the documents as RSS 2.0 on `GET /rss` and Atom 1.0 on `GET /atom`
+/// (or `GET /?format=rss|atom`) on `port` until the process is stopped.
+pub async fn serve(port: u16, state: FeedState) -> Result<()> {
+    let app = router(state);
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    info!("Serving feeds on port {port}");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+fn router(state: FeedState) -> Router {
+    Router::new()
+        .route("/", get(feed))
+        .route("/rss", get(rss))
+        .route("/atom", get(atom))
+        .layer(middleware::from_fn(gzip))
+        .with_state(Arc::new(state))
+}
+
+/// Compress responses larger than [`GZIP_MIN_BYTES`] for clients accepting
+/// gzip.
+async fn gzip(request: Request, next: Next) -> Response {
+    let accepts_gzip = request
+        .headers()
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            params
+                .next()
+                .is_some_and(|name| name.eq_ignore_ascii_case("gzip"))
+                && !params.any(|param| param == "q=0")
+        });
+    let response = next.run(request).await;
+    if !accepts_gzip || response.headers().contains_key(header::CONTENT_ENCODING) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to read response body: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if body.len() <= GZIP_MIN_BYTES {
+        return Response::from_parts(parts, Body::from(body));
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let compressed = match encoder.write_all(&body).and_then(|()| encoder.finish()) {
+        Ok(compressed) => compressed,
+        Err(e) => {
+            error!("Failed to compress response: {e}");
+            return Response::from_parts(parts, Body::from(body));
+        }
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    Response::from_parts(parts, Body::from(compressed))
+}
+
+async fn feed(
+    State(state): State<Arc<FeedState>>,
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+) -> Response {
+    render(&state, &query, &headers).await
+}
+
+async fn rss(
+    State(state): State<Arc<FeedState>>,
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let query = FeedQuery {
+        format: FeedFormat::Rss,
+        ..query
+    };
+    render(&state, &query, &headers).await
+}
+
+async fn atom(
+    State(state): State<Arc<FeedState>>,
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let query = FeedQuery {
+        format: FeedFormat::Atom,
+        ..query
+    };
+    render(&state, &query, &headers).await
+}
+
+async fn render(state: &FeedState, query: &FeedQuery, headers: &HeaderMap) -> Response {
+    let filter = QueryFilter {
+        category: query.category,
+        location: query.location,
+        ..Default::default()
+    };
+    let items = match state
+        .storage
+        .latest_documents(&filter, state.max_items)
+        .await
+    {
+        Ok(items) => items,
+        Err(e) => {
+            error!("Failed to load feed documents: {e:#}");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": format!("{e:#}") })),
+            )
+                .into_response();
+        }
+    };
+
+    let last_modified = items
+        .iter()
+        .map(|item| item.updated_at.unwrap_or(item.created_at))
+        .max()
+        .unwrap_or(DateTime::UNIX_EPOCH);
+    let cache_headers = [
+        (header::LAST_MODIFIED, http_date(&last_modified)),
+        (header::CACHE_CONTROL, format!("max-age={MAX_AGE_SECS}")),
+    ];
+    if not_modified_since(headers, &last_modified) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    let meta = FeedMeta {
+        last_build_date: last_modified,
+        ..state.meta.clone()
+    };
+    let (content_type, body) = match query.format {
+        FeedFormat::Rss => ("application/rss+xml", convert::to_rss_feed(&items, &meta)),
+        FeedFormat::Atom => ("application/atom+xml", convert::to_atom_feed(&items, &meta)),
+    };
+    (
+        [(header::CONTENT_TYPE, content_type.to_string())],
+        cache_headers,
+        body,
+    )
+        .into_response()
+}
+
+fn http_date(time: &DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// The request's `If-Modified-Since` is at or after `last_modified`, to the
+/// second as HTTP dates go.
+fn not_modified_since(headers: &HeaderMap, last_modified: &DateTime<Utc>) -> bool {
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .is_some_and(|since| since.timestamp() >= last_modified.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use axum::http::Request;
+    use chrono::TimeZone;
+    use flate2::read::GzDecoder;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::testing::{InMemoryBackend, ReaderResultBuilder};
+
+    /// Saved an hour apart from this time on.
+    fn saved_at(n: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap() + chrono::Duration::hours(n)
+    }
+
+    async fn app() -> Router {
+        let storage = InMemoryBackend::new();
+        for n in 0..20 {
+            let item = ReaderResultBuilder::new()
+                .id(format!("doc-{n}"))
+                .title(format!("Document {n}"))
+                .created_at(saved_at(n))
+                .build();
+            storage.save(&item).await.unwrap();
+        }
+        // Older than the 20 articles, so only in feeds filtered on PDFs.
+        let pdf = ReaderResultBuilder::new()
+            .id("pdf")
+            .title("Manual")
+            .category(Category::Pdf)
+            .created_at(saved_at(-1))
+            .build();
+        storage.save(&pdf).await.unwrap();
+        router(FeedState {
+            storage: Arc::new(storage),
+            meta: FeedMeta {
+                title: String::from("Test"),
+                description: String::from("Test feed"),
+                link: String::from("https://example.com/"),
+                last_build_date: Utc::now(),
+            },
+            max_items: 20,
+        })
+    }
+
+    async fn get(uri: &str, headers: &[(header::HeaderName, &str)]) -> Response {
+        let mut request = Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        app()
+            .await
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn large_feeds_are_gzipped_for_clients_accepting_it() {
+        let plain = get("/rss", &[]).await;
+        assert!(!plain.headers().contains_key(header::CONTENT_ENCODING));
+        let plain = body(plain).await;
+        assert!(plain.len() > GZIP_MIN_BYTES);
+
+        let gzipped = get("/rss", &[(header::ACCEPT_ENCODING, "br, gzip;q=0.8")]).await;
+        assert_eq!(gzipped.headers()[header::CONTENT_ENCODING], "gzip");
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&body(gzipped).await[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain);
+    }
+
+    #[tokio::test]
+    async fn gzip_is_not_used_when_refused() {
+        let response = get("/rss", &[(header::ACCEPT_ENCODING, "gzip;q=0, identity")]).await;
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn feeds_carry_cache_headers() {
+        let response = get("/rss", &[]).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::LAST_MODIFIED],
+            "Fri, 01 Mar 2024 19:00:00 GMT"
+        );
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "max-age=300");
+    }
+
+    #[tokio::test]
+    async fn unmodified_feeds_are_not_sent_again() {
+        let unmodified = get(
+            "/rss",
+            &[(header::IF_MODIFIED_SINCE, "Fri, 01 Mar 2024 19:00:00 GMT")],
+        )
+        .await;
+        assert_eq!(unmodified.status(), StatusCode::NOT_MODIFIED);
+        assert!(body(unmodified).await.is_empty());
+
+        let modified = get(
+            "/rss",
+            &[(header::IF_MODIFIED_SINCE, "Fri, 01 Mar 2024 18:59:59 GMT")],
+        )
+        .await;
+        assert_eq!(modified.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn the_format_is_chosen_by_query() {
+        let response = get("/?format=atom", &[]).await;
+
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/atom+xml"
+        );
+        let feed = String::from_utf8(body(response).await).unwrap();
+        assert!(feed.contains("<feed"));
+    }
+
+    #[tokio::test]
+    async fn feeds_are_filtered_by_category() {
+        let all = String::from_utf8(body(get("/rss", &[]).await).await).unwrap();
+        assert!(all.contains("Document 19"));
+        assert!(!all.contains("Manual"));
+
+        let pdfs = String::from_utf8(body(get("/rss?category=pdf", &[]).await).await).unwrap();
+        assert!(pdfs.contains("Manual"));
+        assert!(!pdfs.contains("Document"));
+    }
+}
@@ -0,0 +1,569 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use flate2::read::DeflateDecoder;
+use log::warn;
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::models::{Category, Location, ReadingItem};
+
+/// `source` of the documents and highlights read from a Readwise export.
+const EXPORT_SOURCE: &str = "Readwise export";
+
+/// Feed URLs (`xmlUrl`) of every RSS `<outline>` in an OPML document, in
+/// document order. Nested folders are flattened.
+pub fn parse_opml(xml: &str) -> Result<Vec<String>> {
+    let mut reader = Reader::from_str(xml);
+    let mut feeds = Vec::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"outline" => {
+                let mut is_rss = false;
+                let mut xml_url = None;
+                for attr in e.attributes() {
+                    let attr = attr?;
+                    match attr.key.as_ref() {
+                        b"type" => is_rss = attr.unescape_value()?.eq_ignore_ascii_case("rss"),
+                        b"xmlUrl" => xml_url = Some(attr.unescape_value()?.into_owned()),
+                        _ => {}
+                    }
+                }
+                if let (true, Some(url)) = (is_rss, xml_url) {
+                    feeds.push(url);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(feeds)
+}
+
+/// A link from a browser bookmark export.
+#[derive(Debug)]
+pub struct Bookmark {
+    pub url: String,
+    pub title: String,
+    /// Enclosing folder names, outermost first.
+    pub folders: Vec<String>,
+}
+
+/// Links of a Netscape-format bookmark file, as exported by Chrome and Firefox.
+///
+/// The format is loose HTML where folders are an `<H3>` heading followed by a
+/// `<DL>` list of entries, so a tolerant tag scanner is enough.
+pub fn parse_bookmarks(html: &str) -> Vec<Bookmark> {
+    let mut bookmarks = Vec::new();
+    // One entry per open `<DL>`; `None` for lists without a heading (the root).
+    let mut folders: Vec<Option<String>> = Vec::new();
+    let mut heading: Option<String> = None;
+
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let name = tag
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match name.as_str() {
+            "h3" => heading = Some(unescape(inner_text(rest))),
+            "dl" => folders.push(heading.take()),
+            "/dl" => {
+                folders.pop();
+            }
+            "a" => {
+                if let Some(href) = attribute(tag, "href") {
+                    bookmarks.push(Bookmark {
+                        url: unescape(href),
+                        title: unescape(inner_text(rest)),
+                        folders: folders.iter().flatten().cloned().collect(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    bookmarks
+}
+
+/// Text up to the next tag.
+fn inner_text(html: &str) -> &str {
+    html[..html.find('<').unwrap_or(html.len())].trim()
+}
+
+/// Value of a double-quoted attribute, matched case-insensitively. The name
+/// must follow whitespace, so that `href` does not match `data-href`.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let needle = format!("{name}=\"");
+    let lowercase = tag.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(found) = lowercase[from..].find(&needle) {
+        let at = from + found;
+        from = at + needle.len();
+        if lowercase[..at].ends_with(|c: char| c.is_ascii_whitespace()) {
+            let len = tag[from..].find('"')?;
+            return Some(&tag[from..from + len]);
+        }
+    }
+    None
+}
+
+fn unescape(text: &str) -> String {
+    quick_xml::escape::unescape(text)
+        .map(|s| s.into_owned())
+        .unwrap_or_else(|_| text.to_string())
+}
+
+/// Documents and highlights of a Readwise data export: a ZIP archive of CSV
+/// files. Two layouts are recognized by their header row:
+///
+/// - Reader library exports (`Title`, `URL`, `ID`, `Saved date`, ...), one
+///   document per row.
+/// - Readwise highlight exports (`Highlight`, `Book Title`, `Book Author`,
+///   `Note`, `Highlighted at`, ...), one highlight per row. Each book or article
+///   becomes a parent document, with an ID derived from its title and author.
+///
+/// Documents come before the highlights pointing at them. Other files are
+/// skipped.
+pub fn parse_readwise_export(archive: &[u8]) -> Result<Vec<ReadingItem>> {
+    let mut documents = Vec::new();
+    let mut parents = BTreeMap::new();
+    let mut highlights = Vec::new();
+    for (name, data) in zip_entries(archive, MAX_EXPORT_FILE_BYTES)? {
+        if !name.to_ascii_lowercase().ends_with(".csv") {
+            continue;
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(&data[..]);
+        let headers = reader.headers()?.clone();
+        let column = |row: &csv::StringRecord, name: &str| {
+            headers
+                .iter()
+                .position(|h| h.trim().eq_ignore_ascii_case(name))
+                .and_then(|i| row.get(i))
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+        };
+        let is_highlights = headers.iter().any(|h| h.eq_ignore_ascii_case("Highlight"));
+        let is_documents = headers.iter().any(|h| h.eq_ignore_ascii_case("URL"));
+        if !is_highlights && !is_documents {
+            warn!("Skipping {name}: neither highlights nor documents");
+            continue;
+        }
+
+        for (n, row) in reader.records().enumerate() {
+            let row = row.with_context(|| format!("Invalid CSV at {name}:{}", n + 2))?;
+            if is_highlights {
+                let Some(text) = column(&row, "Highlight") else {
+                    continue;
+                };
+                let title = column(&row, "Book Title").unwrap_or_else(|| String::from("Untitled"));
+                let author = column(&row, "Book Author");
+                let parent = parents
+                    .entry((title.clone(), author.clone()))
+                    .or_insert_with(|| ReadingItem {
+                        id: export_id(&[&title, author.as_deref().unwrap_or_default()]),
+                        category: Category::Article,
+                        location: Some(Location::Archive),
+                        ..export_item(title.clone(), author.clone())
+                    });
+                let location = column(&row, "Location").unwrap_or_default();
+                let created_at = column(&row, "Highlighted at").and_then(|at| parse_date(&at));
+                highlights.push(ReadingItem {
+                    id: export_id(&[&parent.id, &text, &location]),
+                    category: Category::Highlight,
+                    content: Some(text),
+                    notes: column(&row, "Note"),
+                    parent_id: Some(parent.id.clone()),
+                    tags: column(&row, "Tags").map(|tags| tag_object(&tags)),
+                    created_at: created_at.unwrap_or(DateTime::UNIX_EPOCH),
+                    updated_at: created_at,
+                    ..export_item(title, author)
+                });
+            } else {
+                let Some(url) = column(&row, "URL") else {
+                    continue;
+                };
+                let title = column(&row, "Title").unwrap_or_else(|| url.clone());
+                let created_at = column(&row, "Saved date").and_then(|at| parse_date(&at));
+                documents.push(ReadingItem {
+                    id: column(&row, "ID").unwrap_or_else(|| export_id(&[&url])),
+                    category: Category::Article,
+                    location: column(&row, "Location")
+                        .and_then(|l| serde_json::from_value(json!(l.to_lowercase())).ok()),
+                    reading_progress: column(&row, "Reading progress")
+                        .and_then(|p| parse_progress(&p))
+                        .unwrap_or_default(),
+                    source_url: url.parse().ok(),
+                    tags: column(&row, "Document tags").map(|tags| tag_object(&tags)),
+                    created_at: created_at.unwrap_or(DateTime::UNIX_EPOCH),
+                    updated_at: created_at,
+                    ..export_item(title, None)
+                });
+            }
+        }
+    }
+    documents.extend(parents.into_values());
+    documents.extend(highlights);
+    Ok(documents)
+}
+
+/// A document with the fields an export doesn't have left empty.
+fn export_item(title: String, author: Option<String>) -> ReadingItem {
+    ReadingItem {
+        author,
+        category: Category::Article,
+        content: None,
+        created_at: DateTime::UNIX_EPOCH,
+        id: String::new(),
+        image_url: None,
+        location: None,
+        notes: None,
+        parent_id: None,
+        published_date: None,
+        reading_progress: 0.0,
+        site_name: None,
+        source: Some(String::from(EXPORT_SOURCE)),
+        source_url: None,
+        summary: None,
+        tags: None,
+        title,
+        updated_at: None,
+        readwise_url: None,
+        word_count: 0,
+    }
+}
+
+/// A stable ID for an item the export gives none, so that importing the same
+/// export twice updates the items instead of duplicating them.
+fn export_id(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\0");
+    }
+    let hash = format!("{:x}", hasher.finalize());
+    format!("export-{}", &hash[..26])
+}
+
+/// Comma-separated tag names as the API's object keyed by tag name.
+fn tag_object(tags: &str) -> Value {
+    let tags: Map<String, Value> = tags
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| (tag.to_string(), json!({ "name": tag })))
+        .collect();
+    Value::Object(tags)
+}
+
+/// Dates of Readwise exports, `2024-01-31 12:34:56+00:00`, RFC 3339, or a
+/// bare date or time taken to be UTC.
+fn parse_date(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .or_else(|_| DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%:z"))
+        .map(|date| date.to_utc())
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")
+                .ok()
+                .map(|date| date.and_utc())
+        })
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        })
+}
+
+/// `0.5` or `50%`, clamped into [0, 1].
+fn parse_progress(text: &str) -> Option<f32> {
+    let progress = match text.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f32>().ok()? / 100.0,
+        None => text.parse().ok()?,
+    };
+    Some(progress.clamp(0.0, 1.0))
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Largest file of a Readwise export read, uncompressed.
+const MAX_EXPORT_FILE_BYTES: usize = 512 * 1024 * 1024;
+
+/// Names and contents of the files of a ZIP archive, stored or deflated. ZIP64
+/// and encrypted archives are not supported: Readwise exports are neither.
+///
+/// Files larger than `max_file_bytes` once inflated are an error, whatever
+/// size their header claims.
+fn zip_entries(archive: &[u8], max_file_bytes: usize) -> Result<Vec<(String, Vec<u8>)>> {
+    /// Sizes in headers are not trusted beyond this for preallocating.
+    const MAX_PREALLOCATION: usize = 64 * 1024 * 1024;
+
+    const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+    const DIRECTORY_ENTRY: u32 = 0x0201_4b50;
+    const LOCAL_HEADER: u32 = 0x0403_4b50;
+
+    // The end of central directory record is 22 bytes and a comment of up
+    // to 64 KiB.
+    let end = (0..archive.len().saturating_sub(21))
+        .rev()
+        .take(22 + usize::from(u16::MAX))
+        .find(|&at| read_u32(archive, at) == Some(END_OF_DIRECTORY))
+        .context("Not a ZIP archive")?;
+    let count = read_u16(archive, end + 10).context("Truncated ZIP archive")?;
+    let mut at = read_u32(archive, end + 16).context("Truncated ZIP archive")? as usize;
+
+    let mut entries = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        let field = |offset: usize, size: usize| {
+            let value = match size {
+                2 => read_u16(archive, at + offset).map(u32::from),
+                _ => read_u32(archive, at + offset),
+            };
+            value.context("Truncated ZIP central directory")
+        };
+        if field(0, 4)? != DIRECTORY_ENTRY {
+            bail!("Corrupted ZIP central directory");
+        }
+        let flags = field(8, 2)?;
+        let method = field(10, 2)?;
+        let compressed_size = field(20, 4)?;
+        let size = field(24, 4)?;
+        let name_len = field(28, 2)? as usize;
+        let extra_len = field(30, 2)? as usize;
+        let comment_len = field(32, 2)? as usize;
+        let local = field(42, 4)? as usize;
+        let name = archive
+            .get(at + 46..at + 46 + name_len)
+            .context("Truncated ZIP central directory")?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        at += 46 + name_len + extra_len + comment_len;
+
+        if name.ends_with('/') {
+            continue;
+        }
+        if flags & 1 != 0 {
+            bail!("{name} is encrypted");
+        }
+        if compressed_size == u32::MAX || size == u32::MAX {
+            bail!("{name} is stored in the unsupported ZIP64 format");
+        }
+        if read_u32(archive, local) != Some(LOCAL_HEADER) {
+            bail!("Corrupted ZIP entry {name}");
+        }
+        let header_len = 30
+            + read_u16(archive, local + 26).context("Truncated ZIP entry")? as usize
+            + read_u16(archive, local + 28).context("Truncated ZIP entry")? as usize;
+        let start = local + header_len;
+        let compressed = archive
+            .get(start..start + compressed_size as usize)
+            .with_context(|| format!("Truncated ZIP entry {name}"))?;
+        let data = match method {
+            0 => compressed.to_vec(),
+            8 => {
+                let mut data = Vec::with_capacity((size as usize).min(MAX_PREALLOCATION));
+                DeflateDecoder::new(compressed)
+                    .take(max_file_bytes as u64 + 1)
+                    .read_to_end(&mut data)
+                    .with_context(|| format!("Failed to inflate {name}"))?;
+                data
+            }
+            method => bail!("{name} uses the unsupported compression method {method}"),
+        };
+        if data.len() > max_file_bytes {
+            bail!("{name} is larger than {max_file_bytes} bytes");
+        }
+        entries.push((name, data));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use chrono::TimeZone;
+    use flate2::Compression;
+    use flate2::write::DeflateEncoder;
+
+    use super::*;
+
+    /// A ZIP archive of `files`, deflated.
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut directory = Vec::new();
+        for (name, content) in files {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(content.as_bytes()).unwrap();
+            let data = encoder.finish().unwrap();
+            let sizes = [data.len() as u32, content.len() as u32];
+            let offset = archive.len() as u32;
+
+            archive.extend(0x0403_4b50u32.to_le_bytes());
+            archive.extend([20, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            archive.extend(sizes.iter().flat_map(|size| size.to_le_bytes()));
+            archive.extend((name.len() as u16).to_le_bytes());
+            archive.extend([0, 0]);
+            archive.extend(name.as_bytes());
+            archive.extend(&data);
+
+            directory.extend(0x0201_4b50u32.to_le_bytes());
+            directory.extend([20, 0, 20, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            directory.extend(sizes.iter().flat_map(|size| size.to_le_bytes()));
+            directory.extend((name.len() as u16).to_le_bytes());
+            directory.extend([0; 12]);
+            directory.extend(offset.to_le_bytes());
+            directory.extend(name.as_bytes());
+        }
+        let directory_offset = archive.len() as u32;
+        archive.extend(&directory);
+        archive.extend(0x0605_4b50u32.to_le_bytes());
+        archive.extend([0, 0, 0, 0]);
+        archive.extend([files.len() as u16; 2].iter().flat_map(|n| n.to_le_bytes()));
+        archive.extend((directory.len() as u32).to_le_bytes());
+        archive.extend(directory_offset.to_le_bytes());
+        archive.extend([0, 0]);
+        archive
+    }
+
+    #[test]
+    fn rss_outlines_of_opml_files_are_feeds() {
+        let feeds = parse_opml(include_str!("../fixtures/feeds.opml")).unwrap();
+        assert_eq!(
+            feeds,
+            [
+                "https://example.com/feed.xml",
+                "https://example.org/rss?format=xml&lang=en",
+                "https://example.net/feed",
+            ]
+        );
+    }
+
+    #[test]
+    fn bookmarks_keep_their_folders() {
+        let bookmarks = parse_bookmarks(include_str!("../fixtures/bookmarks.html"));
+        let summary: Vec<_> = bookmarks
+            .iter()
+            .map(|b| (b.url.as_str(), b.title.as_str(), b.folders.join("/")))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "https://example.com/earthsea",
+                    "A Wizard of Earthsea",
+                    String::from("Bookmarks bar")
+                ),
+                (
+                    "https://example.org/essay?a=1&b=2",
+                    "Essays & more",
+                    String::from("Bookmarks bar/Reading & Writing")
+                ),
+                ("https://example.net/", "Lowercase", String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn attributes_are_matched_whole() {
+        let tag = r#"A DATA-HREF="https://wrong.example/" HREF="https://right.example/""#;
+        assert_eq!(attribute(tag, "href"), Some("https://right.example/"));
+        assert_eq!(
+            attribute(r#"A data-href="https://wrong.example/""#, "href"),
+            None
+        );
+        assert_eq!(attribute(r#"A HREF="unterminated"#, "href"), None);
+    }
+
+    #[test]
+    fn readwise_exports_are_read_from_zip_archives() {
+        let highlights = "Highlight,Book Title,Book Author,Amazon Book ID,Note,Color,Tags,Location Type,Location,Highlighted at,Document tags\n\
+            First passage,Dune,Frank Herbert,,A note,yellow,\"sf, classics\",location,12,2024-01-31 12:00:00+00:00,\n\
+            Second passage,Dune,Frank Herbert,,,yellow,,location,40,2024-02-01 08:30:00+00:00,\n";
+        let documents = "Title,URL,ID,Document tags,Saved date,Reading progress,Location,Seen\n\
+            Aggregation Theory,https://stratechery.com/aggregation,01abc,,2024-01-10 09:00:00+00:00,50%,later,true\n";
+        let archive = zip(&[
+            ("readwise-data.csv", highlights),
+            ("reader/documents.csv", documents),
+            ("README.txt", "Not a CSV"),
+        ]);
+
+        let items = parse_readwise_export(&archive).unwrap();
+        let categories: Vec<_> = items.iter().map(|item| item.category).collect();
+        assert_eq!(
+            categories,
+            [
+                Category::Article,
+                Category::Article,
+                Category::Highlight,
+                Category::Highlight
+            ]
+        );
+
+        let document = &items[0];
+        assert_eq!(document.id, "01abc");
+        assert_eq!(document.location, Some(Location::Later));
+        assert_eq!(document.reading_progress, 0.5);
+        assert_eq!(
+            document.created_at,
+            Utc.with_ymd_and_hms(2024, 1, 10, 9, 0, 0).unwrap()
+        );
+
+        let (book, first) = (&items[1], &items[2]);
+        assert_eq!(book.title, "Dune");
+        assert_eq!(book.author.as_deref(), Some("Frank Herbert"));
+        assert_eq!(first.parent_id.as_ref(), Some(&book.id));
+        assert_eq!(items[3].parent_id.as_ref(), Some(&book.id));
+        assert_eq!(first.content.as_deref(), Some("First passage"));
+        assert_eq!(first.notes.as_deref(), Some("A note"));
+        assert_eq!(
+            first.tags,
+            Some(json!({"sf": {"name": "sf"}, "classics": {"name": "classics"}}))
+        );
+        assert!(first.validate().is_empty());
+
+        // Importing the same export again updates the same items.
+        let again = parse_readwise_export(&archive).unwrap();
+        assert_eq!(again, items);
+    }
+
+    #[test]
+    fn zip_files_are_inflated_up_to_the_limit() {
+        let content = "0".repeat(1000);
+        let mut archive = zip(&[("large.csv", &content)]);
+        assert_eq!(zip_entries(&archive, 1000).unwrap()[0].1.len(), 1000);
+        let err = zip_entries(&archive, 999).unwrap_err();
+        assert_eq!(err.to_string(), "large.csv is larger than 999 bytes");
+
+        // A size header claiming 4 GiB is not trusted.
+        let directory = read_u32(&archive, archive.len() - 6).unwrap() as usize;
+        archive[directory + 24..directory + 28].copy_from_slice(&(u32::MAX - 1).to_le_bytes());
+        assert_eq!(
+            zip_entries(&archive, 1000).unwrap()[0].1,
+            content.as_bytes()
+        );
+    }
+}
@@ -0,0 +1,18 @@
+//! Library side of `reader-sync`: the Readwise API client, database access and
+//! models used by the CLI in `main.rs`.
+
+pub mod api;
+pub mod bench;
+pub mod config;
+pub mod convert;
+pub mod db;
+pub mod error;
+pub mod feed;
+pub mod import;
+pub mod models;
+pub mod notify;
+pub mod storage;
+pub mod sync;
+#[cfg(any(test, feature = "test-helpers"))]
+pub mod testing;
+pub mod webhook;
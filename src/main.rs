@@ -1,89 +1,1941 @@
-mod api;
-mod db;
-mod models;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use chrono::Utc;
-use clap::Parser;
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use futures::TryStreamExt;
+use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, error, info, warn};
-use sqlx::postgres::PgPool;
+use reader_sync::config::Config;
+use reader_sync::error::SyncError;
+use reader_sync::models::{Category, DbRecord, ItemId, Location, WORDS_PER_MINUTE};
+use reader_sync::storage::{
+    DEFAULT_MAX_ROTATIONS, JsonFileBackend, PostgresBackend, StorageBackend, StorageKind,
+};
+use reader_sync::{api, bench, convert, db, feed, import, notify, sync, webhook};
+use sqlx::postgres::{PgConnectOptions, PgSslMode};
+
+/// Exit code for a sync that stopped early but saved its progress.
+const EXIT_PARTIAL: u8 = 2;
+
+/// Exit code for a rejected access token (`EX_CONFIG` of sysexits.h).
+const EXIT_CONFIG: u8 = 78;
+
+/// Default of `--interval`.
+const DEFAULT_INTERVAL_SECS: u64 = 3600;
 
 #[derive(Parser)]
-#[command(about = "Sync Readwise Reader documents to PostgreSQL")]
+#[command(version, about = "Sync Readwise Reader documents to PostgreSQL")]
+#[command(group = ArgGroup::new("schedule").args(["daemon", "per_category_interval"]))]
 struct Args {
-    /// Bypass the checkpoint and re-sync everything from the beginning
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// TOML configuration file [default: $XDG_CONFIG_HOME/readwise-reader-sync/config.toml]
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Log level, overriding RUST_LOG and `log_level` in the config file
+    #[arg(long, value_parser = ["trace", "debug", "info", "warn", "error"])]
+    log_level: Option<String>,
+
+    /// One JSON object per log line, for log aggregation
+    #[arg(long, value_enum, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Clear the checkpoint and re-sync everything from the beginning
     #[arg(long, default_value_t = false)]
     full_sync: bool,
+
+    /// Give up on an API request after this many consecutive failed attempts,
+    /// or twice as many consecutive rate-limited (429) responses [default: 10]
+    #[arg(long)]
+    max_retries: Option<u32>,
+
+    /// Fail on API responses larger than this many MiB [default: 50]
+    #[arg(long = "max-response-mb")]
+    max_response_mb: Option<usize>,
+
+    /// Retry API requests that receive no data for this many seconds, e.g. on
+    /// connections dropped by a firewall during long syncs
+    #[arg(long, value_name = "N")]
+    http_read_timeout_secs: Option<u64>,
+
+    /// Idle HTTP connections kept open for reuse [default: 100]
+    #[arg(long, value_name = "N")]
+    http_pool_max_idle: Option<usize>,
+
+    /// Idle connections to the Readwise API kept open for reuse, best at least --workers
+    /// [default: 10]. More workers make more concurrent requests, and more 429 responses
+    /// once past Readwise's rate limit
+    #[arg(long, value_name = "N")]
+    http_pool_max_idle_per_host: Option<usize>,
+
+    /// Fail when the API returns document fields unknown to this version, instead of warning
+    /// about them once and ignoring them
+    #[arg(long, default_value_t = false)]
+    strict_api: bool,
+
+    /// Stop after this many seconds, saving the page cursor so the next run resumes from it
+    #[arg(long)]
+    sync_timeout_secs: Option<u64>,
+
+    /// Only save documents with at least this reading progress (0.0 to 1.0)
+    #[arg(long, value_parser = parse_fraction)]
+    reading_progress_min: Option<f32>,
+
+    /// Only save documents with at most this reading progress (0.0 to 1.0)
+    #[arg(long, value_parser = parse_fraction)]
+    reading_progress_max: Option<f32>,
+
+    /// Only save documents of at least N words
+    #[arg(long, value_name = "N")]
+    min_word_count: Option<i32>,
+
+    /// Only save documents of at most N words
+    #[arg(long, value_name = "N")]
+    max_word_count: Option<i32>,
+
+    /// Let documents of unknown length (a word count of 0) pass --min-word-count
+    #[arg(long, default_value_t = false)]
+    include_zero_wordcount: bool,
+
+    /// Don't save documents with suspicious values (see `ReadingItem::validate`), count them as failed
+    #[arg(long, default_value_t = false)]
+    strict_validation: bool,
+
+    /// Also push documents edited in the database to Readwise, before pulling
+    #[arg(long, default_value_t = false)]
+    two_way: bool,
+
+    /// With --two-way, which edit wins when a document changed on both sides
+    #[arg(long, value_enum, default_value = "newest", requires = "two_way")]
+    conflict_resolution: sync::ConflictResolution,
+
+    /// Record every field changed by the sync in the `sync_audit` table (see `show-history`)
+    #[arg(long, default_value_t = false)]
+    audit_log: bool,
+
+    /// Replace documents already in the database, or keep the first version saved
+    #[arg(long, value_enum, default_value = "upsert", conflicts_with = "output")]
+    save_mode: db::SaveMode,
+
+    /// Store the content of documents as plain text, stripped of its HTML
+    #[arg(long, default_value_t = false, conflicts_with = "output")]
+    compact_content: bool,
+
+    /// Where to save synced documents
+    #[arg(long, value_enum, default_value = "postgres")]
+    storage_backend: StorageKind,
+
+    /// With --storage-backend json, the JSON Lines file to append documents to
+    #[arg(long, value_name = "PATH", required_if_eq("storage_backend", "json"))]
+    output: Option<PathBuf>,
+
+    /// Rotate the --output file to `<PATH>.1` once it grows past N MiB
+    #[arg(long, value_name = "N", requires = "output")]
+    max_file_size_mb: Option<u64>,
+
+    /// Keep at most N rotations of the --output file, deleting the oldest
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_MAX_ROTATIONS,
+        requires = "max_file_size_mb"
+    )]
+    max_rotated_files: usize,
+
+    /// After syncing, hard-delete documents soft-deleted more than this many days ago
+    #[arg(
+        long,
+        value_name = "DAYS",
+        conflicts_with_all = ["output", "per_category_interval"]
+    )]
+    purge_deleted_older_than: Option<u32>,
+
+    /// After syncing, delete `sync_audit` entries recorded more than this many days ago
+    #[arg(
+        long,
+        value_name = "DAYS",
+        conflicts_with_all = ["output", "per_category_interval"]
+    )]
+    prune_sync_audit_older_than: Option<u32>,
+
+    /// After syncing, archive documents of the inbox saved more than this many days ago.
+    /// The next --two-way sync pushes the moves to Readwise
+    #[arg(
+        long,
+        value_name = "DAYS",
+        conflicts_with_all = ["output", "per_category_interval"],
+        requires = "two_way"
+    )]
+    auto_archive_after_days: Option<u32>,
+
+    /// After syncing, run VACUUM ANALYZE on the `reading` table
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["output", "per_category_interval"]
+    )]
+    vacuum_after_sync: bool,
+
+    /// Keep running, syncing these categories at their own interval in
+    /// seconds, e.g. `rss=300,article=3600`
+    #[arg(
+        long,
+        value_name = "CATEGORY=SECS",
+        value_delimiter = ',',
+        value_parser = parse_category_interval,
+        conflicts_with = "output"
+    )]
+    per_category_interval: Vec<(Category, u64)>,
+
+    /// Keep running, syncing every --interval seconds until SIGTERM or SIGINT
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with = "per_category_interval"
+    )]
+    daemon: bool,
+
+    /// Seconds between syncs with --daemon, or between syncs of every category
+    /// with --per-category-interval [default: 3600]
+    #[arg(long, value_name = "SECS", requires = "schedule")]
+    interval: Option<u64>,
+
+    /// With --daemon, write the process ID to this file, removed on exit
+    #[arg(long, value_name = "PATH", requires = "daemon")]
+    pid_file: Option<PathBuf>,
+
+    /// Print a JSON summary of the sync on stdout when it ends
+    #[arg(long, default_value_t = false)]
+    output_stats: bool,
+
+    /// Format of the report printed on stderr after each sync
+    #[arg(long, value_enum, default_value = "text")]
+    output_format: OutputFormat,
+
+    /// Don't print a report after each sync
+    #[arg(long, default_value_t = false)]
+    quiet: bool,
+
+    /// Show a desktop notification when a sync saves new documents
+    #[arg(long, default_value_t = false)]
+    desktop_notify: bool,
+
+    /// With --desktop-notify, also notify after syncs that found nothing new
+    #[arg(long, default_value_t = false, requires = "desktop_notify")]
+    notify_on_empty: bool,
+
+    /// Instead of syncing, measure insert throughput using N pages of documents
+    #[arg(long, value_name = "N")]
+    benchmark: Option<usize>,
+
+    /// Read benchmark pages from saved API responses in this directory instead of the API
+    #[arg(long, value_name = "DIR", requires = "benchmark")]
+    benchmark_fixtures: Option<PathBuf>,
+
+    /// Number of runtime threads, and of documents saved concurrently [default: 1]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    workers: Option<u32>,
+
+    /// Maximum number of database connections [default: 4 per worker]
+    #[arg(long)]
+    db_max_connections: Option<u32>,
+
+    /// Number of idle database connections to keep open
+    #[arg(long, default_value_t = 0)]
+    db_min_connections: u32,
+
+    /// Seconds to wait for a free database connection before failing
+    #[arg(long, default_value_t = 30)]
+    db_acquire_timeout_secs: u64,
+
+    /// Cancel any single database statement running longer than this many seconds
+    #[arg(long)]
+    db_statement_timeout_secs: Option<u32>,
+
+    /// Connection URL of a read replica to run queries against (writes still go to DATABASE_URL)
+    #[arg(long)]
+    db_read_url: Option<String>,
+
+    /// TLS mode for database connections: disable, allow, prefer, require, verify-ca or
+    /// verify-full [default: prefer, unless set in the connection URL]
+    #[arg(long)]
+    db_sslmode: Option<PgSslMode>,
+
+    /// CA certificate used to verify the database server in verify-ca/verify-full modes
+    #[arg(long, value_name = "PATH")]
+    db_ssl_root_cert: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Commands other than syncing, which is what runs when none is given.
+#[derive(Subcommand)]
+enum Command {
+    /// Seed the database from a JSON Lines file of documents (see `fixtures/`)
+    LoadFixtures { path: PathBuf },
+
+    /// Seed the database from a Readwise data export (a ZIP archive of CSV
+    /// files), without calling the API
+    ImportReadwiseExport { zip: PathBuf },
+
+    /// Subscribe to the RSS feeds of an OPML file in Readwise Reader
+    ImportOpml { file: PathBuf },
+
+    /// Save the links of a Chrome/Firefox HTML bookmark export to Readwise Reader
+    ///
+    /// Bookmark folders are added as tags.
+    ImportBookmarks {
+        file: PathBuf,
+
+        #[arg(long, value_enum, default_value = "later")]
+        location: Location,
+    },
+
+    /// Run an HTTP server that syncs on `POST /sync` and reports on `GET /health`
+    ServeWebhook {
+        port: u16,
+
+        /// Require `Authorization: Bearer <token>` on incoming requests
+        #[arg(long)]
+        webhook_auth_token: Option<String>,
+    },
+
+    /// Run an HTTP server publishing the latest documents as RSS 2.0 (`/rss`)
+    /// and Atom 1.0 (`/atom`), filtered by `?category=` and `?location=`
+    ServeRss {
+        port: u16,
+
+        /// Number of documents in a feed
+        #[arg(long, default_value_t = 50)]
+        max_items: usize,
+
+        #[arg(long, default_value = "Readwise Reader")]
+        feed_title: String,
+    },
+
+    /// Delete a document from Readwise Reader, then from the database
+    DeleteRemote { id: String },
+
+    /// Fetch the highlights of a document from Readwise Reader and save them,
+    /// without syncing anything else
+    SyncHighlights { id: String },
+
+    /// Add a tag to a document in Readwise Reader and in the database
+    AddTag { id: String, tag: String },
+
+    /// Remove a tag from a document in Readwise Reader and in the database
+    RemoveTag { id: String, tag: String },
+
+    /// Print the changes recorded for a document by syncs run with --audit-log
+    ShowHistory { id: String },
+
+    /// Chart the reading progress of a document over time, as seen by syncs
+    ShowProgress { id: String },
+
+    /// Print the reading profile of an author (case-insensitive)
+    ShowAuthor { name: String },
+
+    /// Print the effective configuration as TOML, with secrets masked
+    DumpConfig,
+
+    /// Print the version and build metadata, for bug reports
+    Version {
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+
+    /// Export documents from the database
+    #[command(subcommand)]
+    Export(ExportCommand),
+
+    /// Inspect the database
+    #[command(subcommand)]
+    Check(CheckCommand),
+
+    /// Print documents from the database
+    #[command(subcommand)]
+    Show(ShowCommand),
+
+    /// Database upkeep
+    #[command(subcommand)]
+    Maintenance(MaintenanceCommand),
+
+    /// Print word counts, reading progress and document counts per category
+    Stats {
+        #[command(subcommand)]
+        command: Option<StatsCommand>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ShowCommand {
+    /// A document, with the time left to finish reading it
+    Item { id: String },
+
+    /// Highlights, most recent first
+    Highlights {
+        /// Print at most N highlights
+        #[arg(long, value_name = "N", default_value_t = 20)]
+        limit: i64,
+
+        /// Only highlights of documents by this author, oldest first
+        #[arg(long, conflicts_with = "with_notes")]
+        author: Option<String>,
+
+        /// Only highlights carrying a note, oldest first
+        #[arg(long, default_value_t = false)]
+        with_notes: bool,
+    },
+
+    /// Categories found in the database, one per line, for --category
+    Categories,
+
+    /// Locations found in the database, one per line, for --location
+    Locations,
+
+    /// Authors found in the database, one per line
+    Authors,
+}
+
+#[derive(Subcommand)]
+enum StatsCommand {
+    /// Authors with the most documents
+    Authors(TopArgs),
+
+    /// Sites with the most documents
+    Sites {
+        #[command(flatten)]
+        top: TopArgs,
+
+        /// Only sites with at least this many documents, with their
+        /// categories; other filters and orderings are ignored
+        #[arg(long)]
+        min_articles: Option<i64>,
+    },
+
+    /// Documents per source: RSS feed, email newsletter, browser extension…
+    Sources,
+
+    /// Histogram of reading progress, and the share of documents read to the end
+    Distribution {
+        #[arg(long, default_value_t = 10, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=100))]
+        buckets: usize,
+    },
+
+    /// Words read in documents updated today, in the local time zone
+    Today,
+
+    /// Words read in documents updated this week, since Monday
+    Week,
+
+    /// Words read in documents updated this month
+    Month,
+
+    /// Disk usage of the `reading` table, and when it was last vacuumed
+    Storage,
+
+    /// Documents saved per day, week or month and category
+    Timeline {
+        #[arg(long, value_enum, default_value = "month")]
+        granularity: db::Granularity,
+
+        /// Only count documents saved on or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_parser = parse_date)]
+        since: Option<DateTime<Utc>>,
+
+        /// Draw a bar for each count
+        #[arg(long, default_value_t = false)]
+        chart: bool,
+    },
+
+    /// Tags most often found together with a tag
+    TagPairs {
+        #[arg(long)]
+        tag: String,
+
+        /// Only count the items tagged with both tags
+        #[arg(long)]
+        with: Option<String>,
+
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<()> {
-    env_logger::init();
+#[derive(clap::Args)]
+struct TopArgs {
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+
+    #[arg(long, value_enum, default_value = "count")]
+    sort_by: db::StatsOrder,
+
+    /// Only count documents saved on or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only count documents saved before this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    until: Option<DateTime<Utc>>,
+}
+
+#[derive(Subcommand)]
+enum CheckCommand {
+    /// Look for orphaned highlights, duplicate documents and invalid values
+    Integrity {
+        /// Merge duplicates, delete orphaned highlights, clamp progress values
+        /// and clear malformed source URLs
+        #[arg(long, default_value_t = false)]
+        fix: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum MaintenanceCommand {
+    /// Rebuild the indexes of the `reading` table
+    Reindex,
+
+    /// Print the plan of a query with EXPLAIN ANALYZE, which runs the query
+    Explain {
+        /// stream_all, top_authors, top_sites or progress_distribution
+        query: String,
+
+        /// Confirm running the query, which may be slow on a large database
+        #[arg(long, default_value_t = false)]
+        dangerous: bool,
+
+        #[arg(long)]
+        limit: Option<i64>,
+
+        #[arg(long, value_enum)]
+        category: Option<Category>,
+
+        #[arg(long, value_enum)]
+        location: Option<Location>,
+
+        /// Only documents saved on or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_parser = parse_date)]
+        since: Option<DateTime<Utc>>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ExportCommand {
+    /// Write documents as a JSON Lines fixture file, loadable with `load-fixtures`
+    Fixtures {
+        path: PathBuf,
+
+        /// Maximum number of documents to export
+        #[arg(long)]
+        limit: Option<i64>,
+
+        #[arg(long, value_enum)]
+        category: Option<Category>,
+
+        #[arg(long, value_enum)]
+        location: Option<Location>,
+
+        /// Keep the document content (stripped by default to keep fixtures small)
+        #[arg(long, default_value_t = false)]
+        with_content: bool,
+    },
+
+    /// Write documents as JSON Lines, oldest first, with the Readwise API field names
+    Jsonl {
+        path: PathBuf,
+
+        /// Fields to write [default: every field]
+        #[arg(long, value_delimiter = ',')]
+        fields: Vec<String>,
+
+        /// Leave out the `content` field, usually the largest
+        #[arg(long, default_value_t = false)]
+        jsonl_no_content: bool,
+
+        /// One object per line (the default)
+        #[arg(long, default_value_t = false, conflicts_with = "jsonl_pretty")]
+        jsonl_compact: bool,
+
+        /// Indent objects over several lines
+        #[arg(long, default_value_t = false)]
+        jsonl_pretty: bool,
+
+        /// Maximum number of documents to export
+        #[arg(long)]
+        limit: Option<i64>,
+
+        #[arg(long, value_enum)]
+        category: Option<Category>,
+
+        #[arg(long, value_enum)]
+        location: Option<Location>,
+    },
 
+    /// Write documents as a Pocket HTML export, for services importing those
+    Pocket {
+        path: PathBuf,
+
+        /// Maximum number of documents to export
+        #[arg(long)]
+        limit: Option<i64>,
+
+        #[arg(long, value_enum)]
+        category: Option<Category>,
+
+        #[arg(long, value_enum)]
+        location: Option<Location>,
+    },
+
+    /// Write documents as CSV, oldest first
+    Csv {
+        path: PathBuf,
+
+        /// Columns to write, in this order [default: id,title,author,category,location,reading_progress,word_count,source_url,created_at]
+        #[arg(long, value_enum, value_delimiter = ',')]
+        columns: Vec<db::CsvColumn>,
+
+        /// Don't write the header row
+        #[arg(long, default_value_t = false)]
+        csv_no_headers: bool,
+
+        #[arg(long, value_name = "CHAR", default_value_t = ',')]
+        csv_delimiter: char,
+
+        /// Decimal separator of reading_progress
+        #[arg(long, value_name = "CHAR", default_value_t = '.')]
+        csv_decimal_separator: char,
+
+        /// Separator of the thousands of word_count, none by default
+        #[arg(long, value_name = "CHAR")]
+        csv_thousands_separator: Option<char>,
+
+        /// strftime-like format of the dates, in UTC
+        #[arg(long, value_name = "FORMAT", default_value = "%Y-%m-%d")]
+        csv_date_format: String,
+
+        /// Maximum number of documents to export
+        #[arg(long)]
+        limit: Option<i64>,
+
+        #[arg(long, value_enum)]
+        category: Option<Category>,
+
+        #[arg(long, value_enum)]
+        location: Option<Location>,
+    },
+}
+
+fn main() -> Result<ExitCode> {
     let args = Args::parse();
 
+    if let Some(Command::Version { json }) = args.command {
+        print_version(json);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let config = effective_config(&args)?;
+
+    if let Some(Command::DumpConfig) = args.command {
+        dump_config(config)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    init_logger(config.log_level.as_deref(), args.log_format);
+
+    // A single worker keeps the historical single-threaded runtime.
+    let runtime = if config.workers() > 1 {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(config.workers() as usize)
+            .enable_all()
+            .build()?
+    } else {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+    };
+
+    match runtime.block_on(run(args, config)) {
+        Err(e)
+            if e.downcast_ref::<SyncError>()
+                .is_some_and(SyncError::is_configuration_error) =>
+        {
+            error!("{e}");
+            Ok(ExitCode::from(EXIT_CONFIG))
+        }
+        result => result,
+    }
+}
+
+/// Settings of the config file, overridden by the environment, overridden by
+/// command-line flags.
+fn effective_config(args: &Args) -> Result<Config> {
+    let mut config = Config::load(args.config.as_deref())?;
+    config.log_level = args.log_level.clone().or(config.log_level);
+    config.db_read_url = args.db_read_url.clone().or(config.db_read_url);
+    config.max_retries = args.max_retries.or(config.max_retries);
+    config.max_response_mb = args.max_response_mb.or(config.max_response_mb);
+    config.workers = args.workers.or(config.workers);
+    config.sync_timeout_secs = args.sync_timeout_secs.or(config.sync_timeout_secs);
+    config.http_read_timeout_secs = args
+        .http_read_timeout_secs
+        .or(config.http_read_timeout_secs);
+    config.http_pool_max_idle = args.http_pool_max_idle.or(config.http_pool_max_idle);
+    config.http_pool_max_idle_per_host = args
+        .http_pool_max_idle_per_host
+        .or(config.http_pool_max_idle_per_host);
+    config.db_max_connections = args.db_max_connections.or(config.db_max_connections);
+    config.db_statement_timeout_secs = args
+        .db_statement_timeout_secs
+        .or(config.db_statement_timeout_secs);
+    if args.strict_api {
+        config.strict_api = Some(true);
+    }
+    Ok(config)
+}
+
+fn init_logger(filters: Option<&str>, format: LogFormat) {
+    let mut logger = match filters {
+        Some(filters) => {
+            let mut logger = env_logger::Builder::new();
+            logger.parse_filters(filters);
+            logger
+        }
+        None => env_logger::Builder::from_default_env(),
+    };
+    if format == LogFormat::Json {
+        logger.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{line}")
+        });
+    }
+    logger.init();
+}
+
+fn print_version(json: bool) {
+    let built_at = env!("BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0));
+    if json {
+        let version = serde_json::json!({
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "git_commit": env!("BUILD_GIT_COMMIT"),
+            "built_at": built_at,
+            "rustc": env!("BUILD_RUSTC_VERSION"),
+        });
+        println!("{version}");
+    } else {
+        println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        println!("commit:   {}", env!("BUILD_GIT_COMMIT"));
+        if let Some(built_at) = built_at {
+            println!("built at: {built_at}");
+        }
+        println!("rustc:    {}", env!("BUILD_RUSTC_VERSION"));
+    }
+}
+
+fn dump_config(mut config: Config) -> Result<()> {
+    let defaults = config.fill_defaults();
+    println!("# [defaults] not set by the config file, environment or flags:");
+    for line in toml::to_string_pretty(&defaults)?.lines() {
+        println!("# {line}");
+    }
+    println!();
+    print!("{}", toml::to_string_pretty(&config.redacted())?);
+    Ok(())
+}
+
+async fn run(args: Args, config: Config) -> Result<ExitCode> {
+    if args.command.is_none()
+        && args.benchmark.is_none()
+        && args.storage_backend == StorageKind::Json
+    {
+        let path = args.output.as_deref().context("--output is required")?;
+        let max_file_bytes = args.max_file_size_mb.map(|mb| mb * 1024 * 1024);
+        let storage: Arc<dyn StorageBackend> = Arc::new(
+            JsonFileBackend::open(path, max_file_bytes)?.with_max_rotations(args.max_rotated_files),
+        );
+        if args.daemon {
+            run_daemon(&storage, None, &args, &config).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        let options = sync_options(&args, &config);
+        let result = sync_to(&storage, None, &options, &args, &config, None).await?;
+        return Ok(exit_code(&result));
+    }
+
     info!("Connecting to database...");
-    let pool = PgPool::connect(&dotenvy::var("DATABASE_URL")?).await?;
+    let pools = db::DbPools::connect(
+        db::connection_pool_config(
+            config.db_max_connections(),
+            args.db_min_connections,
+            args.db_acquire_timeout_secs,
+        ),
+        connect_options(&args, config.database_url()?)?,
+        config
+            .db_read_url
+            .as_deref()
+            .map(|url| connect_options(&args, url))
+            .transpose()?,
+    )
+    .await?;
 
     info!("Running migrations...");
-    sqlx::migrate!().run(&pool).await?;
+    let migrations = db::migrate_and_verify(&pools.write).await?;
+    anyhow::ensure!(
+        migrations.pending.is_empty(),
+        "Migrations not applied at schema version {}: {}",
+        migrations.current_version,
+        migrations.pending.join(", ")
+    );
 
-    let access_token = &dotenvy::var("READWISE_ACCESS_TOKEN")?;
+    if let Some(command) = &args.command {
+        match command {
+            Command::DumpConfig | Command::Version { .. } => {
+                unreachable!("handled before connecting")
+            }
+            Command::LoadFixtures { path } => {
+                let loaded = db::load_fixtures(&pools, path, config.batch_chunk_size()).await?;
+                info!("Loaded {loaded} documents from {}", path.display());
+            }
+            Command::ImportReadwiseExport { zip } => {
+                let archive = std::fs::read(zip)
+                    .with_context(|| format!("Failed to read {}", zip.display()))?;
+                let records: Vec<_> = import::parse_readwise_export(&archive)
+                    .with_context(|| format!("Invalid Readwise export {}", zip.display()))?
+                    .into_iter()
+                    .map(DbRecord::from)
+                    .collect();
+                db::save_batch(&pools.write, &records, config.batch_chunk_size()).await?;
+                info!(
+                    "Imported {} documents and highlights from {}",
+                    records.len(),
+                    zip.display()
+                );
+            }
+            Command::ImportOpml { file } => {
+                let xml = std::fs::read_to_string(file)
+                    .with_context(|| format!("Failed to read {}", file.display()))?;
+                let feeds = import::parse_opml(&xml)?;
+                let client = api_client(&config)?;
 
-    let updated_after = if args.full_sync {
-        info!("Full sync requested — ignoring checkpoint.");
-        None
-    } else {
-        match db::load_checkpoint(&pool).await? {
-            Some(ts) => {
-                info!("Resuming from checkpoint: {ts}");
-                Some(ts)
+                let progress = progress_bar(feeds.len());
+                let (mut added, mut skipped) = (0usize, 0usize);
+                for url in feeds {
+                    progress.inc(1);
+                    if db::find_by_source_url(&pools, &url).await?.is_some() {
+                        skipped += 1;
+                        continue;
+                    }
+                    let opts = api::CreateItemOptions {
+                        url,
+                        category: Some(Category::Rss),
+                        ..Default::default()
+                    };
+                    api::create_item(&client, &opts)?;
+                    added += 1;
+                }
+                progress.finish_and_clear();
+                info!("Imported {added} feeds, skipped {skipped} already present");
+            }
+            Command::ImportBookmarks { file, location } => {
+                let html = std::fs::read_to_string(file)
+                    .with_context(|| format!("Failed to read {}", file.display()))?;
+                let bookmarks: Vec<_> = import::parse_bookmarks(&html)
+                    .into_iter()
+                    .filter(|b| b.url.starts_with("http://") || b.url.starts_with("https://"))
+                    .collect();
+                let client = api_client(&config)?;
+
+                let progress = progress_bar(bookmarks.len());
+                let (mut added, mut skipped) = (0usize, 0usize);
+                for bookmark in bookmarks {
+                    progress.inc(1);
+                    if db::find_by_source_url(&pools, &bookmark.url)
+                        .await?
+                        .is_some()
+                    {
+                        skipped += 1;
+                        continue;
+                    }
+                    let opts = api::CreateItemOptions {
+                        url: bookmark.url,
+                        title: Some(bookmark.title).filter(|t| !t.is_empty()),
+                        location: Some(*location),
+                        tags: bookmark.folders,
+                        ..Default::default()
+                    };
+                    api::create_item(&client, &opts)?;
+                    added += 1;
+                }
+                progress.finish_and_clear();
+                info!("Imported {added} bookmarks, skipped {skipped} already present");
+            }
+            Command::ServeWebhook {
+                port,
+                webhook_auth_token,
+            } => {
+                let state = webhook::WebhookState {
+                    storage: storage(&pools, &args, &config),
+                    client: api_client(&config)?,
+                    options: sync_options(&args, &config),
+                    auth_token: webhook_auth_token.clone(),
+                    sync_lock: Default::default(),
+                };
+                webhook::serve(*port, state).await?;
+            }
+            Command::ServeRss {
+                port,
+                max_items,
+                feed_title,
+            } => {
+                let state = feed::FeedState {
+                    storage: storage(&pools, &args, &config),
+                    meta: convert::FeedMeta {
+                        title: feed_title.clone(),
+                        description: String::from("Documents saved to Readwise Reader"),
+                        link: String::from("https://read.readwise.io/"),
+                        last_build_date: Utc::now(),
+                    },
+                    max_items: *max_items,
+                };
+                feed::serve(*port, state).await?;
+            }
+            Command::Export(ExportCommand::Fixtures {
+                path,
+                limit,
+                category,
+                location,
+                with_content,
+            }) => {
+                let filter = db::QueryFilter {
+                    category: *category,
+                    location: *location,
+                    limit: *limit,
+                    ..Default::default()
+                };
+                check_filter_values(&pools, &filter).await?;
+                let exported = db::export_fixtures(&pools, &filter, path, *with_content).await?;
+                info!("Exported {exported} documents to {}", path.display());
+            }
+            Command::Export(ExportCommand::Jsonl {
+                path,
+                fields,
+                jsonl_no_content,
+                jsonl_compact: _,
+                jsonl_pretty,
+                limit,
+                category,
+                location,
+            }) => {
+                let filter = db::QueryFilter {
+                    category: *category,
+                    location: *location,
+                    limit: *limit,
+                    ..Default::default()
+                };
+                check_filter_values(&pools, &filter).await?;
+                let mut options = db::JsonlExportOptions {
+                    pretty: *jsonl_pretty,
+                    ..Default::default()
+                };
+                if !fields.is_empty() {
+                    options.fields = fields.clone();
+                }
+                if *jsonl_no_content {
+                    options.fields.retain(|f| f != "content");
+                }
+                let exported = db::export_jsonl(&pools, &filter, path, &options).await?;
+                info!("Exported {exported} documents to {}", path.display());
+            }
+            Command::Export(ExportCommand::Csv {
+                path,
+                columns,
+                csv_no_headers,
+                csv_delimiter,
+                csv_decimal_separator,
+                csv_thousands_separator,
+                csv_date_format,
+                limit,
+                category,
+                location,
+            }) => {
+                let filter = db::QueryFilter {
+                    category: *category,
+                    location: *location,
+                    limit: *limit,
+                    ..Default::default()
+                };
+                check_filter_values(&pools, &filter).await?;
+                let mut options = db::CsvExportOptions {
+                    include_headers: !csv_no_headers,
+                    delimiter: *csv_delimiter,
+                    locale: db::CsvLocale {
+                        decimal_separator: *csv_decimal_separator,
+                        thousands_separator: *csv_thousands_separator,
+                    },
+                    date_format: csv_date_format.clone(),
+                    ..Default::default()
+                };
+                if !columns.is_empty() {
+                    options.columns = columns.clone();
+                }
+                let exported = db::export_csv(&pools, &filter, path, &options).await?;
+                info!("Exported {exported} documents to {}", path.display());
+            }
+            Command::Export(ExportCommand::Pocket {
+                path,
+                limit,
+                category,
+                location,
+            }) => {
+                let filter = db::QueryFilter {
+                    category: *category,
+                    location: *location,
+                    limit: *limit,
+                    ..Default::default()
+                };
+                check_filter_values(&pools, &filter).await?;
+                let exported = db::export_to_pocket_format(&pools, &filter, path).await?;
+                info!("Exported {exported} documents to {}", path.display());
+            }
+            Command::DeleteRemote { id } => {
+                let id = ItemId::from(id.as_str());
+                let client = api_client(&config)?;
+                if !api::delete_item(&client, &id)? {
+                    warn!("Document {id} was not found in Readwise, deleting it locally only");
+                }
+                match db::delete_by_id(&pools, &id).await? {
+                    0 => info!("Document {id} was not in the database"),
+                    rows => info!("Deleted document {id} ({rows} rows with highlights and notes)"),
+                }
+            }
+            Command::SyncHighlights { id } => {
+                let id = ItemId::from(id.as_str());
+                let client = api_client(&config)?;
+                let storage = storage(&pools, &args, &config);
+                let mut highlights = std::pin::pin!(api::stream_highlights(&client, &id));
+                let mut saved = 0;
+                while let Some(highlight) = highlights.try_next().await? {
+                    storage.save(&highlight).await?;
+                    saved += 1;
+                }
+                info!("Saved {saved} highlights of document {id}");
+            }
+            Command::AddTag { id, tag } | Command::RemoveTag { id, tag } => {
+                let id = ItemId::from(id.as_str());
+                let client = api_client(&config)?;
+                if matches!(command, Command::AddTag { .. }) {
+                    api::add_tag(&client, &id, tag)?;
+                } else {
+                    api::remove_tag(&client, &id, tag)?;
+                }
+                // Refresh the local copy rather than waiting for the next sync.
+                if let Some(item) = api::get_single_item(&client, &id)? {
+                    db::save(&pools.write, &DbRecord::from(item), db::SaveMode::Upsert).await?;
+                }
+                info!("Tags of {id} updated");
+            }
+            Command::ShowHistory { id } => {
+                for entry in db::audit_history(&pools, &ItemId::from(id.as_str())).await? {
+                    println!(
+                        "{} {}: {} -> {}",
+                        entry.changed_at,
+                        entry.field_name,
+                        entry.old_value.as_deref().unwrap_or("null"),
+                        entry.new_value.as_deref().unwrap_or("null")
+                    );
+                }
+            }
+            Command::ShowProgress { id } => {
+                let history = db::progress_history(&pools, &ItemId::from(id.as_str())).await?;
+                if history.is_empty() {
+                    println!("No reading progress recorded for {id}");
+                }
+                for (recorded_at, progress) in history {
+                    let filled = (progress.clamp(0.0, 1.0) * 50.0).round() as usize;
+                    println!(
+                        "{} |{:<50}| {:>3.0}%",
+                        recorded_at.format("%Y-%m-%d %H:%M"),
+                        "#".repeat(filled),
+                        progress * 100.0
+                    );
+                }
+            }
+            Command::ShowAuthor { name } => {
+                let stats = db::author_reading_stats(&pools, name).await?;
+                println!("{}", stats.name);
+                println!(
+                    "{} documents: {} completed, {} in progress, {} unread",
+                    stats.total_articles, stats.completed, stats.in_progress, stats.unread
+                );
+                println!(
+                    "{} words, {:.0}% average progress",
+                    stats.total_words,
+                    stats.avg_progress * 100.0
+                );
+                if let (Some(first), Some(last)) = (stats.first_read, stats.last_read) {
+                    println!("Read from {} to {}", first.date_naive(), last.date_naive());
+                }
+            }
+            Command::Show(ShowCommand::Categories) => {
+                for category in db::list_categories_present(&pools).await? {
+                    println!("{}", category.as_str());
+                }
+            }
+            Command::Show(ShowCommand::Locations) => {
+                for location in db::list_locations_present(&pools).await? {
+                    println!("{}", location.as_str());
+                }
+            }
+            Command::Show(ShowCommand::Authors) => {
+                for author in db::list_authors_present(&pools).await? {
+                    println!("{author}");
+                }
+            }
+            Command::Show(ShowCommand::Item { id }) => {
+                let id = ItemId::from(id.as_str());
+                let item = db::find_by_id(&pools.read, &id)
+                    .await?
+                    .with_context(|| format!("Document {id} not found"))?;
+                println!("{}", item.title);
+                if let Some(author) = &item.author {
+                    println!("by {author}");
+                }
+                println!(
+                    "{} in {}, {} words, {:.0}% read",
+                    item.category.as_str(),
+                    item.location.map_or("-", |l| l.as_str()),
+                    item.word_count,
+                    item.reading_progress * 100.0
+                );
+                match db::estimated_time_remaining(&pools, &id).await? {
+                    Some(remaining) => {
+                        println!("~{} minutes remaining", remaining.as_secs().div_ceil(60))
+                    }
+                    None => println!("Time remaining unknown"),
+                }
+            }
+            Command::Show(ShowCommand::Highlights {
+                limit,
+                author,
+                with_notes,
+            }) => {
+                let highlights = match author {
+                    Some(author) => db::highlights_for_author(&pools, author, *limit).await?,
+                    None if *with_notes => db::highlights_with_notes(&pools, *limit).await?,
+                    None => db::recently_highlighted(&pools, *limit).await?,
+                };
+                for highlight in highlights {
+                    println!("{} {}", highlight.created_at.date_naive(), highlight.title);
+                    if let Some(notes) = highlight.notes.filter(|n| !n.is_empty()) {
+                        println!("    note: {notes}");
+                    }
+                }
+            }
+            Command::Stats { command: None } => print_stats(&pools).await?,
+            Command::Stats {
+                command: Some(StatsCommand::Authors(top)),
+            } => {
+                let authors =
+                    db::top_authors(&pools, &top.filter(), top.sort_by, top.limit).await?;
+                print_top("author", &authors);
+            }
+            Command::Stats {
+                command:
+                    Some(StatsCommand::Sites {
+                        top,
+                        min_articles: None,
+                    }),
+            } => {
+                let sites = db::top_sites(&pools, &top.filter(), top.sort_by, top.limit).await?;
+                print_top("site", &sites);
             }
-            None => {
-                info!("No checkpoint found — performing full sync.");
-                None
+            Command::Stats {
+                command:
+                    Some(StatsCommand::Sites {
+                        min_articles: Some(min_articles),
+                        ..
+                    }),
+            } => {
+                let sites = db::aggregate_by_site(&pools, *min_articles).await?;
+                print_sites(&sites);
+            }
+            Command::Stats {
+                command: Some(StatsCommand::Sources),
+            } => {
+                let mut sources: Vec<_> = db::reading_sources_breakdown(&pools)
+                    .await?
+                    .into_iter()
+                    .collect();
+                sources.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                for (source, count) in sources {
+                    println!("{source:<40} {count:>6}");
+                }
+            }
+            Command::Stats {
+                command:
+                    Some(period @ (StatsCommand::Today | StatsCommand::Week | StatsCommand::Month)),
+            } => {
+                let offset = *chrono::Local::now().offset();
+                let words = match period {
+                    StatsCommand::Today => db::words_read_today(&pools, offset).await?,
+                    StatsCommand::Week => db::words_read_this_week(&pools, offset).await?,
+                    _ => db::words_read_this_month(&pools, offset).await?,
+                };
+                let wpm = db::estimated_reading_speed(&pools)
+                    .await?
+                    .unwrap_or(f64::from(WORDS_PER_MINUTE));
+                println!("~{words} words read (~{:.0} minutes)", words as f64 / wpm);
+            }
+            Command::Stats {
+                command:
+                    Some(StatsCommand::Timeline {
+                        granularity,
+                        since,
+                        chart,
+                    }),
+            } => {
+                let timeline = db::category_timeline(&pools, *granularity, *since).await?;
+                let largest = timeline.iter().map(|p| p.count).max().unwrap_or(0).max(1);
+                for point in &timeline {
+                    let period = match granularity {
+                        db::Granularity::Month => point.period.format("%Y-%m"),
+                        _ => point.period.format("%Y-%m-%d"),
+                    };
+                    print!(
+                        "{period:<10} {:<8} {:>6}",
+                        point.category.as_str(),
+                        point.count
+                    );
+                    if *chart {
+                        print!(" {}", "#".repeat((point.count * 40 / largest) as usize));
+                    }
+                    println!();
+                }
+            }
+            Command::Stats {
+                command: Some(StatsCommand::TagPairs { tag, with, limit }),
+            } => match with {
+                Some(other) => {
+                    let count = db::tag_co_occurrence(&pools, tag, other).await?;
+                    println!("{count} items tagged both \"{tag}\" and \"{other}\"");
+                }
+                None => {
+                    let pairs = db::most_co_occurring_tags(&pools, tag, *limit).await?;
+                    if pairs.is_empty() {
+                        println!("No tag found alongside \"{tag}\"");
+                    }
+                    for (other, count) in pairs {
+                        println!("{other:<40} {count:>6}");
+                    }
+                }
+            },
+            Command::Stats {
+                command: Some(StatsCommand::Storage),
+            } => {
+                let usage = db::storage_usage(&pools).await?;
+                let never = || String::from("never");
+                println!("Table:        {}", format_bytes(usage.table_size_bytes));
+                println!("Indexes:      {}", format_bytes(usage.index_size_bytes));
+                println!("Total:        {}", format_bytes(usage.total_size_bytes));
+                println!("Rows:         ~{}", usage.row_count);
+                println!("Dead rows:    ~{}", usage.dead_row_count);
+                println!(
+                    "Last vacuum:  {}",
+                    usage.last_vacuum.map_or_else(never, |t| t.to_string())
+                );
+                println!(
+                    "Last analyze: {}",
+                    usage.last_analyze.map_or_else(never, |t| t.to_string())
+                );
+            }
+            Command::Stats {
+                command: Some(StatsCommand::Distribution { buckets }),
+            } => {
+                let distribution = db::progress_distribution(&pools, *buckets).await?;
+                let largest = distribution
+                    .iter()
+                    .map(|b| b.count)
+                    .max()
+                    .unwrap_or(0)
+                    .max(1);
+                for bucket in &distribution {
+                    let bar = "#".repeat((bucket.count * 40 / largest) as usize);
+                    println!(
+                        "{:>4.0}%-{:>3.0}% {:>7} {bar}",
+                        bucket.lower * 100.0,
+                        bucket.upper * 100.0,
+                        bucket.count
+                    );
+                }
+                println!(
+                    "Completion rate: {:.1}%",
+                    db::completion_rate(&pools).await? * 100.0
+                );
+            }
+            Command::Check(CheckCommand::Integrity { fix }) => {
+                let report = db::check_consistency(&pools).await?;
+                report.print();
+                if *fix && !report.is_clean() {
+                    let merged = db::merge_duplicates(&pools).await?;
+                    let orphans = db::delete_orphaned_highlights(&pools).await?;
+                    let clamped = db::clamp_reading_progress(&pools).await?;
+                    let cleared = db::clear_malformed_source_urls(&pools).await?;
+                    println!(
+                        "Fixed: {merged} duplicates merged, {orphans} orphaned highlights deleted, \
+                         {clamped} progress values clamped, {cleared} malformed source URLs cleared"
+                    );
+                }
+            }
+            Command::Maintenance(MaintenanceCommand::Reindex) => {
+                db::reindex_concurrently(&pools).await?;
+            }
+            Command::Maintenance(MaintenanceCommand::Explain {
+                query,
+                dangerous,
+                limit,
+                category,
+                location,
+                since,
+            }) => {
+                anyhow::ensure!(
+                    *dangerous,
+                    "EXPLAIN ANALYZE runs the query; pass --dangerous to go ahead"
+                );
+                let filter = db::QueryFilter {
+                    category: *category,
+                    location: *location,
+                    since: *since,
+                    limit: *limit,
+                    ..Default::default()
+                };
+                check_filter_values(&pools, &filter).await?;
+                println!("{}", db::explain(&pools.read, query, &filter).await?);
             }
         }
-    };
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(pages) = args.benchmark {
+        let items = match &args.benchmark_fixtures {
+            Some(dir) => bench::load_fixture_pages(dir, pages)?,
+            None => bench::fetch_pages(&api_client(&config)?, pages)?,
+        };
+        info!("Benchmarking inserts of {} items...", items.len());
+        bench::run(&pools.write, &items).await?.print();
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if !args.per_category_interval.is_empty() {
+        let every_category = sync::Schedule {
+            category: None,
+            interval: Duration::from_secs(args.interval.unwrap_or(DEFAULT_INTERVAL_SECS)),
+            storage: storage(&pools, &args, &config),
+        };
+        let schedules = args.per_category_interval.iter().map(|&(category, secs)| {
+            let storage = postgres_backend(&pools, &args, &config).with_category(Some(category));
+            sync::Schedule {
+                category: Some(category),
+                interval: Duration::from_secs(secs),
+                storage: Arc::new(storage),
+            }
+        });
+        let schedules = std::iter::once(every_category).chain(schedules).collect();
+        sync::run_scheduled(
+            schedules,
+            &api_client(&config)?,
+            &sync_options(&args, &config),
+        )
+        .await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let storage = storage(&pools, &args, &config);
+    if args.daemon {
+        run_daemon(&storage, Some(&pools), &args, &config).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let options = sync_options(&args, &config);
+    let result = sync_to(&storage, Some(&pools), &options, &args, &config, None).await?;
+    maintain(&pools, &args).await?;
+    Ok(exit_code(&result))
+}
+
+/// Database upkeep requested to run after each sync.
+async fn maintain(pools: &db::DbPools, args: &Args) -> Result<()> {
+    if let Some(days) = args.purge_deleted_older_than {
+        let older_than = Duration::from_secs(u64::from(days) * 24 * 60 * 60);
+        let purged = db::purge_deleted(pools, older_than).await?;
+        info!("Purged {purged} documents, highlights and notes deleted more than {days} days ago");
+    }
+
+    if let Some(days) = args.prune_sync_audit_older_than {
+        let older_than = Duration::from_secs(u64::from(days) * 24 * 60 * 60);
+        let pruned = db::prune_sync_audit(pools, older_than).await?;
+        info!("Pruned {pruned} sync_audit entries recorded more than {days} days ago");
+    }
+
+    if let Some(days) = args.auto_archive_after_days {
+        let older_than = Duration::from_secs(u64::from(days) * 24 * 60 * 60);
+        let archived = db::archive_old_items(pools, older_than, Location::Archive).await?;
+        info!("Archived {archived} documents saved more than {days} days ago");
+    }
 
-    // Record start time before the sync so we don't miss documents
-    // updated while the sync is in progress.
-    let sync_started_at = Utc::now();
+    if args.vacuum_after_sync {
+        db::vacuum_and_analyze(pools).await?;
+    }
+    Ok(())
+}
 
-    let mut next_page_cursor: Option<String> = None;
+/// Sync every `--interval` seconds until SIGTERM or SIGINT, which let the
+/// current sync finish. A failed sync is logged and the next one runs as
+/// planned: only configuration errors stop the daemon.
+async fn run_daemon(
+    storage: &Arc<dyn StorageBackend>,
+    pools: Option<&db::DbPools>,
+    args: &Args,
+    config: &Config,
+) -> Result<()> {
+    api_client(config)?;
+    let _pid_file = args.pid_file.as_deref().map(PidFile::create).transpose()?;
+    let interval = Duration::from_secs(args.interval.unwrap_or(DEFAULT_INTERVAL_SECS));
+    let mut shutdown = shutdown_signal()?;
+    let mut options = sync_options(args, config);
 
     loop {
-        info!("Requesting Readwise API...");
-        let url = api::build_url(next_page_cursor.as_deref(), updated_after.as_ref());
+        let synced = async {
+            sync_to(storage, pools, &options, args, config, Some(interval)).await?;
+            if let Some(pools) = pools {
+                maintain(pools, args).await?;
+            }
+            anyhow::Ok(())
+        };
+        match synced.await {
+            Ok(()) => {}
+            Err(e)
+                if e.downcast_ref::<SyncError>()
+                    .is_some_and(SyncError::is_configuration_error) =>
+            {
+                return Err(e);
+            }
+            Err(e) => error!("Sync failed: {e:#}"),
+        }
+        // A full sync is only wanted once.
+        options.full_sync = false;
+
+        if *shutdown.borrow() {
+            break;
+        }
+        info!("Next sync in {} seconds", interval.as_secs());
+        tokio::select! {
+            () = tokio::time::sleep(interval) => {}
+            _ = shutdown.changed() => break,
+        }
+    }
+    info!("Shutting down");
+    Ok(())
+}
+
+/// Set to `true` on SIGTERM or SIGINT.
+fn shutdown_signal() -> Result<tokio::sync::watch::Receiver<bool>> {
+    let (sender, receiver) = tokio::sync::watch::channel(false);
+    #[cfg(unix)]
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = sigterm.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Shutdown requested");
+        let _ = sender.send(true);
+    });
+    Ok(receiver)
+}
 
-        let page = api::get_reading(&url, access_token)?;
+/// The `--pid-file`, removed when dropped.
+struct PidFile(PathBuf);
 
-        next_page_cursor = page.next_page_cursor;
+impl PidFile {
+    fn create(path: &Path) -> Result<Self> {
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Failed to write PID file {}", path.display()))?;
+        Ok(Self(path.to_path_buf()))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Failed to remove PID file {}: {e}", self.0.display());
+        }
+    }
+}
 
-        info!("{} total items remaining", page.total_remaining);
-        info!("Saving {} items to database...", page.results.len());
+/// Sync into `storage`, then report the result as requested by `args`.
+/// `pools` are those of `storage` when it is PostgreSQL, and `next_sync_in`
+/// the daemon's interval.
+async fn sync_to(
+    storage: &Arc<dyn StorageBackend>,
+    pools: Option<&db::DbPools>,
+    options: &sync::SyncOptions,
+    args: &Args,
+    config: &Config,
+    next_sync_in: Option<Duration>,
+) -> Result<sync::SyncResult> {
+    let client = api_client(config)?;
+    let started_at = Instant::now();
+    let result = sync::run(storage, &client, options).await?;
 
-        let mut failures = 0usize;
-        for result in page.results {
-            match db::save(&pool, &result).await {
-                Ok(_) => debug!("Synced: {}", result.title),
+    if args.output_stats {
+        let stats = serde_json::json!({
+            "new": result.items_new,
+            "updated": result.items_updated,
+            "errors": result.items_failed,
+            "pages": result.pages,
+            "duration_ms": started_at.elapsed().as_millis(),
+            "cursor": result.cursor,
+            "last_sync_at": result.last_sync_at,
+        });
+        println!("{stats}");
+    }
+
+    if !args.quiet {
+        let storage = match pools {
+            Some(pools) => match db::storage_usage(pools).await {
+                Ok(usage) => Some(usage),
                 Err(e) => {
-                    error!("{e}");
-                    failures += 1;
+                    warn!("Failed to measure the database size: {e:#}");
+                    None
                 }
-            }
+            },
+            None => None,
+        };
+        let report = sync::SyncReport {
+            result: result.clone(),
+            duration_ms: started_at.elapsed().as_millis(),
+            rate_limit: client.rate_limit_status(),
+            storage,
+            next_sync_at: next_sync_in.map(|interval| Utc::now() + interval),
+        };
+        print_report(&report, args.output_format);
+    }
+
+    if args.desktop_notify && (result.items_new > 0 || args.notify_on_empty) {
+        let message = format!("Readwise sync: {} new items", result.items_new);
+        if let Err(e) = notify::desktop(&message) {
+            warn!("Failed to show desktop notification: {e}");
         }
-        if failures > 0 {
-            warn!("{failures} document(s) failed to save on this page");
+    }
+
+    Ok(result)
+}
+
+fn print_report(report: &sync::SyncReport, format: OutputFormat) {
+    if format == OutputFormat::Json {
+        match serde_json::to_string(report) {
+            Ok(json) => eprintln!("{json}"),
+            Err(e) => warn!("Failed to serialize the sync report: {e}"),
         }
+        return;
+    }
 
-        if next_page_cursor.is_none() {
-            break;
+    let result = &report.result;
+    eprintln!(
+        "{:<10} {:>7} {:>7} {:>7}",
+        "category", "new", "updated", "failed"
+    );
+    for (category, counts) in &result.by_category {
+        eprintln!(
+            "{:<10} {:>7} {:>7} {:>7}",
+            category.as_str(),
+            counts.new,
+            counts.updated,
+            counts.failed
+        );
+    }
+    eprintln!(
+        "{:<10} {:>7} {:>7} {:>7}",
+        "total", result.items_new, result.items_updated, result.items_failed
+    );
+    if result.items_deleted > 0 {
+        eprintln!(
+            "{} documents no longer in Readwise were marked as deleted",
+            result.items_deleted
+        );
+    }
+    if result.progress_clamped > 0 {
+        eprintln!(
+            "{} documents had their reading progress clamped into [0, 1]",
+            result.progress_clamped
+        );
+    }
+    eprintln!(
+        "Duration:      {:.1?}",
+        Duration::from_millis(u64::try_from(report.duration_ms).unwrap_or(u64::MAX))
+    );
+    if let Some(storage) = &report.storage {
+        eprintln!("Database size: {}", format_bytes(storage.total_size_bytes));
+    }
+    if let Some(rate_limit) = &report.rate_limit {
+        eprintln!(
+            "Rate limit:    {}/{} requests remaining (resets at {})",
+            rate_limit.remaining, rate_limit.limit, rate_limit.reset_at
+        );
+    }
+    if let Some(next_sync_at) = report.next_sync_at {
+        eprintln!("Next sync at:  {next_sync_at}");
+    }
+}
+
+fn exit_code(result: &sync::SyncResult) -> ExitCode {
+    if result.timed_out {
+        ExitCode::from(EXIT_PARTIAL)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+async fn print_stats(pools: &db::DbPools) -> Result<()> {
+    let everything = db::QueryFilter::default();
+    let (words, progress, counts, reading_time) = tokio::try_join!(
+        db::word_count_by_category(pools),
+        db::avg_progress_by_category(pools),
+        db::item_count_by_category_and_location(pools),
+        db::total_reading_time_minutes(pools, &everything),
+    )?;
+
+    println!("{:<10} {:>12} {:>9}", "category", "words", "progress");
+    for (category, total) in &words {
+        let avg = progress
+            .iter()
+            .find(|(c, _)| c == category)
+            .map_or(0.0, |(_, p)| *p);
+        println!(
+            "{:<10} {total:>12} {:>8.0}%",
+            category.as_str(),
+            avg * 100.0
+        );
+    }
+
+    let locations = [
+        Location::New,
+        Location::Later,
+        Location::Shortlist,
+        Location::Archive,
+        Location::Feed,
+    ];
+    println!();
+    print!("{:<10}", "category");
+    for location in locations {
+        print!(" {:>9}", location.as_str());
+    }
+    println!();
+    for (category, _) in &words {
+        print!("{:<10}", category.as_str());
+        for location in locations {
+            let count = counts
+                .iter()
+                .find(|(c, l, _)| c == category && *l == location)
+                .map_or(0, |(_, _, n)| *n);
+            print!(" {count:>9}");
         }
+        println!();
     }
 
-    db::save_checkpoint(&pool, &sync_started_at).await?;
-    info!("Checkpoint saved: {sync_started_at}");
+    println!();
+    println!(
+        "Total reading time: ~{} hours {} minutes",
+        reading_time / 60,
+        reading_time % 60
+    );
+    println!(
+        "Highlights with notes: {}",
+        db::count_highlights_with_notes(pools).await?
+    );
+
+    let wpm = db::estimated_reading_speed(pools)
+        .await?
+        .unwrap_or(f64::from(WORDS_PER_MINUTE));
+    let in_progress = db::most_advanced_in_progress(pools, 5).await?;
+    if !in_progress.is_empty() {
+        println!();
+        println!("In progress (estimated finish date at ~{wpm:.0} words per minute):");
+        for item in &in_progress {
+            let finish = item.estimated_read_date(wpm).map_or_else(
+                || String::from("unknown"),
+                |date| date.date_naive().to_string(),
+            );
+            println!(
+                "  {finish:<10} {:>3.0}% {}",
+                item.reading_progress * 100.0,
+                item.title
+            );
+        }
+    }
 
+    let recent = db::recently_highlighted(pools, 5).await?;
+    if !recent.is_empty() {
+        println!();
+        println!("Recently highlighted:");
+        for highlight in recent {
+            println!(
+                "  {} {}",
+                highlight.created_at.date_naive(),
+                highlight.title
+            );
+        }
+    }
     Ok(())
 }
+
+/// Fail if `filter` asks for a category or location no document of the
+/// database has, listing those it has.
+async fn check_filter_values(pools: &db::DbPools, filter: &db::QueryFilter) -> Result<()> {
+    if let Some(category) = filter.category {
+        let present = db::list_categories_present(pools).await?;
+        anyhow::ensure!(
+            present.contains(&category),
+            "No {} documents in the database; categories present: {}",
+            category.as_str(),
+            present
+                .iter()
+                .map(Category::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    if let Some(location) = filter.location {
+        let present = db::list_locations_present(pools).await?;
+        anyhow::ensure!(
+            present.contains(&location),
+            "No documents in {} in the database; locations present: {}",
+            location.as_str(),
+            present
+                .iter()
+                .map(Location::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(())
+}
+
+impl TopArgs {
+    fn filter(&self) -> db::QueryFilter {
+        db::QueryFilter {
+            since: self.since,
+            until: self.until,
+            ..Default::default()
+        }
+    }
+}
+
+fn print_top(label: &str, rows: &[db::AuthorStats]) {
+    println!(
+        "{label:<40} {:>6} {:>10} {:>9}",
+        "count", "words", "progress"
+    );
+    for row in rows {
+        println!(
+            "{:<40} {:>6} {:>10} {:>8.0}%",
+            row.name,
+            row.article_count,
+            row.total_words,
+            row.avg_progress * 100.0
+        );
+    }
+}
+
+fn print_sites(sites: &[db::SiteStats]) {
+    println!(
+        "{:<40} {:>6} {:>10} {:>9}  categories",
+        "site", "count", "words", "progress"
+    );
+    for site in sites {
+        let categories: Vec<_> = site.categories.iter().map(Category::as_str).collect();
+        println!(
+            "{:<40} {:>6} {:>10} {:>8.0}%  {}",
+            site.site_name,
+            site.article_count,
+            site.total_words,
+            site.avg_progress * 100.0,
+            categories.join(", ")
+        );
+    }
+}
+
+fn storage(pools: &db::DbPools, args: &Args, config: &Config) -> Arc<dyn StorageBackend> {
+    Arc::new(postgres_backend(pools, args, config))
+}
+
+fn postgres_backend(pools: &db::DbPools, args: &Args, config: &Config) -> PostgresBackend {
+    PostgresBackend::new(pools.clone())
+        .with_statement_timeout(config.db_statement_timeout_secs)
+        .with_audit_log(args.audit_log)
+        .with_save_mode(args.save_mode)
+        .with_compact_content(args.compact_content)
+        .with_batch_chunk_size(config.batch_chunk_size())
+}
+
+fn sync_options(args: &Args, config: &Config) -> sync::SyncOptions {
+    sync::SyncOptions {
+        full_sync: args.full_sync,
+        workers: config.workers() as usize,
+        filter: db::QueryFilter {
+            min_reading_progress: args.reading_progress_min,
+            max_reading_progress: args.reading_progress_max,
+            min_word_count: args.min_word_count,
+            max_word_count: args.max_word_count,
+            include_zero_word_count: args.include_zero_wordcount,
+            ..Default::default()
+        },
+        strict_validation: args.strict_validation,
+        two_way: args.two_way.then_some(args.conflict_resolution),
+        timeout: config.sync_timeout_secs.map(Duration::from_secs),
+        overlap_buffer: Duration::from_secs(config.overlap_buffer_secs()),
+    }
+}
+
+fn parse_date(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    s.parse()
+        .map_err(|_| String::from("expected YYYY-MM-DD or an RFC 3339 timestamp"))
+}
+
+fn parse_fraction(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(String::from("expected a number between 0.0 and 1.0")),
+    }
+}
+
+/// `bytes` in the largest unit (up to GB) keeping the value at least 1.
+fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["bytes", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} bytes")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Parse a `CATEGORY=SECS` pair of --per-category-interval.
+fn parse_category_interval(s: &str) -> Result<(Category, u64), String> {
+    let (category, secs) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected CATEGORY=SECS, got `{s}`"))?;
+    let category = Category::from_str(category.trim(), true)?;
+    match secs.trim().parse::<u64>() {
+        Ok(secs) if secs > 0 => Ok((category, secs)),
+        _ => Err(format!(
+            "expected a positive number of seconds, got `{secs}`"
+        )),
+    }
+}
+
+fn connect_options(args: &Args, url: &str) -> Result<PgConnectOptions> {
+    let mut options: PgConnectOptions = url.parse()?;
+    if let Some(mode) = args.db_sslmode {
+        options = options.ssl_mode(mode);
+    }
+    if let Some(cert) = &args.db_ssl_root_cert {
+        options = options.ssl_root_cert(cert);
+    }
+    Ok(options)
+}
+
+fn progress_bar(len: usize) -> ProgressBar {
+    ProgressBar::new(len as u64).with_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} ({eta} left)")
+            .expect("progress bar template is valid"),
+    )
+}
+
+fn api_client(config: &Config) -> Result<api::ApiClient> {
+    let mut client = api::ApiClient::new(config.access_token()?)
+        .with_max_retries(config.max_retries())
+        .with_max_response_bytes(config.max_response_mb() * 1024 * 1024)
+        .with_strict(config.strict_api.unwrap_or(false))
+        .with_published_date_offset(config.published_date_offset()?)
+        .with_connection_pool(
+            config.http_pool_max_idle(),
+            config.http_pool_max_idle_per_host(),
+        );
+    debug!(
+        "HTTP connection pool: at most {} idle connections, {} per host",
+        config.http_pool_max_idle(),
+        config.http_pool_max_idle_per_host()
+    );
+    if let Some(secs) = config.http_read_timeout_secs {
+        client = client.with_read_timeout(Duration::from_secs(secs));
+    }
+    Ok(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn interval_needs_a_schedule() {
+        Args::command().debug_assert();
+        let parse = |args: &[&str]| Args::try_parse_from([&["reader-sync"], args].concat());
+
+        assert!(parse(&["--interval", "60"]).is_err());
+        assert!(parse(&["--daemon", "--interval", "60"]).is_ok());
+        assert!(parse(&["--per-category-interval", "rss=300", "--interval", "60"]).is_ok());
+    }
+
+    #[test]
+    fn per_category_schedules_run_no_maintenance() {
+        let parse = |args: &[&str]| Args::try_parse_from([&["reader-sync"], args].concat());
+
+        for maintenance in [
+            &["--purge-deleted-older-than", "30"][..],
+            &["--prune-sync-audit-older-than", "30"],
+            &["--two-way", "--auto-archive-after-days", "30"],
+            &["--vacuum-after-sync"],
+        ] {
+            assert!(parse(&[&["--daemon"], maintenance].concat()).is_ok());
+            let scheduled = [&["--per-category-interval", "rss=300"], maintenance].concat();
+            assert!(parse(&scheduled).is_err());
+        }
+    }
+}
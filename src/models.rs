@@ -1,9 +1,28 @@
-use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeDelta, Utc};
 use log::warn;
+use serde::de::{self, SeqAccess, Visitor};
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
+use thiserror::Error;
+use url::Url;
 
-#[derive(Debug, Deserialize, Serialize, sqlx::Type)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Deserialize,
+    Serialize,
+    sqlx::Type,
+    clap::ValueEnum,
+)]
 #[serde(rename_all = "lowercase")]
 #[sqlx(type_name = "category", rename_all = "lowercase")]
 pub enum Category {
@@ -18,7 +37,26 @@ pub enum Category {
     Video,
 }
 
-#[derive(Debug, Deserialize, Serialize, sqlx::Type)]
+impl Category {
+    /// Name used by the API and the database enum.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Article => "article",
+            Self::Email => "email",
+            Self::Epub => "epub",
+            Self::Highlight => "highlight",
+            Self::Note => "note",
+            Self::Pdf => "pdf",
+            Self::Rss => "rss",
+            Self::Tweet => "tweet",
+            Self::Video => "video",
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, sqlx::Type, clap::ValueEnum,
+)]
 #[serde(rename_all = "lowercase")]
 #[sqlx(type_name = "location", rename_all = "lowercase")]
 pub enum Location {
@@ -29,42 +67,523 @@ pub enum Location {
     Shortlist,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct ReaderResult {
+impl Location {
+    /// Name used by the API and the database enum.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Archive => "archive",
+            Self::Feed => "feed",
+            Self::Later => "later",
+            Self::New => "new",
+            Self::Shortlist => "shortlist",
+        }
+    }
+}
+
+/// How the `content` of a stored document is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum ContentFormat {
+    /// As sent by Readwise.
+    #[default]
+    Html,
+    /// Stripped of its markup by `--compact-content`.
+    Text,
+}
+
+/// A suspicious value in a document. Documents are saved regardless, unless
+/// strict validation is requested.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ValidationWarning {
+    #[error("reading_progress {0} is outside [0, 1]")]
+    ReadingProgressOutOfRange(f32),
+    #[error("title is empty")]
+    EmptyTitle,
+    /// Highlights and notes must have a parent, other categories must not.
+    #[error("{category:?} {id} does not match the presence of a parent_id")]
+    MismatchedCategory { category: Category, id: String },
+    #[error("word_count {0} is negative")]
+    NegativeWordCount(i32),
+}
+
+/// Why a string is not a usable Readwise access token.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum TokenError {
+    #[error("the access token is empty")]
+    Empty,
+    #[error("the access token contains {0:?}: Readwise tokens are only letters and digits")]
+    InvalidCharacter(char),
+}
+
+/// A Readwise access token, checked to be alphanumeric so that it makes a
+/// valid `Authorization` header. Debug output hides it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AccessToken(String);
+
+impl AccessToken {
+    /// Trim the whitespace a copy-paste may have brought along, and check the
+    /// rest.
+    pub fn new(raw: &str) -> Result<Self, TokenError> {
+        let token = raw.trim();
+        if token.is_empty() {
+            return Err(TokenError::Empty);
+        }
+        if let Some(c) = token.chars().find(|c| !c.is_ascii_alphanumeric()) {
+            return Err(TokenError::InvalidCharacter(c));
+        }
+        Ok(Self(token.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AccessToken(***)")
+    }
+}
+
+/// Readwise identifier of a document, highlight or note.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize, sqlx::Type)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct ItemId(pub String);
+
+impl ItemId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ItemId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for ItemId {
+    fn from(id: &str) -> Self {
+        Self(id.to_string())
+    }
+}
+
+impl From<String> for ItemId {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+/// Opaque position in the Readwise list API pagination, only meant to be sent
+/// back as the `pageCursor` parameter.
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct PageCursor(String);
+
+impl PageCursor {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for PageCursor {
+    fn from(cursor: String) -> Self {
+        Self(cursor)
+    }
+}
+
+/// Only shows the first 8 characters, cursors are long and meaningless.
+impl fmt::Debug for PageCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.char_indices().nth(8) {
+            Some((end, _)) => write!(f, "PageCursor({:?}…)", &self.0[..end]),
+            None => write!(f, "PageCursor({:?})", self.0),
+        }
+    }
+}
+
+/// A document, highlight or note.
+///
+/// Documents come in from the API as [`ApiResult`]s and are stored as
+/// [`DbRecord`]s; this is the form the rest of the crate works with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadingItem {
     pub author: Option<String>,
     pub category: Category,
+    /// The highlighted text of a highlight or note; usually empty for
+    /// documents.
     pub content: Option<String>,
+    /// When the document was saved to Reader.
     pub created_at: DateTime<Utc>,
     pub id: String,
+    /// Cover image.
     pub image_url: Option<String>,
+    /// Reader list of a document; `None` for highlights and notes.
     pub location: Option<Location>,
+    /// The user's note on the document or highlight.
     pub notes: Option<String>,
+    /// The document a highlight or note belongs to.
     pub parent_id: Option<String>,
-    #[serde(deserialize_with = "deserialize_published_date")]
+    /// Publication date given by the original source.
     pub published_date: Option<DateTime<Utc>>,
+    /// Fraction of the document read, from 0 to 1.
     pub reading_progress: f32,
+    /// Name of the publication: website, newsletter or feed.
     pub site_name: Option<String>,
+    /// How the document got into Reader, e.g. `Reader RSS` or
+    /// `reader-web-app`, not where it comes from (see `site_name`).
     pub source: Option<String>,
-    pub source_url: Option<String>,
+    /// Original address of the document, on its publisher's site.
+    pub source_url: Option<Url>,
     pub summary: Option<String>,
+    /// Object keyed by tag name, as the API returns them.
     // TODO: import structured tags
     pub tags: Option<Value>,
-    #[serde(deserialize_with = "deserialize_title")]
     pub title: String,
+    /// Last change in Reader, of the progress, location, tags...
     pub updated_at: Option<DateTime<Utc>>,
-    #[serde(rename = "url")]
-    pub readwise_url: Option<String>,
-    #[serde(deserialize_with = "deserialize_word_count")]
+    /// The document's page in Reader, the API's `url`.
+    pub readwise_url: Option<Url>,
+    /// 0 when unknown.
     pub word_count: i32,
 }
 
+/// A document in the format of the Readwise API, also used by fixture files.
+///
+/// The fields are described on [`ReadingItem`].
 #[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct ApiResult {
+    author: Option<String>,
+    category: Category,
+    content: Option<String>,
+    created_at: DateTime<Utc>,
+    id: String,
+    #[serde(default, deserialize_with = "deserialize_image_url")]
+    image_url: Option<String>,
+    location: Option<Location>,
+    notes: Option<String>,
+    parent_id: Option<String>,
+    #[serde(deserialize_with = "deserialize_published_date")]
+    published_date: PublishedDate,
+    reading_progress: f32,
+    site_name: Option<String>,
+    source: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_optional_url")]
+    source_url: Option<Url>,
+    summary: Option<String>,
+    tags: Option<Value>,
+    #[serde(deserialize_with = "deserialize_title")]
+    title: String,
+    updated_at: Option<DateTime<Utc>>,
+    #[serde(rename = "url", default, deserialize_with = "deserialize_optional_url")]
+    readwise_url: Option<Url>,
+    #[serde(deserialize_with = "deserialize_word_count")]
+    word_count: i32,
+    /// Fields this crate doesn't know, to report API changes.
+    #[serde(flatten, skip_serializing)]
+    unknown_fields: BTreeMap<String, Value>,
+    /// Names of all the fields of the first document of a page, known or
+    /// not, set by [`deserialize_results`].
+    #[serde(skip)]
+    field_names: Option<Vec<String>>,
+}
+
+impl ApiResult {
+    /// Take a date-only `published_date` to be midnight at `offset` rather
+    /// than in UTC.
+    pub(crate) fn localize_published_date(&mut self, offset: FixedOffset) {
+        if let PublishedDate::Day(day) = self.published_date {
+            let midnight = day.and_hms_opt(0, 0, 0).unwrap();
+            if let Some(at) = midnight.and_local_timezone(offset).single() {
+                self.published_date = PublishedDate::At(at.to_utc());
+            }
+        }
+    }
+}
+
+/// `published_date` as the API sends it: a date without a time of day is kept
+/// apart until it is placed in a time zone, midnight UTC by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(untagged)]
+pub(crate) enum PublishedDate {
+    #[default]
+    Unknown,
+    At(DateTime<Utc>),
+    Day(NaiveDate),
+}
+
+impl From<PublishedDate> for Option<DateTime<Utc>> {
+    fn from(date: PublishedDate) -> Self {
+        match date {
+            PublishedDate::Unknown => None,
+            PublishedDate::At(at) => Some(at),
+            PublishedDate::Day(day) => Some(day.and_hms_opt(0, 0, 0).unwrap().and_utc()),
+        }
+    }
+}
+
+impl From<Option<DateTime<Utc>>> for PublishedDate {
+    fn from(date: Option<DateTime<Utc>>) -> Self {
+        date.map_or(Self::Unknown, Self::At)
+    }
+}
+
+/// A row of the `reading` table.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct DbRecord {
+    pub author: Option<String>,
+    pub category: Category,
+    pub content: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub id: String,
+    pub image_url: Option<String>,
+    pub location: Option<Location>,
+    pub notes: Option<String>,
+    pub parent_id: Option<String>,
+    pub published_date: Option<DateTime<Utc>>,
+    pub reading_progress: f32,
+    pub site_name: Option<String>,
+    pub source: Option<String>,
+    #[sqlx(try_from = "UrlColumn")]
+    pub source_url: Option<Url>,
+    pub summary: Option<String>,
+    pub tags: Option<Value>,
+    pub title: String,
+    pub updated_at: Option<DateTime<Utc>>,
+    #[sqlx(try_from = "UrlColumn")]
+    pub readwise_url: Option<Url>,
+    pub word_count: i32,
+    pub content_format: ContentFormat,
+    /// Set when the document was removed from Readwise.
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Set by a trigger when the row is edited outside of a sync.
+    pub local_updated_at: Option<DateTime<Utc>>,
+}
+
+/// Move the fields shared by every representation of a document from one to
+/// another, setting the fields only `$target` has to their default.
+macro_rules! convert_item {
+    ($source:expr => $target:ident { $($extra:ident),* }) => {{
+        let source = $source;
+        $target {
+            author: source.author,
+            category: source.category,
+            content: source.content,
+            created_at: source.created_at,
+            id: source.id,
+            image_url: source.image_url,
+            location: source.location,
+            notes: source.notes,
+            parent_id: source.parent_id,
+            published_date: source.published_date.into(),
+            reading_progress: source.reading_progress,
+            site_name: source.site_name,
+            source: source.source,
+            source_url: source.source_url,
+            summary: source.summary,
+            tags: source.tags,
+            title: source.title,
+            updated_at: source.updated_at,
+            readwise_url: source.readwise_url,
+            word_count: source.word_count,
+            $($extra: Default::default(),)*
+        }
+    }};
+}
+
+impl From<ApiResult> for ReadingItem {
+    fn from(result: ApiResult) -> Self {
+        convert_item!(result => ReadingItem {})
+    }
+}
+
+impl From<ReadingItem> for ApiResult {
+    fn from(item: ReadingItem) -> Self {
+        convert_item!(item => ApiResult { unknown_fields, field_names })
+    }
+}
+
+/// A record for saving: `deleted_at` and `local_updated_at` are cleared, and
+/// the content is taken to be HTML.
+impl From<ReadingItem> for DbRecord {
+    fn from(item: ReadingItem) -> Self {
+        convert_item!(item => DbRecord { content_format, deleted_at, local_updated_at })
+    }
+}
+
+impl From<DbRecord> for ReadingItem {
+    fn from(record: DbRecord) -> Self {
+        convert_item!(record => ReadingItem {})
+    }
+}
+
+/// Average reading speed used for reading time estimates.
+pub const WORDS_PER_MINUTE: i32 = 238;
+
+impl ReadingItem {
+    /// Minutes needed to read the document at [`WORDS_PER_MINUTE`], at least
+    /// 1. Matches the `reading_time_minutes` column.
+    pub fn estimated_reading_minutes(&self) -> i32 {
+        (self.word_count / WORDS_PER_MINUTE).max(1)
+    }
+
+    /// When the rest of the document would be read, starting now at `wpm`
+    /// words per minute. `None` when the word count is unknown.
+    pub fn estimated_read_date(&self, wpm: f64) -> Option<DateTime<Utc>> {
+        if self.word_count <= 0 || wpm <= 0.0 {
+            return None;
+        }
+        let progress = f64::from(self.reading_progress.clamp(0.0, 1.0));
+        let remaining_minutes = f64::from(self.word_count) * (1.0 - progress) / wpm;
+        let remaining = TimeDelta::try_milliseconds((remaining_minutes * 60_000.0) as i64)?;
+        Utc::now().checked_add_signed(remaining)
+    }
+
+    /// Names of the document's tags.
+    ///
+    /// The API returns tags as an object keyed by tag name; a plain array of
+    /// names is accepted too.
+    pub fn tag_names(&self) -> Vec<String> {
+        match &self.tags {
+            Some(Value::Object(tags)) => tags.keys().cloned().collect(),
+            Some(Value::Array(tags)) => tags
+                .iter()
+                .filter_map(|tag| tag.as_str().map(String::from))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Bring `reading_progress` back into [0, 1], which the API sometimes
+    /// misses by a rounding error. Returns whether it had to.
+    pub fn clamp_reading_progress(&mut self) -> bool {
+        let clamped = self.reading_progress.clamp(0.0, 1.0);
+        if clamped == self.reading_progress {
+            return false;
+        }
+        warn!(
+            "Clamped reading_progress from {} to {} for item {}",
+            self.reading_progress, clamped, self.id
+        );
+        self.reading_progress = clamped;
+        true
+    }
+
+    /// Check the invariants the API is expected to uphold.
+    pub fn validate(&self) -> Vec<ValidationWarning> {
+        let mut warnings = Vec::new();
+        if !(0.0..=1.0).contains(&self.reading_progress) {
+            warnings.push(ValidationWarning::ReadingProgressOutOfRange(
+                self.reading_progress,
+            ));
+        }
+        if self.title.trim().is_empty() {
+            warnings.push(ValidationWarning::EmptyTitle);
+        }
+        let is_child = matches!(self.category, Category::Highlight | Category::Note);
+        if is_child != self.parent_id.is_some() {
+            warnings.push(ValidationWarning::MismatchedCategory {
+                category: self.category,
+                id: self.id.clone(),
+            });
+        }
+        if self.word_count < 0 {
+            warnings.push(ValidationWarning::NegativeWordCount(self.word_count));
+        }
+        warnings
+    }
+
+    /// Field-by-field equality, tolerating a `reading_progress` difference of up to `epsilon`.
+    pub fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        (self.reading_progress - other.reading_progress).abs() <= epsilon
+            && Self {
+                reading_progress: other.reading_progress,
+                ..self.clone()
+            } == *other
+    }
+}
+
+/// A nullable URL column, read as text. Values that do not parse are read as
+/// `None`, the same way [`deserialize_optional_url`] treats them.
+#[derive(sqlx::Type)]
+#[sqlx(transparent)]
+struct UrlColumn(Option<String>);
+
+impl From<UrlColumn> for Option<Url> {
+    fn from(column: UrlColumn) -> Self {
+        column.0.and_then(|url| parse_url_or_warn(&url))
+    }
+}
+
+fn parse_url_or_warn(url: &str) -> Option<Url> {
+    url.parse()
+        .inspect_err(|e| warn!("Ignoring malformed URL {url:?}: {e}"))
+        .ok()
+}
+
+/// One page of the Readwise list API.
+///
+/// `total_remaining` is the API's `count` field. Despite its name, it is the
+/// total number of documents matching the query, on every page: it does not
+/// decrease as pages are fetched. The number of documents on this page is
+/// [`ReaderResponse::per_page_count`].
+#[derive(Debug, Deserialize)]
 pub struct ReaderResponse {
     #[serde(rename = "count")]
     pub total_remaining: usize,
     #[serde(rename = "nextPageCursor")]
-    pub next_page_cursor: Option<String>,
-    pub results: Vec<ReaderResult>,
+    pub next_page_cursor: Option<PageCursor>,
+    #[serde(deserialize_with = "deserialize_results")]
+    pub(crate) results: Vec<ApiResult>,
+    /// Fields of the page itself this crate doesn't know.
+    #[serde(flatten)]
+    page_fields: BTreeMap<String, Value>,
+    /// [`crate::api::schema_checksum`] of the page, set by
+    /// [`crate::api::get_reading`].
+    #[serde(skip)]
+    pub schema_checksum: Option<String>,
+}
+
+impl ReaderResponse {
+    pub fn per_page_count(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Names of the fields of this page's documents that [`ReadingItem`] has
+    /// no place for, and that are dropped.
+    pub fn unknown_fields(&self) -> BTreeSet<&str> {
+        self.results
+            .iter()
+            .flat_map(|result| result.unknown_fields.keys())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Names of the fields of the first document of this page, if any.
+    pub fn first_field_names(&self) -> Option<&[String]> {
+        self.results.first()?.field_names.as_deref()
+    }
+
+    /// Names of the fields of the page itself, beside `count`,
+    /// `nextPageCursor` and `results`, that are dropped.
+    pub fn unknown_page_fields(&self) -> BTreeSet<&str> {
+        self.page_fields.keys().map(String::as_str).collect()
+    }
+
+    /// Take the date-only `published_date`s of the page's documents to be
+    /// midnight at `offset` rather than in UTC.
+    pub fn localize_published_dates(&mut self, offset: FixedOffset) {
+        for result in &mut self.results {
+            result.localize_published_date(offset);
+        }
+    }
+
+    pub fn into_items(self) -> Vec<ReadingItem> {
+        self.results.into_iter().map(ReadingItem::from).collect()
+    }
 }
 
 /// Deserialize `published_date` from the Readwise API.
@@ -73,40 +592,109 @@ pub struct ReaderResponse {
 /// - `null` → `None`
 /// - A Unix timestamp integer (seconds) → converted to `DateTime<Utc>`
 /// - A full ISO 8601 / RFC 3339 datetime string → parsed directly
-/// - A date-only string like `"2026-01-30"` → treated as midnight UTC
-pub fn deserialize_published_date<'de, D>(
-    deserializer: D,
-) -> Result<Option<DateTime<Utc>>, D::Error>
+/// - A date-only string like `"2026-01-30"` → kept as a date: midnight UTC
+///   unless [`ApiResult::localize_published_date`] picks another offset
+pub(crate) fn deserialize_published_date<'de, D>(deserializer: D) -> Result<PublishedDate, D::Error>
 where
     D: Deserializer<'de>,
 {
     let v: Value = Deserialize::deserialize(deserializer)?;
     match &v {
-        Value::Null => Ok(None),
+        Value::Null => Ok(PublishedDate::Unknown),
         Value::Number(n) => {
             let ts = n.as_i64().ok_or_else(|| {
                 serde::de::Error::custom(format!("invalid timestamp number: {n}"))
             })?;
             DateTime::from_timestamp(ts, 0)
-                .map(Some)
+                .map(PublishedDate::At)
                 .ok_or_else(|| serde::de::Error::custom(format!("timestamp out of range: {ts}")))
         }
         Value::String(s) => {
-            // Try full datetime first, then fall back to date-only (midnight UTC).
+            // Try full datetime first, then fall back to date-only.
             if let Ok(dt) = s.parse::<DateTime<Utc>>() {
-                return Ok(Some(dt));
+                return Ok(PublishedDate::At(dt));
             }
             if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
-                return Ok(Some(date.and_hms_opt(0, 0, 0).unwrap().and_utc()));
+                return Ok(PublishedDate::Day(date));
             }
             warn!("Failed to parse published_date string {s:?}. Defaulting to None.");
-            Ok(None)
+            Ok(PublishedDate::Unknown)
         }
         other => {
             warn!("Unexpected published_date value: {other:?}. Defaulting to None.");
-            Ok(None)
+            Ok(PublishedDate::Unknown)
+        }
+    }
+}
+
+/// Deserialize the documents of a page, keeping the field names of the first
+/// one, for [`crate::api::schema_checksum`]. Only that document goes through
+/// a [`Value`].
+fn deserialize_results<'de, D>(deserializer: D) -> Result<Vec<ApiResult>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct ResultsVisitor;
+
+    impl<'de> Visitor<'de> for ResultsVisitor {
+        type Value = Vec<ApiResult>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a list of documents")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut results = Vec::new();
+            if let Some(first) = seq.next_element::<serde_json::Map<String, Value>>()? {
+                let field_names = first.keys().cloned().collect();
+                let mut result =
+                    ApiResult::deserialize(Value::Object(first)).map_err(de::Error::custom)?;
+                result.field_names = Some(field_names);
+                results.push(result);
+            }
+            while let Some(result) = seq.next_element()? {
+                results.push(result);
+            }
+            Ok(results)
         }
     }
+
+    deserializer.deserialize_seq(ResultsVisitor)
+}
+
+/// Deserialize an optional URL, treating a malformed one as missing.
+pub fn deserialize_optional_url<'de, D>(deserializer: D) -> Result<Option<Url>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let url: Option<String> = Deserialize::deserialize(deserializer)?;
+    Ok(url.as_deref().and_then(parse_url_or_warn))
+}
+
+/// Longest `image_url` kept, as most browsers and CDNs go.
+const MAX_IMAGE_URL_LEN: usize = 2048;
+
+/// Deserialize `image_url`, dropping data URIs and overly long URLs: a whole
+/// base64-encoded image has no place in the `image_url` column.
+pub fn deserialize_image_url<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let url: Option<String> = Deserialize::deserialize(deserializer)?;
+    Ok(url.filter(|url| {
+        let is_data_uri = url
+            .get(..5)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"));
+        if is_data_uri {
+            warn!("Ignoring data URI image_url of {} bytes", url.len());
+        } else if url.len() > MAX_IMAGE_URL_LEN {
+            warn!(
+                "Ignoring image_url of {} bytes, over {MAX_IMAGE_URL_LEN}",
+                url.len()
+            );
+        }
+        !is_data_uri && url.len() <= MAX_IMAGE_URL_LEN
+    }))
 }
 
 /// Deserialize word_count as i32 or default to 0 if the value is null.
@@ -125,3 +713,234 @@ where
     Deserialize::deserialize(deserializer)
         .map(|x: Option<_>| x.unwrap_or_else(|| String::from("Untitled")))
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::ReaderResultBuilder;
+
+    #[test]
+    fn date_only_published_dates_are_midnight_at_the_configured_offset() {
+        let page = json!({
+            "count": 1,
+            "nextPageCursor": null,
+            "results": [{
+                "id": "doc", "category": "article", "created_at": "2024-02-01T00:00:00Z",
+                "author": null, "content": null, "image_url": null, "location": "new",
+                "notes": null, "parent_id": null, "published_date": "2024-01-31",
+                "reading_progress": 0, "site_name": null, "source": null,
+                "summary": null, "tags": null, "title": "Doc", "updated_at": null,
+                "word_count": 0
+            }],
+        });
+        let published = |offset: Option<FixedOffset>| {
+            let mut page: ReaderResponse = serde_json::from_value(page.clone()).unwrap();
+            if let Some(offset) = offset {
+                page.localize_published_dates(offset);
+            }
+            page.into_items()[0].published_date.unwrap()
+        };
+
+        let utc = Utc.with_ymd_and_hms(2024, 1, 31, 0, 0, 0).unwrap();
+        assert_eq!(published(None), utc);
+        let east = FixedOffset::east_opt(12 * 3600).unwrap();
+        assert_eq!(published(Some(east)), utc - TimeDelta::hours(12));
+        let west = FixedOffset::west_opt(12 * 3600).unwrap();
+        assert_eq!(published(Some(west)), utc + TimeDelta::hours(12));
+    }
+
+    #[test]
+    fn access_tokens_are_trimmed_and_must_be_alphanumeric() {
+        let token = AccessToken::new("  AbC123xyz\n").unwrap();
+        assert_eq!(token.as_str(), "AbC123xyz");
+        assert_eq!(format!("{token:?}"), "AccessToken(***)");
+
+        assert_eq!(AccessToken::new(" \n"), Err(TokenError::Empty));
+        assert_eq!(
+            AccessToken::new("abc def"),
+            Err(TokenError::InvalidCharacter(' '))
+        );
+        assert_eq!(
+            AccessToken::new("Token abc"),
+            Err(TokenError::InvalidCharacter(' '))
+        );
+    }
+
+    #[test]
+    fn data_uris_and_long_image_urls_are_dropped() {
+        let image_url = |url: &str| {
+            let mut result =
+                serde_json::to_value(ApiResult::from(ReaderResultBuilder::new().build())).unwrap();
+            result["image_url"] = json!(url);
+            ReadingItem::from(serde_json::from_value::<ApiResult>(result).unwrap()).image_url
+        };
+
+        let url = "https://example.com/cover.png";
+        assert_eq!(image_url(url).as_deref(), Some(url));
+        assert_eq!(image_url("data:image/png;base64,iVBORw0KGgo="), None);
+        assert_eq!(image_url("DATA:image/gif;base64,R0lGODlh"), None);
+        let long = format!("https://example.com/{}", "a".repeat(MAX_IMAGE_URL_LEN));
+        assert_eq!(image_url(&long), None);
+    }
+
+    #[test]
+    fn urls_are_normalized_and_malformed_ones_dropped() {
+        let source_url = |url: serde_json::Value| {
+            let mut result =
+                serde_json::to_value(ApiResult::from(ReaderResultBuilder::new().build())).unwrap();
+            result["source_url"] = url;
+            ReadingItem::from(serde_json::from_value::<ApiResult>(result).unwrap())
+                .source_url
+                .map(String::from)
+        };
+        let normalized = |url: &str| source_url(json!(url));
+
+        assert_eq!(
+            normalized("https://example.com/a?b=c#d").as_deref(),
+            Some("https://example.com/a?b=c#d")
+        );
+        assert_eq!(
+            normalized("HTTPS://Example.COM").as_deref(),
+            Some("https://example.com/")
+        );
+        assert_eq!(
+            normalized("https://example.com:443/a").as_deref(),
+            Some("https://example.com/a")
+        );
+        assert_eq!(
+            normalized("http://example.com/a b/../c").as_deref(),
+            Some("http://example.com/c")
+        );
+        assert_eq!(
+            normalized("https://example.com/caf\u{e9}").as_deref(),
+            Some("https://example.com/caf%C3%A9")
+        );
+        assert_eq!(normalized("example.com/no-scheme"), None);
+        assert_eq!(normalized("https://"), None);
+        assert_eq!(source_url(json!(null)), None);
+    }
+
+    #[test]
+    fn suspicious_values_are_reported() {
+        assert!(ReaderResultBuilder::new().build().validate().is_empty());
+
+        let item = ReaderResultBuilder::new()
+            .id("odd")
+            .title("  ")
+            .reading_progress(1.5)
+            .word_count(-1)
+            .category(Category::Highlight)
+            .build();
+        assert_eq!(
+            item.validate(),
+            [
+                ValidationWarning::ReadingProgressOutOfRange(1.5),
+                ValidationWarning::EmptyTitle,
+                ValidationWarning::MismatchedCategory {
+                    category: Category::Highlight,
+                    id: String::from("odd"),
+                },
+                ValidationWarning::NegativeWordCount(-1),
+            ]
+        );
+
+        let orphan_parent = ReaderResultBuilder::new().parent_id("doc").build();
+        assert_eq!(orphan_parent.validate().len(), 1);
+    }
+
+    #[test]
+    fn reading_progress_is_clamped_into_range() {
+        for (progress, expected) in [(1.000_000_1, 1.0), (-0.001, 0.0)] {
+            let mut item = ReaderResultBuilder::new()
+                .reading_progress(progress)
+                .build();
+            assert!(item.clamp_reading_progress());
+            assert_eq!(item.reading_progress, expected);
+            assert!(item.validate().is_empty());
+        }
+
+        let mut item = ReaderResultBuilder::new().reading_progress(0.5).build();
+        assert!(!item.clamp_reading_progress());
+        assert_eq!(item.reading_progress, 0.5);
+    }
+
+    #[test]
+    fn estimated_read_date_counts_the_words_left() {
+        let item = ReaderResultBuilder::new()
+            .word_count(3000)
+            .reading_progress(0.5)
+            .build();
+        let before = Utc::now();
+        let finish = item.estimated_read_date(250.0).unwrap();
+        // 1500 words left at 250 words per minute.
+        assert!(finish - before >= TimeDelta::minutes(6));
+        assert!(finish - Utc::now() <= TimeDelta::minutes(6));
+
+        let unknown = ReaderResultBuilder::new().word_count(0).build();
+        assert_eq!(unknown.estimated_read_date(250.0), None);
+    }
+
+    #[test]
+    fn unknown_fields_of_pages_and_documents_are_collected() {
+        let page: ReaderResponse = serde_json::from_value(json!({
+            "count": 1,
+            "nextPageCursor": null,
+            "schema_version": 2,
+            "results": [{
+                "id": "doc-1",
+                "title": "Title",
+                "category": "article",
+                "location": "new",
+                "created_at": "2024-03-01T12:00:00Z",
+                "published_date": null,
+                "reading_progress": 0,
+                "word_count": 10,
+                "first_opened_at": null,
+                "is_pinned": true
+            }]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            page.unknown_fields(),
+            BTreeSet::from(["first_opened_at", "is_pinned"])
+        );
+        assert_eq!(
+            page.unknown_page_fields(),
+            BTreeSet::from(["schema_version"])
+        );
+    }
+
+    #[test]
+    fn timestamps_with_an_offset_are_converted_to_utc() {
+        let result: ApiResult = serde_json::from_value(json!({
+            "id": "doc-1",
+            "title": "Title",
+            "category": "article",
+            "location": "new",
+            "created_at": "2024-03-01T14:30:00+02:00",
+            "updated_at": "2024-03-01T09:00:00.250-05:00",
+            "published_date": 1_700_000_000,
+            "reading_progress": 0,
+            "word_count": 10
+        }))
+        .unwrap();
+        let item = ReadingItem::from(result);
+
+        assert_eq!(
+            item.created_at,
+            Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap()
+        );
+        assert_eq!(
+            item.updated_at.unwrap().to_rfc3339(),
+            "2024-03-01T14:00:00.250+00:00"
+        );
+        assert_eq!(
+            item.published_date,
+            Utc.timestamp_opt(1_700_000_000, 0).single()
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use std::process::Command;
+
+use anyhow::{Result, bail};
+
+/// Show `message` as a desktop notification using the platform's own tooling.
+pub fn desktop(message: &str) -> Result<()> {
+    let status = notification_command(message).status()?;
+    if !status.success() {
+        bail!("Notification command exited with {status}");
+    }
+    Ok(())
+}
+
+// Other Unix desktops generally ship `notify-send` too.
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn notification_command(message: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.arg(message);
+    command
+}
+
+#[cfg(target_os = "macos")]
+fn notification_command(message: &str) -> Command {
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(format!(
+        "display notification \"{}\"",
+        message.replace('\\', "\\\\").replace('"', "\\\"")
+    ));
+    command
+}
+
+#[cfg(target_os = "windows")]
+fn notification_command(message: &str) -> Command {
+    let mut command = Command::new("powershell.exe");
+    command.args(["-NoProfile", "-Command"]).arg(format!(
+        "New-BurntToastNotification -Text '{}'",
+        message.replace('\'', "''")
+    ));
+    command
+}
@@ -0,0 +1,748 @@
+//! Where synced documents are kept.
+//!
+//! The sync only talks to a [`StorageBackend`], so that documents can be
+//! stored somewhere else than PostgreSQL.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use async_stream::try_stream;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use futures::stream::BoxStream;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::convert;
+use crate::db::{self, DbPools, QueryFilter, SaveMode};
+use crate::models::{
+    ApiResult, Category, ContentFormat, DbRecord, ItemId, PageCursor, ReadingItem,
+};
+
+/// What saving a document did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveOutcome {
+    /// The document was not stored before.
+    Inserted,
+    /// The document was stored with different content.
+    Updated,
+    /// The document was stored as it is.
+    Unchanged,
+}
+
+/// Number of documents of a batch per [`SaveOutcome`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchSaveResult {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+impl BatchSaveResult {
+    pub fn add(&mut self, outcome: SaveOutcome) {
+        match outcome {
+            SaveOutcome::Inserted => self.inserted += 1,
+            SaveOutcome::Updated => self.updated += 1,
+            SaveOutcome::Unchanged => self.unchanged += 1,
+        }
+    }
+}
+
+impl FromIterator<SaveOutcome> for BatchSaveResult {
+    fn from_iter<I: IntoIterator<Item = SaveOutcome>>(outcomes: I) -> Self {
+        let mut result = Self::default();
+        for outcome in outcomes {
+            result.add(outcome);
+        }
+        result
+    }
+}
+
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Insert or replace a document.
+    async fn save(&self, item: &ReadingItem) -> Result<SaveOutcome>;
+
+    /// Save several documents: either all of them are stored or none is.
+    /// Returns what saving each of them did, in order.
+    async fn save_batch(&self, items: &[ReadingItem]) -> Result<Vec<SaveOutcome>>;
+
+    /// Start time of the last complete sync.
+    async fn load_checkpoint(&self) -> Result<Option<DateTime<Utc>>>;
+
+    /// Record the start time of a sync, and the page an interrupted sync
+    /// resumes from (`None` once a sync completed).
+    async fn save_checkpoint(&self, ts: &DateTime<Utc>, cursor: Option<&PageCursor>) -> Result<()>;
+
+    /// Forget the last sync and any saved page cursor.
+    async fn clear_checkpoint(&self) -> Result<()>;
+
+    /// Page cursor saved by an interrupted sync, if any.
+    async fn load_page_cursor(&self) -> Result<Option<PageCursor>>;
+
+    /// Record how far an unfinished sync got, leaving the checkpoint time
+    /// untouched.
+    async fn save_page_cursor(&self, cursor: Option<&PageCursor>) -> Result<()>;
+
+    /// Checksum of the document fields of the last API page. Storages without
+    /// a place for it don't track it.
+    async fn load_schema_checksum(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    async fn save_schema_checksum(&self, _checksum: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Soft-delete the stored documents whose ID is not in `seen_ids`, every
+    /// ID a full sync got from Readwise. Returns the number of documents
+    /// soft-deleted. Storages without soft deletion keep every document.
+    async fn mark_missing_deleted(&self, _seen_ids: &[ItemId]) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Documents matching `filter`, oldest first.
+    fn stream_all<'a>(&'a self, filter: &'a QueryFilter) -> BoxStream<'a, Result<ReadingItem>>;
+
+    /// The `limit` most recently saved documents matching `filter`, newest
+    /// first. Highlights and notes are left out.
+    async fn latest_documents(
+        &self,
+        filter: &QueryFilter,
+        limit: usize,
+    ) -> Result<Vec<ReadingItem>> {
+        // Documents stream oldest first: keep the tail.
+        let mut latest = VecDeque::with_capacity(limit + 1);
+        let mut items = self.stream_all(filter);
+        while let Some(item) = items.try_next().await? {
+            if item.parent_id.is_some() {
+                continue;
+            }
+            latest.push_back(item);
+            if latest.len() > limit {
+                latest.pop_front();
+            }
+        }
+        Ok(latest.into_iter().rev().collect())
+    }
+
+    /// Documents edited in the storage since they were last synced, with the
+    /// time of the edit, for two-way syncs. Storages that are not edited by
+    /// hand have none.
+    async fn locally_modified(&self) -> Result<Vec<(ReadingItem, DateTime<Utc>)>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Documents stored in the `reading` table.
+pub struct PostgresBackend {
+    pools: DbPools,
+    statement_timeout_secs: Option<u32>,
+    audit_log: bool,
+    save_mode: SaveMode,
+    compact_content: bool,
+    category: Option<Category>,
+    batch_chunk_size: usize,
+}
+
+impl PostgresBackend {
+    pub fn new(pools: DbPools) -> Self {
+        Self {
+            pools,
+            statement_timeout_secs: None,
+            audit_log: false,
+            save_mode: SaveMode::Upsert,
+            compact_content: false,
+            category: None,
+            batch_chunk_size: db::MAX_ROWS_PER_INSERT,
+        }
+    }
+
+    /// Cancel saves whose statements run longer than `secs` seconds.
+    pub fn with_statement_timeout(mut self, secs: Option<u32>) -> Self {
+        self.statement_timeout_secs = secs;
+        self
+    }
+
+    /// Record the fields changed by each save in `sync_audit`.
+    pub fn with_audit_log(mut self, audit_log: bool) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
+
+    /// Whether saving a document already stored replaces it.
+    pub fn with_save_mode(mut self, save_mode: SaveMode) -> Self {
+        self.save_mode = save_mode;
+        self
+    }
+
+    /// Store the content of documents as plain text rather than HTML.
+    pub fn with_compact_content(mut self, compact_content: bool) -> Self {
+        self.compact_content = compact_content;
+        self
+    }
+
+    /// Keep the checkpoint in the `category_sync_state` row of `category`, for
+    /// syncs restricted to it.
+    pub fn with_category(mut self, category: Option<Category>) -> Self {
+        self.category = category;
+        self
+    }
+
+    /// Write batches by `INSERT`s of up to `chunk_size` documents (see
+    /// [`db::save_batch`]).
+    pub fn with_batch_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.batch_chunk_size = chunk_size;
+        self
+    }
+
+    pub fn pools(&self) -> &DbPools {
+        &self.pools
+    }
+
+    fn record(&self, item: &ReadingItem) -> DbRecord {
+        let mut record = DbRecord::from(item.clone());
+        if self.compact_content {
+            record.content = record.content.as_deref().map(convert::html_to_text);
+            record.content_format = ContentFormat::Text;
+        }
+        record
+    }
+
+    /// Save `records` on `conn`, which should be in a transaction: the
+    /// stored versions are read by one query, and the documents written by
+    /// multi-row `INSERT`s. Returns the outcome of each record, in order.
+    async fn save_records(
+        &self,
+        conn: &mut PgConnection,
+        records: &[DbRecord],
+    ) -> Result<Vec<SaveOutcome>> {
+        let ids: Vec<&str> = records.iter().map(|record| record.id.as_str()).collect();
+        let stored: HashMap<String, DbRecord> = db::find_records(&mut *conn, &ids)
+            .await?
+            .into_iter()
+            .map(|record| (record.id.clone(), record))
+            .collect();
+
+        // Each record is compared with the version it replaces, the one saved
+        // earlier in the batch for a document listed twice.
+        let mut current: HashMap<&str, &DbRecord> = stored
+            .iter()
+            .map(|(id, record)| (id.as_str(), record))
+            .collect();
+        let mut outcomes = Vec::with_capacity(records.len());
+        let mut changes = Vec::new();
+        let mut progress = Vec::new();
+        let mut written: Vec<&str> = Vec::new();
+        let mut queued = HashSet::new();
+        for record in records {
+            let old = current.get(record.id.as_str()).copied();
+            if old.is_some() && self.save_mode == SaveMode::InsertOnly {
+                debug!("Kept: {}", record.id);
+                outcomes.push(SaveOutcome::Unchanged);
+                continue;
+            }
+            let outcome = match old {
+                None => SaveOutcome::Inserted,
+                Some(old) if db::changed_fields(old, record).is_empty() => SaveOutcome::Unchanged,
+                Some(_) => SaveOutcome::Updated,
+            };
+            match outcome {
+                SaveOutcome::Inserted => debug!("Inserted: {}", record.id),
+                SaveOutcome::Updated => debug!("Updated: {}", record.id),
+                SaveOutcome::Unchanged => debug!("Unchanged: {}", record.id),
+            }
+            if let Some(old) = old
+                && self.audit_log
+            {
+                changes.push((old, record));
+            }
+            progress.push((old, record));
+            current.insert(&record.id, record);
+            if queued.insert(record.id.as_str()) {
+                written.push(&record.id);
+            }
+            outcomes.push(outcome);
+        }
+
+        db::record_changes(conn, &changes).await?;
+        db::record_progress(conn, &progress).await?;
+        // Saved even when unchanged, to clear `local_updated_at` and `deleted_at`.
+        let unique: Vec<DbRecord> = written
+            .iter()
+            .map(|id| db::strip_null_bytes(current[id]).into_owned())
+            .collect();
+        db::upsert_chunks(conn, &unique, self.batch_chunk_size).await?;
+        Ok(outcomes)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for PostgresBackend {
+    async fn save(&self, item: &ReadingItem) -> Result<SaveOutcome> {
+        let outcomes = self.save_batch(std::slice::from_ref(item)).await?;
+        Ok(outcomes[0])
+    }
+
+    async fn save_batch(&self, items: &[ReadingItem]) -> Result<Vec<SaveOutcome>> {
+        let records = &items
+            .iter()
+            .map(|item| self.record(item))
+            .collect::<Vec<_>>();
+        let outcomes = db::retry_serialization_failures(|| async move {
+            match self.statement_timeout_secs {
+                Some(secs) => {
+                    db::with_statement_timeout(&self.pools.write, secs, async |conn| {
+                        self.save_records(conn, records).await
+                    })
+                    .await
+                }
+                None => {
+                    let mut tx = self.pools.write.begin().await?;
+                    let outcomes = self.save_records(&mut tx, records).await?;
+                    tx.commit().await?;
+                    Ok(outcomes)
+                }
+            }
+        })
+        .await?;
+        if records.len() > 1 {
+            let result: BatchSaveResult = outcomes.iter().copied().collect();
+            debug!(
+                "Saved a batch of {} documents: {} inserted, {} updated, {} unchanged",
+                records.len(),
+                result.inserted,
+                result.updated,
+                result.unchanged
+            );
+        }
+        Ok(outcomes)
+    }
+
+    async fn load_checkpoint(&self) -> Result<Option<DateTime<Utc>>> {
+        match self.category {
+            Some(category) => Ok(db::load_category_state(&self.pools, category).await?.0),
+            None => db::load_checkpoint(&self.pools).await,
+        }
+    }
+
+    async fn save_checkpoint(&self, ts: &DateTime<Utc>, cursor: Option<&PageCursor>) -> Result<()> {
+        match self.category {
+            Some(category) => {
+                db::save_category_checkpoint(&self.pools, category, Some(ts), cursor).await
+            }
+            None => db::save_checkpoint(&self.pools.write, ts, cursor).await,
+        }
+    }
+
+    async fn clear_checkpoint(&self) -> Result<()> {
+        match self.category {
+            Some(category) => db::save_category_checkpoint(&self.pools, category, None, None).await,
+            None => db::clear_checkpoint(&self.pools).await,
+        }
+    }
+
+    async fn load_page_cursor(&self) -> Result<Option<PageCursor>> {
+        match self.category {
+            Some(category) => Ok(db::load_category_state(&self.pools, category).await?.1),
+            None => db::load_page_cursor(&self.pools).await,
+        }
+    }
+
+    async fn save_page_cursor(&self, cursor: Option<&PageCursor>) -> Result<()> {
+        match self.category {
+            Some(category) => db::save_category_page_cursor(&self.pools, category, cursor).await,
+            None => db::save_page_cursor(&self.pools, cursor).await,
+        }
+    }
+
+    async fn load_schema_checksum(&self) -> Result<Option<String>> {
+        db::load_schema_checksum(&self.pools).await
+    }
+
+    async fn save_schema_checksum(&self, checksum: &str) -> Result<()> {
+        db::save_schema_checksum(&self.pools, checksum).await
+    }
+
+    async fn mark_missing_deleted(&self, seen_ids: &[ItemId]) -> Result<u64> {
+        let missing = db::find_ids_not_in(&self.pools, seen_ids).await?;
+        db::mark_deleted(&self.pools, &missing).await
+    }
+
+    fn stream_all<'a>(&'a self, filter: &'a QueryFilter) -> BoxStream<'a, Result<ReadingItem>> {
+        db::stream_all(&self.pools, filter)
+    }
+
+    async fn latest_documents(
+        &self,
+        filter: &QueryFilter,
+        limit: usize,
+    ) -> Result<Vec<ReadingItem>> {
+        db::latest_documents(&self.pools, filter, limit).await
+    }
+
+    async fn locally_modified(&self) -> Result<Vec<(ReadingItem, DateTime<Utc>)>> {
+        db::get_locally_modified(&self.pools).await
+    }
+}
+
+/// Documents appended as JSON lines (in the API format, like fixture files) to
+/// a file, for syncing without a database.
+///
+/// Every save appends a line, so a document saved twice appears twice: the
+/// last line wins. Once the file grows past `max_file_bytes` it is rotated to
+/// `<path>.1`, older rotations shifting to `<path>.2` and so on, up to
+/// `max_rotations` files: the oldest rotation is then deleted, and with it the
+/// documents not saved again since. The checkpoint is kept in
+/// `<path>.checkpoint.json`.
+///
+/// Files are read and written on the blocking thread pool.
+#[derive(Clone)]
+pub struct JsonFileBackend {
+    path: PathBuf,
+    max_file_bytes: Option<u64>,
+    max_rotations: usize,
+    /// IDs of the documents in the file and its rotations.
+    ids: Arc<Mutex<HashSet<ItemId>>>,
+}
+
+/// Rotations kept by default by [`JsonFileBackend`].
+pub const DEFAULT_MAX_ROTATIONS: usize = 10;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct JsonCheckpoint {
+    last_sync_at: Option<DateTime<Utc>>,
+    page_cursor: Option<PageCursor>,
+}
+
+/// The only field of a stored document needed to know what is stored.
+#[derive(Deserialize)]
+struct JsonDocumentId {
+    id: ItemId,
+}
+
+impl JsonFileBackend {
+    /// Use the file at `path`, creating it on the first save.
+    pub fn open(path: impl Into<PathBuf>, max_file_bytes: Option<u64>) -> Result<Self> {
+        let backend = Self {
+            path: path.into(),
+            max_file_bytes,
+            max_rotations: DEFAULT_MAX_ROTATIONS,
+            ids: Arc::default(),
+        };
+        *backend.ids.lock().unwrap() = backend.read_ids()?;
+        Ok(backend)
+    }
+
+    /// Keep at most `max_rotations` rotated files, at least one.
+    pub fn with_max_rotations(mut self, max_rotations: usize) -> Self {
+        self.max_rotations = max_rotations.max(1);
+        self
+    }
+
+    /// Run `f` on the blocking thread pool, so that file I/O doesn't stall
+    /// the async workers.
+    async fn blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Self) -> Result<T> + Send + 'static,
+    {
+        let backend = self.clone();
+        tokio::task::spawn_blocking(move || f(&backend)).await?
+    }
+
+    fn checkpoint_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".checkpoint.json");
+        path.into()
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    fn rotations(&self) -> usize {
+        (1..).take_while(|&n| self.rotated_path(n).exists()).count()
+    }
+
+    /// The file and its rotations, oldest first.
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = (1..=self.rotations())
+            .rev()
+            .map(|n| self.rotated_path(n))
+            .collect();
+        if self.path.exists() {
+            files.push(self.path.clone());
+        }
+        files
+    }
+
+    /// Parse every non-empty line of the file and its rotations, oldest first.
+    fn read_lines<T: serde::de::DeserializeOwned>(&self, mut f: impl FnMut(T)) -> Result<()> {
+        for path in self.files() {
+            let file =
+                File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+            for (n, line) in BufReader::new(file).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let value = serde_json::from_str(&line)
+                    .with_context(|| format!("Invalid document at {}:{}", path.display(), n + 1))?;
+                f(value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Latest version of every stored document.
+    fn read_all(&self) -> Result<HashMap<ItemId, ReadingItem>> {
+        let mut items = HashMap::new();
+        self.read_lines(|result: ApiResult| {
+            let item = ReadingItem::from(result);
+            items.insert(ItemId::from(item.id.as_str()), item);
+        })?;
+        Ok(items)
+    }
+
+    /// IDs of the stored documents, without keeping the documents in memory.
+    fn read_ids(&self) -> Result<HashSet<ItemId>> {
+        let mut ids = HashSet::new();
+        self.read_lines(|doc: JsonDocumentId| {
+            ids.insert(doc.id);
+        })?;
+        Ok(ids)
+    }
+
+    fn read_checkpoint(&self) -> Result<JsonCheckpoint> {
+        let path = self.checkpoint_path();
+        if !path.exists() {
+            return Ok(JsonCheckpoint::default());
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&text)
+            .with_context(|| format!("Invalid checkpoint {}", path.display()))
+    }
+
+    fn write_checkpoint(&self, checkpoint: &JsonCheckpoint) -> Result<()> {
+        let path = self.checkpoint_path();
+        fs::write(&path, serde_json::to_vec(checkpoint)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Append `items` and flush, rotating the file first if it is too large.
+    fn append(&self, items: &[ReadingItem]) -> Result<Vec<SaveOutcome>> {
+        // Held while writing, so that concurrent saves don't interleave lines.
+        let mut ids = self.ids.lock().unwrap();
+        if self.rotate_if_full()? {
+            *ids = self.read_ids()?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        let mut writer = BufWriter::new(file);
+        let mut outcomes = Vec::with_capacity(items.len());
+        for item in items {
+            serde_json::to_writer(&mut writer, &ApiResult::from(item.clone()))?;
+            writer.write_all(b"\n")?;
+            outcomes.push(if ids.insert(ItemId::from(item.id.as_str())) {
+                SaveOutcome::Inserted
+            } else {
+                SaveOutcome::Updated
+            });
+        }
+        writer.flush()?;
+        Ok(outcomes)
+    }
+
+    /// Rotate the file if it is too large, returning whether the oldest
+    /// rotations were deleted to make room.
+    fn rotate_if_full(&self) -> Result<bool> {
+        let Some(max) = self.max_file_bytes else {
+            return Ok(false);
+        };
+        let size = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return Ok(false),
+        };
+        if size < max {
+            return Ok(false);
+        }
+        let rotations = self.rotations();
+        for n in (self.max_rotations..=rotations).rev() {
+            let path = self.rotated_path(n);
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to delete {}", path.display()))?;
+            log::info!("Deleted {}", path.display());
+        }
+        for n in (1..=rotations.min(self.max_rotations - 1)).rev() {
+            fs::rename(self.rotated_path(n), self.rotated_path(n + 1))?;
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        log::info!(
+            "Rotated {} after reaching {size} bytes",
+            self.path.display()
+        );
+        Ok(rotations >= self.max_rotations)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for JsonFileBackend {
+    async fn save(&self, item: &ReadingItem) -> Result<SaveOutcome> {
+        let outcomes = self.save_batch(std::slice::from_ref(item)).await?;
+        Ok(outcomes[0])
+    }
+
+    async fn save_batch(&self, items: &[ReadingItem]) -> Result<Vec<SaveOutcome>> {
+        let items = items.to_vec();
+        self.blocking(move |backend| backend.append(&items)).await
+    }
+
+    async fn load_checkpoint(&self) -> Result<Option<DateTime<Utc>>> {
+        let checkpoint = self.blocking(Self::read_checkpoint).await?;
+        Ok(checkpoint.last_sync_at)
+    }
+
+    async fn save_checkpoint(&self, ts: &DateTime<Utc>, cursor: Option<&PageCursor>) -> Result<()> {
+        let checkpoint = JsonCheckpoint {
+            last_sync_at: Some(*ts),
+            page_cursor: cursor.cloned(),
+        };
+        self.blocking(move |backend| backend.write_checkpoint(&checkpoint))
+            .await
+    }
+
+    async fn clear_checkpoint(&self) -> Result<()> {
+        self.blocking(|backend| backend.write_checkpoint(&JsonCheckpoint::default()))
+            .await
+    }
+
+    async fn load_page_cursor(&self) -> Result<Option<PageCursor>> {
+        let checkpoint = self.blocking(Self::read_checkpoint).await?;
+        Ok(checkpoint.page_cursor)
+    }
+
+    async fn save_page_cursor(&self, cursor: Option<&PageCursor>) -> Result<()> {
+        let cursor = cursor.cloned();
+        self.blocking(move |backend| {
+            let checkpoint = JsonCheckpoint {
+                page_cursor: cursor,
+                ..backend.read_checkpoint()?
+            };
+            backend.write_checkpoint(&checkpoint)
+        })
+        .await
+    }
+
+    fn stream_all<'a>(&'a self, filter: &'a QueryFilter) -> BoxStream<'a, Result<ReadingItem>> {
+        Box::pin(try_stream! {
+            let mut items = self
+                .blocking(Self::read_all)
+                .await?
+                .into_values()
+                .filter(|item| filter.matches(item))
+                .collect::<Vec<_>>();
+            items.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+            if let Some(limit) = filter.limit {
+                items.truncate(usize::try_from(limit).unwrap_or(0));
+            }
+            for item in items {
+                yield item;
+            }
+        })
+    }
+}
+
+/// Where [`sync`](crate::sync) stores documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StorageKind {
+    /// The `reading` table of the PostgreSQL database
+    Postgres,
+    /// A JSON Lines file, see --output
+    Json,
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::testing::ReaderResultBuilder;
+
+    #[tokio::test]
+    async fn json_file_keeps_last_version_across_rotations() {
+        let dir = std::env::temp_dir().join(format!("reader-sync-json-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("reading.jsonl");
+
+        let backend = JsonFileBackend::open(&path, Some(1)).unwrap();
+        let first = ReaderResultBuilder::new().id("doc-1").build();
+        let renamed = ReaderResultBuilder::new()
+            .id("doc-1")
+            .title("Renamed")
+            .build();
+        let other = ReaderResultBuilder::new().id("doc-2").build();
+        assert_eq!(backend.save(&first).await.unwrap(), SaveOutcome::Inserted);
+        assert_eq!(backend.save(&renamed).await.unwrap(), SaveOutcome::Updated);
+        assert_eq!(backend.save(&other).await.unwrap(), SaveOutcome::Inserted);
+        assert!(backend.rotated_path(2).exists());
+
+        let ts = Utc::now();
+        backend.save_checkpoint(&ts, None).await.unwrap();
+
+        let reopened = JsonFileBackend::open(&path, None).unwrap();
+        let all = QueryFilter::default();
+        let items: Vec<_> = reopened.stream_all(&all).try_collect().await.unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items.iter().find(|i| i.id == "doc-1").unwrap().title,
+            "Renamed"
+        );
+        assert_eq!(reopened.load_checkpoint().await.unwrap(), Some(ts));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn json_file_deletes_the_oldest_rotations() {
+        let dir =
+            std::env::temp_dir().join(format!("reader-sync-rotations-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("reading.jsonl");
+
+        let backend = JsonFileBackend::open(&path, Some(1))
+            .unwrap()
+            .with_max_rotations(2);
+        for id in ["doc-1", "doc-2", "doc-3", "doc-4"] {
+            let item = ReaderResultBuilder::new().id(id).build();
+            backend.save(&item).await.unwrap();
+        }
+        assert!(backend.rotated_path(2).exists());
+        assert!(!backend.rotated_path(3).exists());
+
+        let all = QueryFilter::default();
+        let items: Vec<_> = backend.stream_all(&all).try_collect().await.unwrap();
+        let mut ids: Vec<_> = items.iter().map(|i| i.id.as_str()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, ["doc-2", "doc-3", "doc-4"]);
+
+        // doc-1 went with the deleted rotation, so it is new again.
+        let first = ReaderResultBuilder::new().id("doc-1").build();
+        assert_eq!(backend.save(&first).await.unwrap(), SaveOutcome::Inserted);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
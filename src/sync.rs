@@ -0,0 +1,744 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::api::{self, ApiClient, HttpClient, PaginateOpts, RateLimitInfo};
+use crate::db::{QueryFilter, StorageStats};
+use crate::error::SyncError;
+use crate::models::{Category, ItemId, PageCursor, ReadingItem};
+use crate::storage::{SaveOutcome, StorageBackend};
+
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Ignore the checkpoint and fetch every document.
+    pub full_sync: bool,
+    /// Maximum number of documents saved concurrently when a page fails to
+    /// save as one batch.
+    pub workers: usize,
+    /// Documents fetched from the API but not matching this filter are not
+    /// saved. The API has no such filters, so this is applied client-side,
+    /// except for the category which is also requested from the API.
+    pub filter: QueryFilter,
+    /// Skip documents failing [`ReadingItem::validate`](crate::models::ReadingItem::validate), counting them as failed.
+    pub strict_validation: bool,
+    /// Stop after this long, saving the page cursor so the next run resumes from it.
+    pub timeout: Option<Duration>,
+    /// Also fetch documents updated this long before the checkpoint, which
+    /// would be missed if the clocks here and at Readwise disagree.
+    pub overlap_buffer: Duration,
+    /// Push documents edited in the storage to Readwise before pulling,
+    /// resolving conflicting edits with this strategy.
+    pub two_way: Option<ConflictResolution>,
+}
+
+/// What to do with a document edited both in the database and in Readwise
+/// since the last sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConflictResolution {
+    /// Push the local edit, overwriting the Readwise one
+    #[value(name = "local")]
+    LocalWins,
+    /// Discard the local edit
+    #[value(name = "remote")]
+    RemoteWins,
+    /// Keep the most recent edit
+    Newest,
+}
+
+/// Outcome of a sync run.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SyncResult {
+    /// Documents that were not stored before this run.
+    pub items_new: usize,
+    /// Stored documents that changed since they were last saved.
+    pub items_updated: usize,
+    pub items_saved: usize,
+    pub items_failed: usize,
+    /// Documents left out by `SyncOptions::filter`.
+    pub items_skipped: usize,
+    /// Documents whose `reading_progress` was brought back into [0, 1].
+    pub progress_clamped: usize,
+    /// Stored documents soft-deleted because a full sync no longer got them
+    /// from Readwise.
+    pub items_deleted: usize,
+    /// Local edits pushed to Readwise by a two-way sync.
+    pub items_pushed: usize,
+    /// Documents edited on both sides, whatever the resolution.
+    pub conflicts: usize,
+    pub pages: usize,
+    /// The run stopped at `SyncOptions::timeout` before fetching every page.
+    pub timed_out: bool,
+    /// Page the next run resumes from, when the run timed out.
+    pub cursor: Option<PageCursor>,
+    /// Checkpoint the next incremental sync starts from.
+    pub last_sync_at: Option<DateTime<Utc>>,
+    /// New, updated and failed documents of each category synced.
+    pub by_category: BTreeMap<Category, CategoryCounts>,
+}
+
+/// Documents of one category handled by a sync run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CategoryCounts {
+    pub new: usize,
+    pub updated: usize,
+    pub failed: usize,
+}
+
+/// What a sync run did, with the state of the API and the database after it,
+/// for the summary printed when it ends.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncReport {
+    pub result: SyncResult,
+    pub duration_ms: u128,
+    /// Latest rate limit state sent by the API, if it sent any.
+    pub rate_limit: Option<RateLimitInfo>,
+    /// Unknown for storages other than PostgreSQL.
+    pub storage: Option<StorageStats>,
+    /// When the daemon syncs next.
+    pub next_sync_at: Option<DateTime<Utc>>,
+}
+
+/// Fetch every document updated since the last checkpoint and save it.
+pub async fn run<C: HttpClient + Clone + 'static>(
+    storage: &Arc<dyn StorageBackend>,
+    client: &ApiClient<C>,
+    options: &SyncOptions,
+) -> Result<SyncResult> {
+    let mut result = SyncResult::default();
+    if let Some(resolution) = options.two_way {
+        let last_sync_at = storage.load_checkpoint().await?;
+        push_local_changes(storage, client, last_sync_at, resolution, &mut result).await?;
+    }
+
+    let checkpoint = if options.full_sync {
+        info!("Full sync requested — clearing checkpoint.");
+        storage.clear_checkpoint().await?;
+        None
+    } else {
+        let checkpoint = storage.load_checkpoint().await?;
+        if checkpoint.is_none() {
+            info!("No checkpoint found — performing full sync.");
+        }
+        checkpoint
+    };
+    let updated_after = checkpoint.map(|ts| {
+        let since = ts - options.overlap_buffer;
+        info!("Incremental sync: last sync at {ts}, fetching items updated since {since}");
+        since
+    });
+
+    let mut cursor = storage.load_page_cursor().await?;
+    if cursor.is_some() {
+        info!("Resuming interrupted sync from saved page cursor.");
+    }
+
+    // Record start time before the sync so we don't miss documents
+    // updated while the sync is in progress.
+    let sync_started_at = Utc::now();
+    let first_run = !options.full_sync && updated_after.is_none();
+    // Only a sync listing every document, from the first page on, can tell
+    // which stored documents Readwise no longer has.
+    let lists_everything =
+        updated_after.is_none() && cursor.is_none() && options.filter.category.is_none();
+
+    let pages = sync_pages(
+        storage,
+        client,
+        options,
+        updated_after.as_ref(),
+        &mut cursor,
+        first_run,
+        &mut result,
+    );
+    let seen_ids = match options.timeout {
+        Some(timeout) => {
+            if let Ok(synced) = tokio::time::timeout(timeout, pages).await {
+                synced?
+            } else {
+                storage.save_page_cursor(cursor.as_ref()).await?;
+                warn!(
+                    "Sync timeout reached after {} seconds, progress saved at {}",
+                    timeout.as_secs(),
+                    cursor
+                        .as_ref()
+                        .map_or_else(|| String::from("the start"), |c| format!("{c:?}"))
+                );
+                result.timed_out = true;
+                result.cursor = cursor;
+                result.last_sync_at = checkpoint;
+                return Ok(result);
+            }
+        }
+        None => pages.await?,
+    };
+
+    if lists_everything {
+        let deleted = storage.mark_missing_deleted(&seen_ids).await?;
+        if deleted > 0 {
+            info!("{deleted} documents no longer in Readwise marked as deleted");
+        }
+        result.items_deleted = usize::try_from(deleted).unwrap_or(usize::MAX);
+    }
+
+    // Every save of the last page was awaited, so the documents are committed
+    // before the checkpoint, but not atomically with it: after a crash in
+    // between, the next sync fetches them again, which the upserts allow.
+    storage.save_checkpoint(&sync_started_at, None).await?;
+    info!("Checkpoint saved: {sync_started_at}");
+    result.last_sync_at = Some(sync_started_at);
+    info!(
+        "{} new documents, {} updated",
+        result.items_new, result.items_updated
+    );
+
+    Ok(result)
+}
+
+/// A recurring sync of one category, or of every category when `category` is
+/// `None`.
+pub struct Schedule {
+    pub category: Option<Category>,
+    pub interval: Duration,
+    /// Storage keeping the checkpoint of this category apart from the others.
+    pub storage: Arc<dyn StorageBackend>,
+}
+
+/// Sync each schedule at its own interval, forever. At most `options.workers`
+/// syncs run at the same time; a failed sync is logged and retried at its
+/// next interval, unless it failed on a
+/// [configuration error](SyncError::is_configuration_error), which is returned.
+pub async fn run_scheduled<C: HttpClient + Clone + 'static>(
+    schedules: Vec<Schedule>,
+    client: &ApiClient<C>,
+    options: &SyncOptions,
+) -> Result<()> {
+    let permits = Arc::new(Semaphore::new(options.workers));
+    let mut tasks = JoinSet::new();
+    for schedule in schedules {
+        let mut options = options.clone();
+        options.filter.category = schedule.category;
+        if schedule.category.is_some() {
+            // Local edits are pushed once, by the sync of every category.
+            options.two_way = None;
+        }
+        info!(
+            "Syncing {} every {}s",
+            schedule.category.map_or("all categories", |c| c.as_str()),
+            schedule.interval.as_secs()
+        );
+        tasks.spawn(run_every(
+            schedule,
+            client.clone(),
+            options,
+            Arc::clone(&permits),
+        ));
+    }
+    while let Some(task) = tasks.join_next().await {
+        task??;
+    }
+    Ok(())
+}
+
+async fn run_every<C: HttpClient + Clone + 'static>(
+    schedule: Schedule,
+    client: ApiClient<C>,
+    mut options: SyncOptions,
+    permits: Arc<Semaphore>,
+) -> Result<()> {
+    let label = schedule.category.map_or("all categories", |c| c.as_str());
+    let mut interval = tokio::time::interval(schedule.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let _permit = permits.acquire().await?;
+        match run(&schedule.storage, &client, &options).await {
+            Ok(result) => info!(
+                "Synced {label}: {} saved, {} new, {} failed",
+                result.items_saved, result.items_new, result.items_failed
+            ),
+            Err(e)
+                if e.downcast_ref::<SyncError>()
+                    .is_some_and(SyncError::is_configuration_error) =>
+            {
+                return Err(e);
+            }
+            Err(e) => error!("Sync of {label} failed: {e:#}"),
+        }
+        // A full sync is only wanted once.
+        options.full_sync = false;
+    }
+}
+
+/// Push documents edited in the storage to Readwise.
+///
+/// This runs before pulling: a pull overwrites local edits, and a document
+/// edited in Readwise since `last_sync_at` is only recognisable before the pull
+/// advances the checkpoint. Pushed documents come back with the next pull.
+async fn push_local_changes<C: HttpClient + Clone + 'static>(
+    storage: &Arc<dyn StorageBackend>,
+    client: &ApiClient<C>,
+    last_sync_at: Option<DateTime<Utc>>,
+    resolution: ConflictResolution,
+    result: &mut SyncResult,
+) -> Result<()> {
+    let modified = storage.locally_modified().await?;
+    if !modified.is_empty() {
+        info!(
+            "Pushing {} locally modified documents to Readwise",
+            modified.len()
+        );
+    }
+
+    for (local, local_updated_at) in modified {
+        let id = ItemId::from(local.id.as_str());
+        let remote = {
+            let (client, id) = (client.clone(), id.clone());
+            tokio::task::spawn_blocking(move || api::get_single_item(&client, &id)).await??
+        };
+        let Some(remote) = remote else {
+            warn!("Locally modified document {id} no longer exists in Readwise");
+            continue;
+        };
+
+        // Without a checkpoint there is no telling what changed remotely.
+        let remote_changed = match (last_sync_at, remote.updated_at) {
+            (Some(last_sync_at), Some(remote_updated_at)) => remote_updated_at > last_sync_at,
+            _ => true,
+        };
+        let push = if remote_changed {
+            result.conflicts += 1;
+            let push = match resolution {
+                ConflictResolution::LocalWins => true,
+                ConflictResolution::RemoteWins => false,
+                ConflictResolution::Newest => remote
+                    .updated_at
+                    .is_none_or(|remote| local_updated_at > remote),
+            };
+            warn!(
+                "Document {id} was edited both locally and in Readwise, keeping the {} version",
+                if push { "local" } else { "Readwise" }
+            );
+            push
+        } else {
+            true
+        };
+
+        if push {
+            let opts = api::UpdateItemOptions {
+                title: Some(local.title.clone()),
+                author: local.author.clone(),
+                summary: local.summary.clone(),
+                notes: local.notes.clone(),
+                location: local.location,
+                category: Some(local.category),
+                tags: Some(local.tag_names()),
+            };
+            let client = client.clone();
+            tokio::task::spawn_blocking(move || api::update_item(&client, &id, &opts)).await??;
+            result.items_pushed += 1;
+        } else {
+            // Overwrite the local edit, which also clears `local_updated_at`.
+            storage.save(&remote).await?;
+        }
+    }
+    Ok(())
+}
+
+/// What saving a document did, with its category and title.
+type Saved = (Category, Result<(String, SaveOutcome)>);
+
+/// Save `items` concurrently, at most `semaphore` permits at a time, so that
+/// a document failing to save doesn't keep the others of its page from being
+/// saved.
+async fn save_one_by_one(
+    storage: &Arc<dyn StorageBackend>,
+    semaphore: &Arc<Semaphore>,
+    items: Vec<ReadingItem>,
+) -> Result<Vec<Saved>> {
+    let mut saves = JoinSet::new();
+    for item in items {
+        let permit = Arc::clone(semaphore).acquire_owned().await?;
+        let storage = Arc::clone(storage);
+        saves.spawn(async move {
+            let _permit = permit;
+            let saved = storage.save(&item).await;
+            (item.category, saved.map(|outcome| (item.title, outcome)))
+        });
+    }
+    let mut saved = Vec::new();
+    while let Some(item) = saves.join_next().await {
+        saved.push(item?);
+    }
+    Ok(saved)
+}
+
+/// Fetch and save pages until the API runs out of them. Returns the ID of
+/// every document fetched, saved or not.
+///
+/// The documents of a page are saved as one batch. If the batch fails, they
+/// are saved one at a time instead, at most `options.workers` concurrently.
+/// `cursor` only advances once every item of a page has been handled, so that
+/// an interrupted sync can resume from the first page it did not finish.
+async fn sync_pages<C: HttpClient + Clone + 'static>(
+    storage: &Arc<dyn StorageBackend>,
+    client: &ApiClient<C>,
+    options: &SyncOptions,
+    updated_after: Option<&DateTime<Utc>>,
+    cursor: &mut Option<PageCursor>,
+    first_run: bool,
+    result: &mut SyncResult,
+) -> Result<Vec<ItemId>> {
+    let mut seen_ids = Vec::new();
+    let semaphore = Arc::new(Semaphore::new(options.workers));
+    let pages = api::paginate_all(
+        client,
+        PaginateOpts {
+            cursor: cursor.clone(),
+            updated_after: updated_after.copied(),
+            category: options.filter.category,
+            ..Default::default()
+        },
+    );
+    let mut pages = std::pin::pin!(pages);
+    let mut schema_checksum = storage.load_schema_checksum().await?;
+
+    loop {
+        info!("Requesting Readwise API...");
+        let fetch_started_at = Instant::now();
+        let Some(page) = pages.next().await else {
+            return Ok(seen_ids);
+        };
+        let page = page?;
+
+        if result.pages == 0 && first_run {
+            let estimate = estimate_sync_duration(
+                page.total_remaining,
+                page.per_page_count(),
+                fetch_started_at.elapsed(),
+            );
+            info!(
+                "First sync detected: ~{} items to sync, estimated {} minutes at current rate",
+                page.total_remaining,
+                estimate.as_secs().div_ceil(60)
+            );
+        }
+        result.pages += 1;
+
+        if let Some(checksum) = page.schema_checksum.as_deref()
+            && schema_checksum.as_deref() != Some(checksum)
+        {
+            if let Some(old) = &schema_checksum {
+                warn!("API response schema changed: {old} → {checksum}");
+            }
+            storage.save_schema_checksum(checksum).await?;
+            schema_checksum = Some(checksum.to_string());
+        }
+
+        info!("{} items in total", page.total_remaining);
+        info!("Saving {} items to database...", page.per_page_count());
+
+        let mut items = Vec::with_capacity(page.results.len());
+        for mut item in page.results.into_iter().map(ReadingItem::from) {
+            seen_ids.push(ItemId::from(item.id.as_str()));
+            if item.clamp_reading_progress() {
+                result.progress_clamped += 1;
+            }
+            if !options.filter.matches(&item) {
+                debug!("Skipped by filter: {}", item.title);
+                result.items_skipped += 1;
+                continue;
+            }
+            let warnings = item.validate();
+            if options.strict_validation && !warnings.is_empty() {
+                for warning in warnings {
+                    error!("Document {}: {warning}", item.id);
+                }
+                result.items_failed += 1;
+                result.by_category.entry(item.category).or_default().failed += 1;
+                continue;
+            }
+            for warning in warnings {
+                warn!("Document {}: {warning}", item.id);
+            }
+            items.push(item);
+        }
+
+        let saved = match storage.save_batch(&items).await {
+            Ok(outcomes) => items
+                .into_iter()
+                .zip(outcomes)
+                .map(|(item, outcome)| (item.category, Ok((item.title, outcome))))
+                .collect(),
+            Err(e) => {
+                warn!("{e:#}. Saving the documents of this page one at a time");
+                save_one_by_one(storage, &semaphore, items).await?
+            }
+        };
+
+        let mut failures = 0usize;
+        for (category, saved) in saved {
+            let counts = result.by_category.entry(category).or_default();
+            match saved {
+                Ok((title, outcome)) => {
+                    debug!("Synced: {title}");
+                    result.items_saved += 1;
+                    match outcome {
+                        SaveOutcome::Inserted => {
+                            result.items_new += 1;
+                            counts.new += 1;
+                        }
+                        SaveOutcome::Updated => {
+                            result.items_updated += 1;
+                            counts.updated += 1;
+                        }
+                        SaveOutcome::Unchanged => {}
+                    }
+                }
+                Err(e) => {
+                    error!("{e:#}");
+                    failures += 1;
+                    counts.failed += 1;
+                }
+            }
+        }
+        if failures > 0 {
+            warn!("{failures} document(s) failed to save on this page");
+            result.items_failed += failures;
+        }
+
+        match page.next_page_cursor {
+            Some(next) => *cursor = Some(next),
+            None => return Ok(seen_ids),
+        }
+    }
+}
+
+/// Extrapolate the total sync duration from the time taken to fetch one page.
+fn estimate_sync_duration(total_items: usize, page_size: usize, page_time: Duration) -> Duration {
+    if page_size == 0 {
+        return Duration::ZERO;
+    }
+    let pages = total_items.div_ceil(page_size);
+    page_time * u32::try_from(pages).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::models::ApiResult;
+    use crate::testing::{InMemoryBackend, MockHttpClient, ReaderResultBuilder, api_page, token};
+
+    fn options() -> SyncOptions {
+        SyncOptions {
+            full_sync: false,
+            workers: 1,
+            filter: QueryFilter::default(),
+            strict_validation: false,
+            timeout: None,
+            two_way: None,
+            overlap_buffer: Duration::from_secs(300),
+        }
+    }
+
+    #[tokio::test]
+    async fn documents_updated_just_before_the_checkpoint_are_fetched() {
+        let checkpoint = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        // Saved by Readwise one minute before the checkpoint, by its clock.
+        let late = ReaderResultBuilder::new()
+            .id("late")
+            .updated_at(checkpoint - Duration::from_secs(60))
+            .build();
+        let page = serde_json::json!({
+            "count": 1,
+            "nextPageCursor": null,
+            "results": [ApiResult::from(late)],
+        });
+        let http = MockHttpClient::new().respond(&page.to_string());
+        let client = ApiClient::with_http_client(http.clone(), token());
+        let memory = Arc::new(InMemoryBackend::new());
+        memory.save_checkpoint(&checkpoint, None).await.unwrap();
+        let storage: Arc<dyn StorageBackend> = memory.clone();
+
+        let result = run(&storage, &client, &options()).await.unwrap();
+
+        assert!(http.requests()[0].contains("updatedAfter=2024-03-01T11%3A55%3A00Z"));
+        assert_eq!(result.items_new, 1);
+        assert_eq!(
+            result.by_category[&Category::Article],
+            CategoryCounts {
+                new: 1,
+                ..Default::default()
+            }
+        );
+        assert!(memory.get("late").is_some());
+        assert!(memory.load_checkpoint().await.unwrap().unwrap() > checkpoint);
+    }
+
+    #[tokio::test]
+    async fn full_syncs_soft_delete_documents_gone_from_readwise() {
+        let kept = ReaderResultBuilder::new().id("kept").build();
+        let gone = ReaderResultBuilder::new().id("gone").build();
+        let memory = Arc::new(InMemoryBackend::new());
+        memory.save(&kept).await.unwrap();
+        memory.save(&gone).await.unwrap();
+        let storage: Arc<dyn StorageBackend> = memory.clone();
+        let http = MockHttpClient::new().respond(&api_page(&[kept]));
+        let client = ApiClient::with_http_client(http, token());
+        let options = SyncOptions {
+            full_sync: true,
+            ..options()
+        };
+
+        let result = run(&storage, &client, &options).await.unwrap();
+
+        assert_eq!(result.items_deleted, 1);
+        assert!(memory.is_deleted("gone"));
+        assert!(!memory.is_deleted("kept"));
+    }
+
+    #[tokio::test]
+    async fn partial_syncs_delete_nothing() {
+        let memory = Arc::new(InMemoryBackend::new());
+        memory
+            .save(&ReaderResultBuilder::new().id("stored").build())
+            .await
+            .unwrap();
+        memory
+            .save_checkpoint(&Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(), None)
+            .await
+            .unwrap();
+        let storage: Arc<dyn StorageBackend> = memory.clone();
+        let http = MockHttpClient::new()
+            .respond(&api_page(&[]))
+            .respond(&api_page(&[]));
+        let client = ApiClient::with_http_client(http, token());
+
+        // Incremental, then full but restricted to a category.
+        let result = run(&storage, &client, &options()).await.unwrap();
+        assert_eq!(result.items_deleted, 0);
+        let mut options = SyncOptions {
+            full_sync: true,
+            ..options()
+        };
+        options.filter.category = Some(Category::Pdf);
+        let result = run(&storage, &client, &options).await.unwrap();
+        assert_eq!(result.items_deleted, 0);
+        assert!(!memory.is_deleted("stored"));
+    }
+
+    /// A document edited locally, then in Readwise after the last sync.
+    async fn conflicting_edit(
+        resolution: ConflictResolution,
+    ) -> (Arc<InMemoryBackend>, MockHttpClient, SyncResult) {
+        let last_sync_at = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let local = ReaderResultBuilder::new()
+            .id("doc")
+            .title("Local title")
+            .build();
+        let remote = ReaderResultBuilder::new()
+            .id("doc")
+            .title("Readwise title")
+            .updated_at(last_sync_at + Duration::from_secs(7200))
+            .build();
+        let memory = Arc::new(InMemoryBackend::new());
+        memory.edit(&local, last_sync_at + Duration::from_secs(3600));
+        let storage: Arc<dyn StorageBackend> = memory.clone();
+        let http = MockHttpClient::new()
+            .respond(&api_page(&[remote]))
+            .respond("{}");
+        let client = ApiClient::with_http_client(http.clone(), token());
+        let mut result = SyncResult::default();
+
+        push_local_changes(
+            &storage,
+            &client,
+            Some(last_sync_at),
+            resolution,
+            &mut result,
+        )
+        .await
+        .unwrap();
+        (memory, http, result)
+    }
+
+    #[tokio::test]
+    async fn local_wins_pushes_the_local_edit() {
+        let (memory, http, result) = conflicting_edit(ConflictResolution::LocalWins).await;
+
+        assert_eq!(result.conflicts, 1);
+        assert_eq!(result.items_pushed, 1);
+        assert_eq!(http.requests().len(), 2);
+        assert!(http.requests()[1].starts_with("PATCH "));
+        assert!(http.requests()[1].contains("/doc/"));
+        assert_eq!(memory.get("doc").unwrap().title, "Local title");
+    }
+
+    #[tokio::test]
+    async fn remote_wins_discards_the_local_edit() {
+        let (memory, http, result) = conflicting_edit(ConflictResolution::RemoteWins).await;
+
+        assert_eq!(result.conflicts, 1);
+        assert_eq!(result.items_pushed, 0);
+        assert_eq!(http.requests().len(), 1);
+        assert_eq!(memory.get("doc").unwrap().title, "Readwise title");
+        assert!(!memory.is_edited("doc"));
+    }
+
+    #[tokio::test]
+    async fn strict_validation_fails_suspicious_documents() {
+        let untitled = ReaderResultBuilder::new().id("untitled").title("").build();
+        let fine = ReaderResultBuilder::new().id("fine").build();
+        let page = api_page(&[untitled, fine]);
+
+        for strict_validation in [false, true] {
+            let memory = Arc::new(InMemoryBackend::new());
+            let storage: Arc<dyn StorageBackend> = memory.clone();
+            let client = ApiClient::with_http_client(MockHttpClient::new().respond(&page), token());
+            let options = SyncOptions {
+                strict_validation,
+                ..options()
+            };
+
+            let result = run(&storage, &client, &options).await.unwrap();
+
+            assert_eq!(result.items_failed, usize::from(strict_validation));
+            assert_eq!(memory.get("untitled").is_some(), !strict_validation);
+            assert!(memory.get("fine").is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn scheduled_syncs_stop_on_a_rejected_token() {
+        let http = MockHttpClient::new().respond_with(401, "");
+        let client = ApiClient::with_http_client(http.clone(), token());
+        let schedule = Schedule {
+            category: None,
+            interval: Duration::from_millis(10),
+            storage: Arc::new(InMemoryBackend::new()),
+        };
+
+        let stopped = tokio::time::timeout(
+            Duration::from_secs(5),
+            run_scheduled(vec![schedule], &client, &options()),
+        )
+        .await
+        .expect("the schedule kept running");
+
+        let error = stopped.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(SyncError::Unauthorized)
+        ));
+        assert_eq!(http.requests().len(), 1);
+    }
+}
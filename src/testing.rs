@@ -0,0 +1,478 @@
+//! Test data helpers, available to integration tests through the
+//! `test-helpers` feature.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream};
+use serde_json::Value;
+
+use crate::api::{HttpClient, HttpRequest, HttpResponse};
+use crate::db::QueryFilter;
+use crate::error::SyncError;
+use crate::models::{AccessToken, ApiResult, Category, ItemId, Location, PageCursor, ReadingItem};
+use crate::storage::{SaveOutcome, StorageBackend};
+
+/// Chainable constructor for [`ReadingItem`].
+///
+/// The default value is a minimal valid article, so tests only need to set the
+/// fields they care about:
+///
+/// ```ignore
+/// let highlight = ReaderResultBuilder::new()
+///     .id("hl-1")
+///     .category(Category::Highlight)
+///     .parent_id("doc-1")
+///     .build();
+/// ```
+pub struct ReaderResultBuilder {
+    result: ReadingItem,
+}
+
+impl Default for ReaderResultBuilder {
+    fn default() -> Self {
+        Self {
+            result: ReadingItem {
+                author: None,
+                category: Category::Article,
+                content: None,
+                created_at: DateTime::UNIX_EPOCH,
+                id: String::from("test-item"),
+                image_url: None,
+                location: Some(Location::New),
+                notes: None,
+                parent_id: None,
+                published_date: None,
+                reading_progress: 0.0,
+                site_name: None,
+                source: None,
+                source_url: None,
+                summary: None,
+                tags: None,
+                title: String::from("Untitled"),
+                updated_at: None,
+                readwise_url: None,
+                word_count: 0,
+            },
+        }
+    }
+}
+
+impl ReaderResultBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn build(self) -> ReadingItem {
+        self.result
+    }
+
+    pub fn author(mut self, author: impl Into<String>) -> Self {
+        self.result.author = Some(author.into());
+        self
+    }
+
+    pub fn category(mut self, category: Category) -> Self {
+        self.result.category = category;
+        self
+    }
+
+    pub fn content(mut self, content: impl Into<String>) -> Self {
+        self.result.content = Some(content.into());
+        self
+    }
+
+    pub fn created_at(mut self, created_at: DateTime<Utc>) -> Self {
+        self.result.created_at = created_at;
+        self
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.result.id = id.into();
+        self
+    }
+
+    pub fn image_url(mut self, image_url: impl Into<String>) -> Self {
+        self.result.image_url = Some(image_url.into());
+        self
+    }
+
+    pub fn location(mut self, location: Option<Location>) -> Self {
+        self.result.location = location;
+        self
+    }
+
+    pub fn notes(mut self, notes: impl Into<String>) -> Self {
+        self.result.notes = Some(notes.into());
+        self
+    }
+
+    pub fn parent_id(mut self, parent_id: impl Into<String>) -> Self {
+        self.result.parent_id = Some(parent_id.into());
+        self
+    }
+
+    pub fn published_date(mut self, published_date: DateTime<Utc>) -> Self {
+        self.result.published_date = Some(published_date);
+        self
+    }
+
+    pub fn reading_progress(mut self, reading_progress: f32) -> Self {
+        self.result.reading_progress = reading_progress;
+        self
+    }
+
+    /// Panics if `readwise_url` is not a valid URL.
+    pub fn readwise_url(mut self, readwise_url: &str) -> Self {
+        self.result.readwise_url = Some(readwise_url.parse().expect("invalid readwise_url"));
+        self
+    }
+
+    pub fn site_name(mut self, site_name: impl Into<String>) -> Self {
+        self.result.site_name = Some(site_name.into());
+        self
+    }
+
+    pub fn source(mut self, source: impl Into<String>) -> Self {
+        self.result.source = Some(source.into());
+        self
+    }
+
+    /// Panics if `source_url` is not a valid URL.
+    pub fn source_url(mut self, source_url: &str) -> Self {
+        self.result.source_url = Some(source_url.parse().expect("invalid source_url"));
+        self
+    }
+
+    pub fn summary(mut self, summary: impl Into<String>) -> Self {
+        self.result.summary = Some(summary.into());
+        self
+    }
+
+    pub fn tags(mut self, tags: Value) -> Self {
+        self.result.tags = Some(tags);
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.result.title = title.into();
+        self
+    }
+
+    pub fn updated_at(mut self, updated_at: DateTime<Utc>) -> Self {
+        self.result.updated_at = Some(updated_at);
+        self
+    }
+
+    pub fn word_count(mut self, word_count: i32) -> Self {
+        self.result.word_count = word_count;
+        self
+    }
+}
+
+/// [`StorageBackend`] keeping everything in memory, to test the sync without a
+/// database. Clones share the same storage.
+#[derive(Debug, Default, Clone)]
+pub struct InMemoryBackend {
+    pub items: Arc<RwLock<HashMap<ItemId, ReadingItem>>>,
+    pub checkpoint: Arc<RwLock<Option<DateTime<Utc>>>>,
+    pub page_cursor: Arc<RwLock<Option<PageCursor>>>,
+    pub schema_checksum: Arc<RwLock<Option<String>>>,
+    /// Soft-deleted documents, kept in `items` until saved again.
+    pub deleted: Arc<RwLock<HashSet<ItemId>>>,
+    /// Time of the local edit of documents edited since they were last saved.
+    pub local_edits: Arc<RwLock<HashMap<ItemId, DateTime<Utc>>>>,
+}
+
+impl InMemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The stored document with this ID.
+    pub fn get(&self, id: &str) -> Option<ReadingItem> {
+        self.items.read().unwrap().get(&ItemId::from(id)).cloned()
+    }
+
+    /// Store `item` as edited locally at `at`, like a hand edit of the database.
+    pub fn edit(&self, item: &ReadingItem, at: DateTime<Utc>) {
+        let id = ItemId::from(item.id.as_str());
+        self.items.write().unwrap().insert(id.clone(), item.clone());
+        self.local_edits.write().unwrap().insert(id, at);
+    }
+
+    /// Whether the document has a local edit not synced yet.
+    pub fn is_edited(&self, id: &str) -> bool {
+        self.local_edits
+            .read()
+            .unwrap()
+            .contains_key(&ItemId::from(id))
+    }
+
+    pub fn is_deleted(&self, id: &str) -> bool {
+        self.deleted.read().unwrap().contains(&ItemId::from(id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn save_item(items: &mut HashMap<ItemId, ReadingItem>, item: &ReadingItem) -> SaveOutcome {
+        let id = ItemId::from(item.id.as_str());
+        let mut item = item.clone();
+        // As in PostgreSQL, a missing `updated_at` keeps the stored one.
+        if item.updated_at.is_none() {
+            item.updated_at = items.get(&id).and_then(|old| old.updated_at);
+        }
+        match items.insert(id, item.clone()) {
+            None => SaveOutcome::Inserted,
+            Some(old) if old == item => SaveOutcome::Unchanged,
+            Some(_) => SaveOutcome::Updated,
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for InMemoryBackend {
+    async fn save(&self, item: &ReadingItem) -> Result<SaveOutcome> {
+        let id = ItemId::from(item.id.as_str());
+        self.deleted.write().unwrap().remove(&id);
+        self.local_edits.write().unwrap().remove(&id);
+        Ok(Self::save_item(&mut self.items.write().unwrap(), item))
+    }
+
+    async fn save_batch(&self, items: &[ReadingItem]) -> Result<Vec<SaveOutcome>> {
+        let mut stored = self.items.write().unwrap();
+        let mut deleted = self.deleted.write().unwrap();
+        let mut local_edits = self.local_edits.write().unwrap();
+        let mut outcomes = Vec::with_capacity(items.len());
+        for item in items {
+            let id = ItemId::from(item.id.as_str());
+            deleted.remove(&id);
+            local_edits.remove(&id);
+            outcomes.push(Self::save_item(&mut stored, item));
+        }
+        Ok(outcomes)
+    }
+
+    async fn load_checkpoint(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(*self.checkpoint.read().unwrap())
+    }
+
+    async fn save_checkpoint(&self, ts: &DateTime<Utc>, cursor: Option<&PageCursor>) -> Result<()> {
+        *self.checkpoint.write().unwrap() = Some(*ts);
+        *self.page_cursor.write().unwrap() = cursor.cloned();
+        Ok(())
+    }
+
+    async fn clear_checkpoint(&self) -> Result<()> {
+        *self.checkpoint.write().unwrap() = None;
+        *self.page_cursor.write().unwrap() = None;
+        Ok(())
+    }
+
+    async fn load_page_cursor(&self) -> Result<Option<PageCursor>> {
+        Ok(self.page_cursor.read().unwrap().clone())
+    }
+
+    async fn save_page_cursor(&self, cursor: Option<&PageCursor>) -> Result<()> {
+        *self.page_cursor.write().unwrap() = cursor.cloned();
+        Ok(())
+    }
+
+    async fn load_schema_checksum(&self) -> Result<Option<String>> {
+        Ok(self.schema_checksum.read().unwrap().clone())
+    }
+
+    async fn save_schema_checksum(&self, checksum: &str) -> Result<()> {
+        *self.schema_checksum.write().unwrap() = Some(checksum.to_string());
+        Ok(())
+    }
+
+    async fn mark_missing_deleted(&self, seen_ids: &[ItemId]) -> Result<u64> {
+        let seen: HashSet<&ItemId> = seen_ids.iter().collect();
+        let mut deleted = self.deleted.write().unwrap();
+        let mut marked = 0;
+        for id in self.items.read().unwrap().keys() {
+            if !seen.contains(id) && deleted.insert(id.clone()) {
+                marked += 1;
+            }
+        }
+        Ok(marked)
+    }
+
+    async fn locally_modified(&self) -> Result<Vec<(ReadingItem, DateTime<Utc>)>> {
+        let items = self.items.read().unwrap();
+        Ok(self
+            .local_edits
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(id, at)| Some((items.get(id)?.clone(), *at)))
+            .collect())
+    }
+
+    fn stream_all<'a>(&'a self, filter: &'a QueryFilter) -> BoxStream<'a, Result<ReadingItem>> {
+        let deleted = self.deleted.read().unwrap();
+        let mut items: Vec<ReadingItem> = self
+            .items
+            .read()
+            .unwrap()
+            .values()
+            .filter(|item| !deleted.contains(&ItemId::from(item.id.as_str())))
+            .filter(|item| filter.matches(item))
+            .cloned()
+            .collect();
+        items.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        if let Some(limit) = filter.limit {
+            items.truncate(usize::try_from(limit).unwrap_or(0));
+        }
+        Box::pin(stream::iter(items.into_iter().map(Ok)))
+    }
+}
+
+/// A last page of the list API holding `items`, as a [`MockHttpClient`]
+/// response.
+pub fn api_page(items: &[ReadingItem]) -> String {
+    let results: Vec<ApiResult> = items.iter().cloned().map(ApiResult::from).collect();
+    serde_json::json!({
+        "count": results.len(),
+        "nextPageCursor": null,
+        "results": results,
+    })
+    .to_string()
+}
+
+/// A well-formed access token, for API clients talking to a [`MockHttpClient`].
+pub fn token() -> AccessToken {
+    AccessToken::new("token").unwrap()
+}
+
+/// [`HttpClient`] answering requests with queued responses, in order.
+///
+/// ```ignore
+/// let http = MockHttpClient::new()
+///     .respond_with(429, "")
+///     .respond(r#"{"count": 0, "nextPageCursor": null, "results": []}"#);
+/// let client = ApiClient::with_http_client(http.clone(), token());
+/// ```
+#[derive(Clone, Default)]
+pub struct MockHttpClient {
+    responses: Arc<Mutex<VecDeque<Result<HttpResponse, SyncError>>>>,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockHttpClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a `200 OK` response.
+    pub fn respond(self, body: &str) -> Self {
+        self.respond_with(200, body)
+    }
+
+    /// Queue a response with `status`. Retryable statuses get `Retry-After: 0`
+    /// so that tests don't wait.
+    pub fn respond_with(self, status: u16, body: &str) -> Self {
+        let headers = vec![("Retry-After".to_string(), "0".to_string())];
+        self.push(Ok(HttpResponse {
+            status,
+            headers,
+            body: body.to_string(),
+            ..Default::default()
+        }))
+    }
+
+    /// Queue a network failure.
+    pub fn fail(self, error: &str) -> Self {
+        self.push(Err(SyncError::Transport(error.to_string())))
+    }
+
+    fn push(self, response: Result<HttpResponse, SyncError>) -> Self {
+        self.responses.lock().unwrap().push_back(response);
+        self
+    }
+
+    /// `METHOD url` of every request received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl HttpClient for MockHttpClient {
+    fn send(&self, request: &HttpRequest<'_>) -> Result<HttpResponse> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", request.method, request.url));
+        match self.responses.lock().unwrap().pop_front() {
+            Some(response) => Ok(response?),
+            None => Err(anyhow!("No response queued for {}", request.url)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::storage::BatchSaveResult;
+
+    #[tokio::test]
+    async fn in_memory_save_outcomes() {
+        let backend = InMemoryBackend::new();
+        let item = ReaderResultBuilder::new().id("doc-1").build();
+
+        assert_eq!(backend.save(&item).await.unwrap(), SaveOutcome::Inserted);
+        assert_eq!(backend.save(&item).await.unwrap(), SaveOutcome::Unchanged);
+        let renamed = ReaderResultBuilder::new()
+            .id("doc-1")
+            .title("Renamed")
+            .build();
+        assert_eq!(backend.save(&renamed).await.unwrap(), SaveOutcome::Updated);
+        assert_eq!(backend.get("doc-1").unwrap().title, "Renamed");
+    }
+
+    #[tokio::test]
+    async fn in_memory_batch_and_filtered_stream() {
+        let backend = InMemoryBackend::new();
+        let items = [
+            ReaderResultBuilder::new()
+                .id("doc-1")
+                .category(Category::Pdf)
+                .build(),
+            ReaderResultBuilder::new().id("doc-2").build(),
+            ReaderResultBuilder::new()
+                .id("doc-2")
+                .title("Twice")
+                .build(),
+        ];
+
+        let outcomes = backend.save_batch(&items).await.unwrap();
+        assert_eq!(
+            outcomes.into_iter().collect::<BatchSaveResult>(),
+            BatchSaveResult {
+                inserted: 2,
+                updated: 1,
+                unchanged: 0
+            }
+        );
+
+        let articles = QueryFilter {
+            category: Some(Category::Article),
+            ..Default::default()
+        };
+        let found: Vec<_> = backend.stream_all(&articles).try_collect().await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].title, "Twice");
+    }
+}
@@ -0,0 +1,222 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::extract::{Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+use crate::api::ApiClient;
+use crate::storage::StorageBackend;
+use crate::sync::{self, SyncOptions};
+
+pub struct WebhookState {
+    pub storage: Arc<dyn StorageBackend>,
+    pub client: ApiClient,
+    /// Options of webhook-triggered syncs; `full_sync` is set per request.
+    pub options: SyncOptions,
+    /// When set, requests must carry `Authorization: Bearer <token>`.
+    pub auth_token: Option<String>,
+    /// Held for the duration of a sync, so that only one runs at a time.
+    pub sync_lock: Mutex<()>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SyncRequest {
+    #[serde(default)]
+    full_resync: bool,
+}
+
+/// Serve `POST /sync` and `GET /health` on `port` until the process is stopped.
+pub async fn serve(port: u16, state: WebhookState) -> Result<()> {
+    let app = router(Arc::new(state));
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    info!("Listening for webhooks on port {port}");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+fn router(state: Arc<WebhookState>) -> Router {
+    Router::new()
+        .route("/sync", post(trigger_sync))
+        .route("/health", get(health))
+        .layer(middleware::from_fn_with_state(
+            Arc::clone(&state),
+            require_token,
+        ))
+        .with_state(state)
+}
+
+async fn require_token(
+    State(state): State<Arc<WebhookState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(token) = &state.auth_token {
+        let provided = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .map_or(&[][..], |value| value.as_bytes());
+        // Digests of equal length, so that the time the comparison takes
+        // tells nothing about how much of the token was right.
+        if Sha256::digest(provided) != Sha256::digest(format!("Bearer {token}")) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+    next.run(request).await
+}
+
+async fn trigger_sync(State(state): State<Arc<WebhookState>>, body: Bytes) -> Response {
+    // The body is optional: an empty POST triggers a regular sync.
+    let request: SyncRequest = if body.is_empty() {
+        SyncRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(request) => request,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({ "error": e.to_string() })),
+                )
+                    .into_response();
+            }
+        }
+    };
+
+    let Ok(_running) = state.sync_lock.try_lock() else {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "A sync is already running" })),
+        )
+            .into_response();
+    };
+
+    info!("Sync triggered by webhook");
+    let options = SyncOptions {
+        full_sync: request.full_resync,
+        ..state.options.clone()
+    };
+    match sync::run(&state.storage, &state.client, &options).await {
+        Ok(result) => Json(result).into_response(),
+        Err(e) => {
+            error!("Webhook-triggered sync failed: {e:#}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": format!("{e:#}") })),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn health(State(state): State<Arc<WebhookState>>) -> Response {
+    match state.storage.load_checkpoint().await {
+        Ok(last_sync) => Json(json!({ "status": "ok", "last_sync": last_sync })).into_response(),
+        Err(e) => {
+            error!("Health check failed: {e:#}");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({ "status": "error" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::db::QueryFilter;
+    use crate::testing::{InMemoryBackend, token};
+
+    fn state() -> Arc<WebhookState> {
+        Arc::new(WebhookState {
+            storage: Arc::new(InMemoryBackend::new()),
+            client: ApiClient::new(token()),
+            options: SyncOptions {
+                full_sync: false,
+                workers: 1,
+                filter: QueryFilter::default(),
+                strict_validation: false,
+                timeout: None,
+                two_way: None,
+                overlap_buffer: std::time::Duration::ZERO,
+            },
+            auth_token: Some(String::from("secret")),
+            sync_lock: Mutex::new(()),
+        })
+    }
+
+    async fn send(
+        state: &Arc<WebhookState>,
+        request: axum::http::request::Builder,
+        body: &str,
+    ) -> Response {
+        router(Arc::clone(state))
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn authorized_sync() -> axum::http::request::Builder {
+        Request::post("/sync").header(header::AUTHORIZATION, "Bearer secret")
+    }
+
+    #[tokio::test]
+    async fn requests_without_the_token_are_rejected() {
+        let state = state();
+        let missing = send(&state, Request::post("/sync"), "").await;
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+
+        let wrong = Request::post("/sync").header(header::AUTHORIZATION, "Bearer secreT");
+        assert_eq!(
+            send(&state, wrong, "").await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn only_one_sync_runs_at_a_time() {
+        let state = state();
+        let _running = state.sync_lock.try_lock().unwrap();
+
+        let response = send(&state, authorized_sync(), "").await;
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn malformed_bodies_are_rejected() {
+        let response = send(&state(), authorized_sync(), r#"{"full_resync": "yes"}"#).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn health_reports_the_last_sync() {
+        let state = state();
+        let request = Request::get("/health").header(header::AUTHORIZATION, "Bearer secret");
+
+        let response = send(&state, request, "").await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({ "status": "ok", "last_sync": null }));
+    }
+}
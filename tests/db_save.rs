@@ -0,0 +1,521 @@
+//! Saving, querying and soft-deleting documents against a real database.
+//!
+//! Needs the `test-helpers` feature and `DATABASE_URL` pointing to a
+//! PostgreSQL server on which the tests can create databases, hence ignored
+//! by default: `cargo test --features test-helpers --test db_save -- --ignored`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use reader_sync::api::ApiClient;
+use reader_sync::db::{self, DbPools, QueryFilter, SaveMode};
+use reader_sync::models::{Category, DbRecord, ItemId, Location, ReadingItem};
+use reader_sync::storage::{PostgresBackend, SaveOutcome, StorageBackend};
+use reader_sync::sync::{self, ConflictResolution, SyncOptions};
+use reader_sync::testing::{MockHttpClient, ReaderResultBuilder, api_page, token};
+use sqlx::PgPool;
+
+const SAVES: usize = 5;
+
+fn item() -> ReaderResultBuilder {
+    ReaderResultBuilder::new()
+        .id("01hx0k3d9w5t3y2b1q8v7c6n4m")
+        .title("A Wizard of Earthsea")
+        .author("Ursula K. Le Guin")
+        .source_url("https://example.com/earthsea")
+        .created_at(Utc.with_ymd_and_hms(2024, 3, 3, 14, 26, 0).unwrap())
+        .updated_at(Utc.with_ymd_and_hms(2024, 3, 4, 9, 0, 0).unwrap())
+        .reading_progress(0.25)
+        .word_count(8000)
+}
+
+fn backend(pool: &PgPool) -> PostgresBackend {
+    PostgresBackend::new(DbPools {
+        write: pool.clone(),
+        read: pool.clone(),
+    })
+    .with_audit_log(true)
+}
+
+async fn count(pool: &PgPool, table: &str) -> i64 {
+    sqlx::query_scalar(&format!("SELECT count(*) FROM {table}"))
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+async fn stored(pool: &PgPool, item: &ReadingItem) -> Option<ReadingItem> {
+    db::find_by_id(pool, &ItemId::from(item.id.as_str()))
+        .await
+        .unwrap()
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn saving_the_same_document_again_changes_nothing(pool: PgPool) {
+    let backend = backend(&pool);
+    let item = item().build();
+    let mut outcomes = Vec::new();
+    for _ in 0..SAVES {
+        outcomes.push(backend.save(&item).await.unwrap());
+    }
+
+    assert_eq!(outcomes[0], SaveOutcome::Inserted);
+    assert!(outcomes[1..].iter().all(|o| *o == SaveOutcome::Unchanged));
+    assert_eq!(count(&pool, "reading").await, 1);
+    assert_eq!(stored(&pool, &item).await, Some(item));
+    assert_eq!(count(&pool, "sync_audit").await, 0);
+    assert_eq!(count(&pool, "reading_progress_history").await, 1);
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn upserts_keep_the_last_version(pool: PgPool) {
+    let backend = backend(&pool);
+    let versions: Vec<_> = (1..=SAVES)
+        .map(|n| item().title(format!("Edition {n}")).build())
+        .collect();
+    for version in &versions {
+        backend.save(version).await.unwrap();
+    }
+
+    let last = versions.last().unwrap();
+    assert_eq!(count(&pool, "reading").await, 1);
+    assert_eq!(stored(&pool, last).await.as_ref(), Some(last));
+    // One audit entry per title change, none for the unchanged fields.
+    let audited: Vec<String> = sqlx::query_scalar("SELECT field_name FROM sync_audit")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(audited, vec!["title"; SAVES - 1]);
+    assert_eq!(count(&pool, "reading_progress_history").await, 1);
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn insert_only_saves_keep_the_first_version(pool: PgPool) {
+    let versions: Vec<_> = (1..=SAVES)
+        .map(|n| item().title(format!("Edition {n}")).build())
+        .collect();
+    for version in &versions {
+        db::save(
+            &pool,
+            &DbRecord::from(version.clone()),
+            SaveMode::InsertOnly,
+        )
+        .await
+        .unwrap();
+    }
+
+    assert_eq!(count(&pool, "reading").await, 1);
+    assert_eq!(
+        stored(&pool, &versions[0]).await.as_ref(),
+        Some(&versions[0])
+    );
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn saves_without_updated_at_keep_the_stored_one(pool: PgPool) {
+    let backend = backend(&pool);
+    let first = item().build();
+    let other = item().id("other").build();
+    backend
+        .save_batch(&[first.clone(), other.clone()])
+        .await
+        .unwrap();
+    let undated = |item: &ReadingItem, title: &str| ReadingItem {
+        title: title.to_string(),
+        updated_at: None,
+        ..item.clone()
+    };
+
+    // One document at a time...
+    let renamed = undated(&first, "Renamed");
+    backend.save(&renamed).await.unwrap();
+    let saved = stored(&pool, &first).await.unwrap();
+    assert_eq!(saved.title, "Renamed");
+    assert_eq!(saved.updated_at, first.updated_at);
+
+    // ...and in multi-row inserts, one statement per document.
+    let records = [
+        DbRecord::from(undated(&first, "Renamed again")),
+        DbRecord::from(undated(&other, "Other renamed")),
+    ];
+    db::save_batch(&pool, &records, 1).await.unwrap();
+    for (original, title) in [(&first, "Renamed again"), (&other, "Other renamed")] {
+        let saved = stored(&pool, original).await.unwrap();
+        assert_eq!(saved.title, title);
+        assert_eq!(saved.updated_at, original.updated_at);
+    }
+}
+
+async fn missing_ids_are_soft_deleted(pool: PgPool, extra_seen: usize) {
+    let backend = backend(&pool);
+    for id in ["kept", "gone", "also-gone"] {
+        backend.save(&item().id(id).build()).await.unwrap();
+    }
+    let mut seen = vec![ItemId::from("kept")];
+    // Unknown IDs, to push the set past the size bound as arrays.
+    seen.extend((0..extra_seen).map(|n| ItemId::from(format!("unknown-{n}"))));
+
+    let missing = db::find_ids_not_in(backend.pools(), &seen).await.unwrap();
+    assert_eq!(missing, [ItemId::from("also-gone"), ItemId::from("gone")]);
+
+    assert_eq!(backend.mark_missing_deleted(&seen).await.unwrap(), 2);
+    assert!(
+        db::find_ids_not_in(backend.pools(), &seen)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let deleted: i64 =
+        sqlx::query_scalar("SELECT count(*) FROM reading WHERE deleted_at IS NOT NULL")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(deleted, 2);
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn missing_documents_are_soft_deleted(pool: PgPool) {
+    missing_ids_are_soft_deleted(pool, 0).await;
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn missing_documents_are_found_among_many_seen_ids(pool: PgPool) {
+    // Above the 10,000 IDs bound as one array: loaded in a temporary table.
+    missing_ids_are_soft_deleted(pool, 20_000).await;
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn purging_deletes_highlights_and_notes_too(pool: PgPool) {
+    let backend = backend(&pool);
+    let document = item().build();
+    let highlight = item()
+        .id("highlight")
+        .category(Category::Highlight)
+        .parent_id(document.id.as_str())
+        .build();
+    let note = item()
+        .id("note")
+        .category(Category::Note)
+        .parent_id("highlight")
+        .build();
+    let other = item().id("other").build();
+    for saved in [&document, &highlight, &note, &other] {
+        backend.save(saved).await.unwrap();
+    }
+    sqlx::query("UPDATE reading SET deleted_at = now() - interval '2 days' WHERE id = $1")
+        .bind(&document.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let purged = db::purge_deleted(backend.pools(), Duration::from_secs(24 * 60 * 60))
+        .await
+        .unwrap();
+
+    assert_eq!(purged, 3);
+    let left: Vec<String> = sqlx::query_scalar("SELECT id FROM reading")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(left, ["other"]);
+    assert_eq!(count(&pool, "reading_progress_history").await, 1);
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn soft_deleted_documents_are_hidden_from_queries(pool: PgPool) {
+    let backend = backend(&pool);
+    let deleted = item().build();
+    let kept = item().id("kept").build();
+    for saved in [&deleted, &kept] {
+        backend.save(saved).await.unwrap();
+    }
+
+    db::mark_deleted(backend.pools(), &[ItemId::from(deleted.id.as_str())])
+        .await
+        .unwrap();
+
+    let found = db::find_all(backend.pools(), &QueryFilter::default())
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, "kept");
+    assert_eq!(db::count(backend.pools()).await.unwrap(), 1);
+    let path =
+        std::env::temp_dir().join(format!("reader-sync-deleted-{}.jsonl", std::process::id()));
+    let exported = db::export_jsonl(
+        backend.pools(),
+        &QueryFilter::default(),
+        &path,
+        &db::JsonlExportOptions::default(),
+    )
+    .await
+    .unwrap();
+    let lines = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(exported, 1);
+    assert!(!lines.contains(deleted.id.as_str()));
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn reading_time_leaves_highlights_out(pool: PgPool) {
+    let backend = backend(&pool);
+    let document = item().build();
+    let highlight = item()
+        .id("highlight")
+        .category(Category::Highlight)
+        .parent_id(document.id.as_str())
+        .build();
+    for saved in [&document, &highlight] {
+        backend.save(saved).await.unwrap();
+    }
+
+    let minutes = db::total_reading_time_minutes(backend.pools(), &QueryFilter::default())
+        .await
+        .unwrap();
+
+    assert_eq!(minutes, 8000 / 238);
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn highlights_with_notes_are_counted_and_limited(pool: PgPool) {
+    let backend = backend(&pool);
+    for n in 0..3 {
+        let highlight = item()
+            .id(format!("highlight-{n}"))
+            .category(Category::Highlight)
+            .notes(format!("note {n}"))
+            .build();
+        backend.save(&highlight).await.unwrap();
+    }
+    let bare = item().id("bare").category(Category::Highlight).build();
+    backend.save(&bare).await.unwrap();
+
+    let count = db::count_highlights_with_notes(backend.pools())
+        .await
+        .unwrap();
+    let first = db::highlights_with_notes(backend.pools(), 2).await.unwrap();
+    let by_author = db::highlights_for_author(backend.pools(), "Ursula K. Le Guin", 2)
+        .await
+        .unwrap();
+
+    assert_eq!(count, 3);
+    assert_eq!(first.len(), 2);
+    assert_eq!(by_author.len(), 2);
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn in_progress_documents_are_the_started_unfinished_ones(pool: PgPool) {
+    let backend = backend(&pool);
+    for (id, progress) in [
+        ("unread", 0.0),
+        ("half", 0.5),
+        ("almost", 0.9),
+        ("started", 0.1),
+        ("done", 1.0),
+    ] {
+        let saved = item().id(id).reading_progress(progress).build();
+        backend.save(&saved).await.unwrap();
+    }
+    let highlight = item()
+        .id("highlight")
+        .category(Category::Highlight)
+        .parent_id("half")
+        .reading_progress(0.95)
+        .build();
+    backend.save(&highlight).await.unwrap();
+
+    let in_progress = db::most_advanced_in_progress(backend.pools(), 2)
+        .await
+        .unwrap();
+
+    let ids: Vec<_> = in_progress.iter().map(|i| i.id.as_str()).collect();
+    assert_eq!(ids, ["almost", "half"]);
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn source_urls_are_found_as_given_or_normalized(pool: PgPool) {
+    let backend = backend(&pool);
+    let normalized = item().build();
+    backend.save(&normalized).await.unwrap();
+    // Saved before source_url was parsed, as Readwise sent it.
+    let raw = item().id("raw").build();
+    backend.save(&raw).await.unwrap();
+    sqlx::query("UPDATE reading SET source_url = 'HTTPS://Example.com/raw' WHERE id = 'raw'")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let pools = backend.pools();
+    let found = |item: Option<ReadingItem>| item.map(|i| i.id);
+    let by_normalized = db::find_by_source_url(pools, "HTTPS://EXAMPLE.com:443/earthsea");
+    let by_raw = db::find_by_source_url(pools, "HTTPS://Example.com/raw");
+    let by_other = db::find_by_source_url(pools, "https://example.com/other");
+
+    assert_eq!(found(by_normalized.await.unwrap()), Some(normalized.id));
+    assert_eq!(found(by_raw.await.unwrap()), Some(raw.id));
+    assert_eq!(found(by_other.await.unwrap()), None);
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn auto_archived_documents_stay_archived_after_a_two_way_sync(pool: PgPool) {
+    let backend = backend(&pool);
+    let inbox = item().location(Some(Location::New)).build();
+    backend.save(&inbox).await.unwrap();
+    let checkpoint = Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap();
+    backend.save_checkpoint(&checkpoint, None).await.unwrap();
+
+    let archived = db::archive_old_items(
+        backend.pools(),
+        Duration::from_secs(30 * 24 * 60 * 60),
+        Location::Archive,
+    )
+    .await
+    .unwrap();
+    assert_eq!(archived, 1);
+
+    // Readwise sends the document back archived once the move is pushed.
+    let pushed = item()
+        .location(Some(Location::Archive))
+        .updated_at(Utc::now())
+        .build();
+    let http = MockHttpClient::new()
+        .respond(&api_page(std::slice::from_ref(&inbox)))
+        .respond("{}")
+        .respond(&api_page(&[pushed]));
+    let client = ApiClient::with_http_client(http.clone(), token());
+    let storage: Arc<dyn StorageBackend> = Arc::new(backend);
+    let options = SyncOptions {
+        full_sync: false,
+        workers: 1,
+        filter: QueryFilter::default(),
+        strict_validation: false,
+        timeout: None,
+        overlap_buffer: Duration::ZERO,
+        two_way: Some(ConflictResolution::Newest),
+    };
+
+    let result = sync::run(&storage, &client, &options).await.unwrap();
+
+    assert_eq!(result.items_pushed, 1);
+    assert!(http.requests()[1].starts_with("PATCH "));
+    let stored = stored(&pool, &inbox).await.unwrap();
+    assert_eq!(stored.location, Some(Location::Archive));
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn sync_pages_larger_than_the_chunk_size_are_saved_whole(pool: PgPool) {
+    let backend = backend(&pool).with_batch_chunk_size(2);
+    let renamed = item().build();
+    backend
+        .save(&item().title("Earthsea").build())
+        .await
+        .unwrap();
+    let mut page = vec![renamed.clone()];
+    page.extend((0..4).map(|n| item().id(format!("doc-{n}")).build()));
+    let http = MockHttpClient::new().respond(&api_page(&page));
+    let client = ApiClient::with_http_client(http, token());
+    let storage: Arc<dyn StorageBackend> = Arc::new(backend);
+    let options = SyncOptions {
+        full_sync: true,
+        workers: 1,
+        filter: QueryFilter::default(),
+        strict_validation: false,
+        timeout: None,
+        overlap_buffer: Duration::ZERO,
+        two_way: None,
+    };
+
+    let result = sync::run(&storage, &client, &options).await.unwrap();
+
+    assert_eq!(result.items_saved, 5);
+    assert_eq!(result.items_new, 4);
+    assert_eq!(result.items_updated, 1);
+    assert_eq!(count(&pool, "reading").await, 5);
+    assert_eq!(stored(&pool, &renamed).await.unwrap().title, renamed.title);
+    // The first save and the 4 new documents; the rename kept the progress.
+    assert_eq!(count(&pool, "reading_progress_history").await, 5);
+    assert_eq!(count(&pool, "sync_audit").await, 1);
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn syncs_recreate_a_deleted_sync_state_row(pool: PgPool) {
+    sqlx::query("DELETE FROM sync_state")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let http = MockHttpClient::new().respond(&api_page(&[item().build()]));
+    let client = ApiClient::with_http_client(http, token());
+    let storage: Arc<dyn StorageBackend> = Arc::new(backend(&pool));
+    let options = SyncOptions {
+        full_sync: false,
+        workers: 1,
+        filter: QueryFilter::default(),
+        strict_validation: false,
+        timeout: None,
+        overlap_buffer: Duration::ZERO,
+        two_way: None,
+    };
+
+    let result = sync::run(&storage, &client, &options).await.unwrap();
+
+    assert_eq!(result.items_saved, 1);
+    assert_eq!(count(&pool, "sync_state").await, 1);
+    assert!(storage.load_checkpoint().await.unwrap().is_some());
+    assert_eq!(storage.load_page_cursor().await.unwrap(), None);
+}
+
+#[sqlx::test]
+#[ignore = "needs PostgreSQL at DATABASE_URL"]
+async fn latest_documents_are_the_newest_matching_ones(pool: PgPool) {
+    let backend = backend(&pool);
+    let saved_at = |n| Utc.with_ymd_and_hms(2024, 3, n, 0, 0, 0).unwrap();
+    let pdf = item()
+        .id("pdf")
+        .category(Category::Pdf)
+        .created_at(saved_at(1))
+        .build();
+    let highlight = item()
+        .id("highlight")
+        .category(Category::Highlight)
+        .parent_id("pdf")
+        .created_at(saved_at(5))
+        .build();
+    let articles: Vec<_> = (2..5)
+        .map(|day| {
+            item()
+                .id(format!("article-{day}"))
+                .created_at(saved_at(day))
+                .build()
+        })
+        .collect();
+    for saved in articles.iter().chain([&pdf, &highlight]) {
+        backend.save(saved).await.unwrap();
+    }
+
+    let ids = |items: Vec<ReadingItem>| items.into_iter().map(|i| i.id).collect::<Vec<_>>();
+    let all = QueryFilter::default();
+    let latest = backend.latest_documents(&all, 2).await.unwrap();
+    assert_eq!(ids(latest), ["article-4", "article-3"]);
+    let pdfs = QueryFilter {
+        category: Some(Category::Pdf),
+        ..Default::default()
+    };
+    let latest = backend.latest_documents(&pdfs, 2).await.unwrap();
+    assert_eq!(ids(latest), ["pdf"]);
+}